@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS migrations (
+        id INT PRIMARY KEY,
+        date TEXT,
+        query TEXT
+    );
@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS keys (
+        id INT PRIMARY KEY,
+        userid TEXT,
+        key TEXT
+    );
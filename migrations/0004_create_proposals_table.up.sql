@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS proposals (
+        id INT PRIMARY KEY,
+        title TEXT,
+        description TEXT,
+        authorId TEXT,
+        createdAt TEXT,
+        updatedAt TEXT
+    );
@@ -0,0 +1 @@
+ALTER TABLE proposals ADD COLUMN decisionRule TEXT DEFAULT 'majority';
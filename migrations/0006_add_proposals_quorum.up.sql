@@ -0,0 +1 @@
+ALTER TABLE proposals ADD COLUMN quorum INT DEFAULT 0;
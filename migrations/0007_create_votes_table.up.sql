@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS votes (
+        proposalId INT,
+        userId TEXT,
+        choice TEXT,
+        createdAt TEXT,
+        updatedAt TEXT,
+        PRIMARY KEY (proposalId, userId)
+    );
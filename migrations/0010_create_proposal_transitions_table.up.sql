@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS proposal_transitions (
+        proposalId INT,
+        fromStatus TEXT,
+        toStatus TEXT,
+        at TEXT
+    );
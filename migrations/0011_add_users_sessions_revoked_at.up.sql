@@ -0,0 +1 @@
+ALTER TABLE users ADD COLUMN sessionsRevokedAt INT DEFAULT 0;
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS sessions (
+        id TEXT PRIMARY KEY,
+        session TEXT,
+        userId TEXT,
+        expiresAt INT
+    );
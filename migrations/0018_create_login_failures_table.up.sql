@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS login_failures (
+        scope TEXT,
+        subject TEXT,
+        failures INT,
+        lastFailureAt INT,
+        lockedUntil INT,
+        PRIMARY KEY (scope, subject)
+    );
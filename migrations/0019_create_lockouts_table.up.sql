@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS lockouts (
+        scope TEXT,
+        subject TEXT,
+        failures INT,
+        lockedAt TEXT,
+        lockedUntil TEXT
+    );
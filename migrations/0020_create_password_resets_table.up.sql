@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS password_resets (
+        tokenHash TEXT PRIMARY KEY,
+        userId TEXT,
+        createdAt TEXT,
+        expiresAt INT,
+        usedAt TEXT
+    );
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS outbox (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        recipient TEXT,
+        subject TEXT,
+        text TEXT,
+        html TEXT,
+        attempts INT DEFAULT 0,
+        nextAttemptAt INT,
+        lastError TEXT,
+        createdAt TEXT,
+        sentAt TEXT
+    );
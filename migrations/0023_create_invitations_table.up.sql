@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS invitations (
+        tokenHash TEXT PRIMARY KEY,
+        email TEXT,
+        invitedBy TEXT,
+        createdAt TEXT,
+        expiresAt INT,
+        usedAt TEXT
+    );
@@ -0,0 +1,5 @@
+-- the up step made the longest-standing account an admin, since someone has to be able to
+-- hand out roles. undoing the step before drops the role column anyway, so this only
+-- puts back the default
+UPDATE users SET role = 'member'
+    WHERE rowid = (SELECT MIN(rowid) FROM users);
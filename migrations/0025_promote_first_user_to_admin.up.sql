@@ -0,0 +1,2 @@
+UPDATE users SET role = 'admin'
+        WHERE rowid = (SELECT MIN(rowid) FROM users);
@@ -0,0 +1,37 @@
+-- back to the shapes the earlier migrations left, without the foreign keys and indexes.
+-- the old key column was never written, so it comes back empty
+CREATE TABLE keys_old (
+    id INT PRIMARY KEY,
+    userid TEXT,
+    key TEXT,
+    pubkey TEXT,
+    credentialId TEXT,
+    nickname TEXT,
+    createdAt TEXT,
+    lastUsedAt TEXT
+);
+INSERT INTO keys_old (userid, pubkey, credentialId, nickname, createdAt, lastUsedAt)
+    SELECT userid, pubkey, credentialId, nickname, createdAt, lastUsedAt FROM keys
+    ORDER BY id;
+DROP TABLE keys;
+ALTER TABLE keys_old RENAME TO keys;
+
+CREATE TABLE proposals_old (
+    id INT PRIMARY KEY,
+    title TEXT,
+    description TEXT,
+    authorId TEXT,
+    createdAt TEXT,
+    updatedAt TEXT,
+    decisionRule TEXT DEFAULT 'majority',
+    quorum INT DEFAULT 0,
+    status TEXT DEFAULT 'draft',
+    closesAt TEXT
+);
+INSERT INTO proposals_old (id, title, description, authorId, createdAt, updatedAt,
+        decisionRule, quorum, status, closesAt)
+    SELECT id, title, description, authorId, createdAt, updatedAt,
+        decisionRule, quorum, status, closesAt
+    FROM proposals;
+DROP TABLE proposals;
+ALTER TABLE proposals_old RENAME TO proposals;
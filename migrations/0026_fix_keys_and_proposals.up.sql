@@ -0,0 +1,43 @@
+-- sqlite can't add foreign keys to a table that already exists, so both tables are rebuilt,
+-- copied over and swapped in. foreign keys aren't enforced while migrations run, so rows
+-- whose user is already gone are kept as they are.
+
+-- keys.id was INT PRIMARY KEY, which isn't a rowid alias, so it was never filled in; and
+-- key was superseded by pubkey
+CREATE TABLE keys_new (
+    id INTEGER PRIMARY KEY,
+    userid TEXT REFERENCES users (id) ON DELETE CASCADE,
+    pubkey TEXT,
+    credentialId TEXT,
+    nickname TEXT,
+    createdAt TEXT,
+    lastUsedAt TEXT
+);
+INSERT INTO keys_new (userid, pubkey, credentialId, nickname, createdAt, lastUsedAt)
+    SELECT userid, pubkey, credentialId, nickname, createdAt, lastUsedAt FROM keys
+    ORDER BY rowid;
+DROP TABLE keys;
+ALTER TABLE keys_new RENAME TO keys;
+CREATE INDEX keys_userid ON keys (userid);
+
+-- ids were assigned by hand and are kept, so links to proposals still work
+CREATE TABLE proposals_new (
+    id INTEGER PRIMARY KEY,
+    title TEXT,
+    description TEXT,
+    authorId TEXT REFERENCES users (id) ON DELETE CASCADE,
+    createdAt TEXT,
+    updatedAt TEXT,
+    decisionRule TEXT DEFAULT 'majority',
+    quorum INT DEFAULT 0,
+    status TEXT DEFAULT 'draft',
+    closesAt TEXT
+);
+INSERT INTO proposals_new (id, title, description, authorId, createdAt, updatedAt,
+        decisionRule, quorum, status, closesAt)
+    SELECT id, title, description, authorId, createdAt, updatedAt,
+        decisionRule, quorum, status, closesAt
+    FROM proposals;
+DROP TABLE proposals;
+ALTER TABLE proposals_new RENAME TO proposals;
+CREATE INDEX proposals_author_id ON proposals (authorId);
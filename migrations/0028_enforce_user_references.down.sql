@@ -0,0 +1,6 @@
+DROP TRIGGER users_delete;
+DROP TRIGGER users_id_update;
+DROP TRIGGER proposals_author_id_update;
+DROP TRIGGER proposals_author_id_insert;
+DROP TRIGGER keys_userid_update;
+DROP TRIGGER keys_userid_insert;
@@ -0,0 +1,34 @@
+-- sqlite only enforces foreign keys on connections that turn them on, and a remote database
+-- opens a new connection for each request, so the references to users are also kept by
+-- triggers, which run on every connection. they check the same things the foreign keys do
+CREATE TRIGGER keys_userid_insert BEFORE INSERT ON keys
+    WHEN NEW.userid IS NOT NULL AND NOT EXISTS (SELECT 1 FROM users WHERE id = NEW.userid)
+BEGIN
+    SELECT RAISE(ABORT, 'FOREIGN KEY constraint failed');
+END;
+CREATE TRIGGER keys_userid_update BEFORE UPDATE OF userid ON keys
+    WHEN NEW.userid IS NOT NULL AND NOT EXISTS (SELECT 1 FROM users WHERE id = NEW.userid)
+BEGIN
+    SELECT RAISE(ABORT, 'FOREIGN KEY constraint failed');
+END;
+CREATE TRIGGER proposals_author_id_insert BEFORE INSERT ON proposals
+    WHEN NEW.authorId IS NOT NULL AND NOT EXISTS (SELECT 1 FROM users WHERE id = NEW.authorId)
+BEGIN
+    SELECT RAISE(ABORT, 'FOREIGN KEY constraint failed');
+END;
+CREATE TRIGGER proposals_author_id_update BEFORE UPDATE OF authorId ON proposals
+    WHEN NEW.authorId IS NOT NULL AND NOT EXISTS (SELECT 1 FROM users WHERE id = NEW.authorId)
+BEGIN
+    SELECT RAISE(ABORT, 'FOREIGN KEY constraint failed');
+END;
+CREATE TRIGGER users_id_update BEFORE UPDATE OF id ON users
+    WHEN EXISTS (SELECT 1 FROM keys WHERE userid = OLD.id)
+        OR EXISTS (SELECT 1 FROM proposals WHERE authorId = OLD.id)
+BEGIN
+    SELECT RAISE(ABORT, 'FOREIGN KEY constraint failed');
+END;
+CREATE TRIGGER users_delete AFTER DELETE ON users
+BEGIN
+    DELETE FROM keys WHERE userid = OLD.id;
+    DELETE FROM proposals WHERE authorId = OLD.id;
+END;
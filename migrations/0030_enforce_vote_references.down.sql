@@ -0,0 +1,35 @@
+-- back to the shapes 0007 and 0010 left, without the foreign keys, indexes and triggers
+DROP TRIGGER users_delete_votes;
+DROP TRIGGER proposals_delete;
+DROP TRIGGER users_id_update_votes;
+DROP TRIGGER proposals_id_update;
+DROP TRIGGER proposal_transitions_proposal_id_update;
+DROP TRIGGER proposal_transitions_proposal_id_insert;
+DROP TRIGGER votes_user_id_update;
+DROP TRIGGER votes_user_id_insert;
+DROP TRIGGER votes_proposal_id_update;
+DROP TRIGGER votes_proposal_id_insert;
+
+CREATE TABLE proposal_transitions_old (
+    proposalId INT,
+    fromStatus TEXT,
+    toStatus TEXT,
+    at TEXT
+);
+INSERT INTO proposal_transitions_old (proposalId, fromStatus, toStatus, at)
+    SELECT proposalId, fromStatus, toStatus, at FROM proposal_transitions ORDER BY rowid;
+DROP TABLE proposal_transitions;
+ALTER TABLE proposal_transitions_old RENAME TO proposal_transitions;
+
+CREATE TABLE votes_old (
+    proposalId INT,
+    userId TEXT,
+    choice TEXT,
+    createdAt TEXT,
+    updatedAt TEXT,
+    PRIMARY KEY (proposalId, userId)
+);
+INSERT INTO votes_old (proposalId, userId, choice, createdAt, updatedAt)
+    SELECT proposalId, userId, choice, createdAt, updatedAt FROM votes;
+DROP TABLE votes;
+ALTER TABLE votes_old RENAME TO votes;
@@ -0,0 +1,90 @@
+-- votes and proposal transitions point at proposals and users too, so they get the same
+-- treatment keys and proposals did: rebuilt with foreign keys (see 0026), and kept by
+-- triggers on every connection (see 0028). rows whose proposal or member is already gone
+-- are dropped, as deleting them would have done
+CREATE TABLE votes_new (
+    proposalId INT REFERENCES proposals (id) ON DELETE CASCADE,
+    userId TEXT REFERENCES users (id) ON DELETE CASCADE,
+    choice TEXT,
+    createdAt TEXT,
+    updatedAt TEXT,
+    PRIMARY KEY (proposalId, userId)
+);
+INSERT INTO votes_new (proposalId, userId, choice, createdAt, updatedAt)
+    SELECT proposalId, userId, choice, createdAt, updatedAt FROM votes
+    WHERE EXISTS (SELECT 1 FROM proposals WHERE id = votes.proposalId)
+        AND EXISTS (SELECT 1 FROM users WHERE id = votes.userId);
+DROP TABLE votes;
+ALTER TABLE votes_new RENAME TO votes;
+CREATE INDEX votes_user_id ON votes (userId);
+
+CREATE TABLE proposal_transitions_new (
+    proposalId INT REFERENCES proposals (id) ON DELETE CASCADE,
+    fromStatus TEXT,
+    toStatus TEXT,
+    at TEXT
+);
+INSERT INTO proposal_transitions_new (proposalId, fromStatus, toStatus, at)
+    SELECT proposalId, fromStatus, toStatus, at FROM proposal_transitions
+    WHERE EXISTS (SELECT 1 FROM proposals WHERE id = proposal_transitions.proposalId)
+    ORDER BY rowid;
+DROP TABLE proposal_transitions;
+ALTER TABLE proposal_transitions_new RENAME TO proposal_transitions;
+CREATE INDEX proposal_transitions_proposal_id ON proposal_transitions (proposalId);
+
+CREATE TRIGGER votes_proposal_id_insert BEFORE INSERT ON votes
+    WHEN NEW.proposalId IS NOT NULL
+        AND NOT EXISTS (SELECT 1 FROM proposals WHERE id = NEW.proposalId)
+BEGIN
+    SELECT RAISE(ABORT, 'FOREIGN KEY constraint failed');
+END;
+CREATE TRIGGER votes_proposal_id_update BEFORE UPDATE OF proposalId ON votes
+    WHEN NEW.proposalId IS NOT NULL
+        AND NOT EXISTS (SELECT 1 FROM proposals WHERE id = NEW.proposalId)
+BEGIN
+    SELECT RAISE(ABORT, 'FOREIGN KEY constraint failed');
+END;
+CREATE TRIGGER votes_user_id_insert BEFORE INSERT ON votes
+    WHEN NEW.userId IS NOT NULL AND NOT EXISTS (SELECT 1 FROM users WHERE id = NEW.userId)
+BEGIN
+    SELECT RAISE(ABORT, 'FOREIGN KEY constraint failed');
+END;
+CREATE TRIGGER votes_user_id_update BEFORE UPDATE OF userId ON votes
+    WHEN NEW.userId IS NOT NULL AND NOT EXISTS (SELECT 1 FROM users WHERE id = NEW.userId)
+BEGIN
+    SELECT RAISE(ABORT, 'FOREIGN KEY constraint failed');
+END;
+CREATE TRIGGER proposal_transitions_proposal_id_insert BEFORE INSERT ON proposal_transitions
+    WHEN NEW.proposalId IS NOT NULL
+        AND NOT EXISTS (SELECT 1 FROM proposals WHERE id = NEW.proposalId)
+BEGIN
+    SELECT RAISE(ABORT, 'FOREIGN KEY constraint failed');
+END;
+CREATE TRIGGER proposal_transitions_proposal_id_update
+    BEFORE UPDATE OF proposalId ON proposal_transitions
+    WHEN NEW.proposalId IS NOT NULL
+        AND NOT EXISTS (SELECT 1 FROM proposals WHERE id = NEW.proposalId)
+BEGIN
+    SELECT RAISE(ABORT, 'FOREIGN KEY constraint failed');
+END;
+CREATE TRIGGER proposals_id_update BEFORE UPDATE OF id ON proposals
+    WHEN EXISTS (SELECT 1 FROM votes WHERE proposalId = OLD.id)
+        OR EXISTS (SELECT 1 FROM proposal_transitions WHERE proposalId = OLD.id)
+BEGIN
+    SELECT RAISE(ABORT, 'FOREIGN KEY constraint failed');
+END;
+CREATE TRIGGER users_id_update_votes BEFORE UPDATE OF id ON users
+    WHEN EXISTS (SELECT 1 FROM votes WHERE userId = OLD.id)
+BEGIN
+    SELECT RAISE(ABORT, 'FOREIGN KEY constraint failed');
+END;
+-- users_delete from 0028 deletes a member's proposals, which sets this one off in turn
+CREATE TRIGGER proposals_delete AFTER DELETE ON proposals
+BEGIN
+    DELETE FROM votes WHERE proposalId = OLD.id;
+    DELETE FROM proposal_transitions WHERE proposalId = OLD.id;
+END;
+CREATE TRIGGER users_delete_votes AFTER DELETE ON users
+BEGIN
+    DELETE FROM votes WHERE userId = OLD.id;
+END;
@@ -0,0 +1,469 @@
+use argon2::Argon2;
+use libsql_client::Client;
+
+use crate::{
+    config::Config,
+    errors::Errors,
+    models::{
+        self,
+        db::Query,
+        migrations::{self, MIGRATIONS},
+        passwords,
+        roles::Role,
+        users,
+    },
+    Error,
+};
+
+pub const USAGE: &str = "usage: lochstep [command]
+
+commands:
+    serve                                   run the web server; the default
+    check-config                            check the environment, that the database is reachable
+                                            and that it's had every migration
+    migrate status                          list migrations and when each was applied
+    migrate up [--dry-run]                  apply pending migrations
+    migrate down --to N [--dry-run]         undo every migration after N, newest first
+    user list
+    user create USERNAME [--email ADDRESS]  prints a generated password
+    user set-role USERNAME ROLE             member, steward or admin
+    user disable USERNAME                   stops them logging in and ends their sessions
+    user enable USERNAME
+    user reset-password USERNAME            prints a generated password and ends their sessions
+    help
+
+a dry run prints the sql it would run instead of running it";
+
+/// what the binary was asked to do
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Serve,
+    CheckConfig,
+    Migrate(MigrateCommand),
+    User(UserCommand),
+    Help,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum MigrateCommand {
+    Status,
+    Up { dry_run: bool },
+    Down { to: usize, dry_run: bool },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum UserCommand {
+    List,
+    Create {
+        username: String,
+        email: Option<String>,
+    },
+    SetRole {
+        username: String,
+        role: Role,
+    },
+    Disable {
+        username: String,
+    },
+    Enable {
+        username: String,
+    },
+    ResetPassword {
+        username: String,
+    },
+}
+
+impl Command {
+    /// parses the arguments after the binary's name; errors are meant for the operator
+    pub fn parse(args: &[String]) -> Result<Self, String> {
+        let args: Vec<&str> = args.iter().map(String::as_str).collect();
+        match args.as_slice() {
+            [] | ["serve"] => Ok(Command::Serve),
+            ["check-config"] => Ok(Command::CheckConfig),
+            ["help" | "--help" | "-h"] => Ok(Command::Help),
+            ["migrate", rest @ ..] => MigrateCommand::parse(rest).map(Command::Migrate),
+            ["user", rest @ ..] => UserCommand::parse(rest).map(Command::User),
+            _ => Err(format!("unknown command: {}", args.join(" "))),
+        }
+    }
+}
+
+impl MigrateCommand {
+    fn parse(args: &[&str]) -> Result<Self, String> {
+        let dry_run = args.contains(&"--dry-run");
+        let args: Vec<&str> = args
+            .iter()
+            .copied()
+            .filter(|arg| *arg != "--dry-run")
+            .collect();
+        match args.as_slice() {
+            [] | ["status"] if !dry_run => Ok(MigrateCommand::Status),
+            ["up"] => Ok(MigrateCommand::Up { dry_run }),
+            ["down", "--to", to] => match to.parse() {
+                Ok(to) => Ok(MigrateCommand::Down { to, dry_run }),
+                Err(_) => Err(format!("--to takes a migration number, not {}", to)),
+            },
+            ["down", ..] => Err("migrate down needs --to N".to_string()),
+            _ => Err(format!("unknown migrate command: {}", args.join(" "))),
+        }
+    }
+}
+
+impl UserCommand {
+    fn parse(args: &[&str]) -> Result<Self, String> {
+        let username = |username: &str| username.to_string();
+        match args {
+            ["list"] => Ok(UserCommand::List),
+            ["create", name] => Ok(UserCommand::Create {
+                username: username(name),
+                email: None,
+            }),
+            ["create", name, "--email", email] => Ok(UserCommand::Create {
+                username: username(name),
+                email: Some(email.to_string()),
+            }),
+            ["set-role", name, role] => match role.parse() {
+                Ok(role) => Ok(UserCommand::SetRole {
+                    username: username(name),
+                    role,
+                }),
+                Err(_) => Err(format!("{} isn't a role", role)),
+            },
+            ["disable", name] => Ok(UserCommand::Disable {
+                username: username(name),
+            }),
+            ["enable", name] => Ok(UserCommand::Enable {
+                username: username(name),
+            }),
+            ["reset-password", name] => Ok(UserCommand::ResetPassword {
+                username: username(name),
+            }),
+            _ => Err(format!("unknown user command: {}", args.join(" "))),
+        }
+    }
+}
+
+/// checks everything `serve` needs before it starts: the environment, that the database can
+/// be reached, and that it's up to date. returns every problem found, not only the first
+pub async fn check_config() -> Result<String, Vec<String>> {
+    let config = match Config::from_env() {
+        Ok(config) => config,
+        Err(Errors::ConfigError(problems)) => return Err(problems),
+        Err(e) => return Err(vec![describe(&e)]),
+    };
+
+    let mut problems = vec![];
+    if let Err(e) = config.webauthn.validate() {
+        problems.push(describe(&e));
+    }
+    if let Err(e) = config.mail.validate(&config.stage) {
+        problems.push(describe(&e));
+    }
+    match crate::init_db_client(&config).await {
+        Ok(db) => {
+            if let Err(e) = check_db(&db).await {
+                problems.push(describe(&e));
+            }
+        }
+        Err(e) => problems.push(e.to_string()),
+    }
+
+    if !problems.is_empty() {
+        return Err(problems);
+    }
+    Ok(format!(
+        "config ok: {:?} stage, serving {}, database reachable and up to date",
+        config.stage, config.public_url
+    ))
+}
+
+async fn check_db(db: &Client) -> Result<(), Errors> {
+    Query::new("SELECT 1;").fetch(db).await?;
+    match pending_migrations(db).await? {
+        0 => Ok(()),
+        pending => Err(Errors::PendingMigrations(pending)),
+    }
+}
+
+/// how many migrations the database is behind this build. errors if the ones it's had no
+/// longer match it
+async fn pending_migrations(db: &Client) -> Result<usize, Errors> {
+    migrations::validate(&MIGRATIONS)?;
+    Ok(migrations::status(db, &MIGRATIONS)
+        .await?
+        .iter()
+        .filter(|(_, applied)| applied.is_none())
+        .count())
+}
+
+/// an error and what caused it, for an operator to read
+fn describe(e: &Errors) -> String {
+    match std::error::Error::source(e) {
+        Some(source) => format!("{}: {}", e, source),
+        None => e.to_string(),
+    }
+}
+
+/// runs a `migrate` command, returning what to print
+pub async fn migrate(db: &Client, command: MigrateCommand) -> Result<String, Error> {
+    migrations::validate(&MIGRATIONS)?;
+    let lines: Vec<String> = match command {
+        MigrateCommand::Status => migrations::status(db, &MIGRATIONS)
+            .await?
+            .iter()
+            .map(|(step, applied)| {
+                format!(
+                    "{:>4}  {:<44}  {}",
+                    step.id,
+                    step.name,
+                    applied.as_deref().unwrap_or("pending")
+                )
+            })
+            .collect(),
+        MigrateCommand::Up { dry_run: true } => migrations::plan_migrate(db, &MIGRATIONS)
+            .await?
+            .iter()
+            .map(|step| format!("{}\n", step))
+            .collect(),
+        MigrateCommand::Up { dry_run: false } => {
+            vec![format!("applied {} migrations", models::init_db(db).await?)]
+        }
+        MigrateCommand::Down { to, dry_run: true } => {
+            migrations::plan_rollback(db, &MIGRATIONS, to)
+                .await?
+                .iter()
+                .map(|step| format!("{}\n", step))
+                .collect()
+        }
+        MigrateCommand::Down { to, dry_run: false } => {
+            vec![format!(
+                "rolled back {} migrations",
+                models::rollback_db(db, to).await?
+            )]
+        }
+    };
+    Ok(lines.join("\n"))
+}
+
+/// runs a `user` command, returning what to print. it refuses to touch a database that
+/// isn't up to date rather than migrating it unasked; that's what `migrate up` is for
+pub async fn user(db: &Client, argon2: &Argon2<'_>, command: UserCommand) -> Result<String, Error> {
+    match pending_migrations(db).await? {
+        0 => (),
+        pending => return Err(Errors::PendingMigrations(pending).into()),
+    }
+    match command {
+        UserCommand::List => Ok(users::all_users(db)
+            .await?
+            .iter()
+            .map(|user| {
+                let status = if user.disabled { "disabled" } else { "" };
+                format!(
+                    "{:<24}  {:<8}  {}",
+                    user.username,
+                    user.role.to_string(),
+                    status
+                )
+                .trim_end()
+                .to_string()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")),
+        UserCommand::Create { username, email } => {
+            let password = passwords::generate_password();
+            users::create_user_with_password(db, argon2, &username, &password, email.as_deref())
+                .await?;
+            Ok(format!("created {} with password {}", username, password))
+        }
+        UserCommand::SetRole { username, role } => {
+            users::set_role(db, &username, role).await?;
+            Ok(format!("{} is now {}", username, role))
+        }
+        UserCommand::Disable { username } => {
+            users::set_disabled(db, &username, true).await?;
+            Ok(format!("disabled {}", username))
+        }
+        UserCommand::Enable { username } => {
+            users::set_disabled(db, &username, false).await?;
+            Ok(format!("enabled {}", username))
+        }
+        UserCommand::ResetPassword { username } => {
+            let userid = users::get_user_id(db, &username).await?;
+            let password = passwords::generate_password();
+            passwords::set_password(db, argon2, userid, &password).await?;
+            users::revoke_sessions(db, userid).await?;
+            Ok(format!("{}'s new password is {}", username, password))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use argon2::Params;
+
+    use super::*;
+
+    fn parse(args: &str) -> Result<Command, String> {
+        let args: Vec<String> = args.split_whitespace().map(str::to_string).collect();
+        Command::parse(&args)
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse(""), Ok(Command::Serve));
+        assert_eq!(parse("serve"), Ok(Command::Serve));
+        assert_eq!(parse("check-config"), Ok(Command::CheckConfig));
+        assert_eq!(parse("--help"), Ok(Command::Help));
+
+        assert_eq!(
+            parse("migrate"),
+            Ok(Command::Migrate(MigrateCommand::Status))
+        );
+        assert_eq!(
+            parse("migrate up --dry-run"),
+            Ok(Command::Migrate(MigrateCommand::Up { dry_run: true }))
+        );
+        assert_eq!(
+            parse("migrate down --dry-run --to 3"),
+            Ok(Command::Migrate(MigrateCommand::Down {
+                to: 3,
+                dry_run: true
+            }))
+        );
+        assert!(parse("migrate down").is_err());
+        assert!(parse("migrate down --to three").is_err());
+        assert!(parse("migrate status --dry-run").is_err());
+
+        assert_eq!(
+            parse("user create alice --email alice@example.com"),
+            Ok(Command::User(UserCommand::Create {
+                username: "alice".to_string(),
+                email: Some("alice@example.com".to_string())
+            }))
+        );
+        assert_eq!(
+            parse("user set-role alice steward"),
+            Ok(Command::User(UserCommand::SetRole {
+                username: "alice".to_string(),
+                role: Role::Steward
+            }))
+        );
+        assert!(parse("user set-role alice emperor").is_err());
+        assert!(parse("user disable").is_err());
+        assert!(parse("serve --port 80").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_user_commands() {
+        let client = Client::in_memory().unwrap();
+        let argon2 = passwords::hasher(Params::new(8, 1, 1, None).unwrap());
+        let (db, hasher) = (&client, &argon2);
+        let run = move |command: &str| {
+            let Ok(Command::User(command)) = parse(command) else {
+                panic!("not a user command: {}", command)
+            };
+            user(db, hasher, command)
+        };
+        let password_in = |output: String| output.rsplit(' ').next().unwrap().to_string();
+
+        // nothing's done until the database has been migrated
+        let e = run("user create admin").await.unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<Errors>(),
+            Some(Errors::PendingMigrations(pending)) if *pending == MIGRATIONS.len()
+        ));
+        models::init_db(&client).await.unwrap();
+
+        let admin = password_in(run("user create admin").await.unwrap());
+        run("user create alice --email alice@example.com")
+            .await
+            .unwrap();
+        assert!(run("user create alice").await.is_err());
+        run("user set-role alice steward").await.unwrap();
+        assert_eq!(
+            run("user list").await.unwrap(),
+            format!("{:<24}  admin\n{:<24}  steward", "admin", "alice")
+        );
+
+        // a disabled user can't log in, even with the right password
+        run("user disable admin").await.unwrap();
+        assert!(run("user list").await.unwrap().contains("disabled"));
+        assert!(matches!(
+            passwords::validate_password(&client, &argon2, "admin", &admin).await,
+            Err(Errors::UserDisabled(_))
+        ));
+        assert!(
+            users::sessions_revoked_at(
+                &client,
+                users::get_user_id(&client, "admin").await.unwrap()
+            )
+            .await
+            .unwrap()
+                > 0
+        );
+        run("user enable admin").await.unwrap();
+        passwords::validate_password(&client, &argon2, "admin", &admin)
+            .await
+            .unwrap();
+
+        let reset = password_in(run("user reset-password admin").await.unwrap());
+        assert!(
+            passwords::validate_password(&client, &argon2, "admin", &admin)
+                .await
+                .is_err()
+        );
+        passwords::validate_password(&client, &argon2, "admin", &reset)
+            .await
+            .unwrap();
+        assert!(run("user disable nobody").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_migrate_commands() {
+        let client = Client::in_memory().unwrap();
+        let status = migrate(&client, MigrateCommand::Status).await.unwrap();
+        assert_eq!(status.lines().count(), MIGRATIONS.len());
+        assert!(status.lines().all(|line| line.ends_with("pending")));
+
+        let dry_run = migrate(&client, MigrateCommand::Up { dry_run: true })
+            .await
+            .unwrap();
+        assert!(dry_run.contains(MIGRATIONS[1].up.trim()));
+        assert!(migrate(&client, MigrateCommand::Status)
+            .await
+            .unwrap()
+            .lines()
+            .all(|line| line.ends_with("pending")));
+
+        assert_eq!(
+            migrate(&client, MigrateCommand::Up { dry_run: false })
+                .await
+                .unwrap(),
+            format!("applied {} migrations", MIGRATIONS.len())
+        );
+        assert!(!migrate(&client, MigrateCommand::Status)
+            .await
+            .unwrap()
+            .contains("pending"));
+
+        let to = MIGRATIONS.len() - 2;
+        let dry_run = migrate(&client, MigrateCommand::Down { to, dry_run: true })
+            .await
+            .unwrap();
+        assert!(dry_run.contains(MIGRATIONS[to + 1].down.unwrap().trim()));
+        assert_eq!(
+            migrate(&client, MigrateCommand::Down { to, dry_run: false })
+                .await
+                .unwrap(),
+            "rolled back 2 migrations"
+        );
+        let status = migrate(&client, MigrateCommand::Status).await.unwrap();
+        assert_eq!(
+            status
+                .lines()
+                .filter(|line| line.ends_with("pending"))
+                .count(),
+            2
+        );
+    }
+}
@@ -0,0 +1,45 @@
+use axum_sessions::async_session::chrono::{DateTime, Utc};
+
+/// the source of "now" for anything time-sensitive enough to need testing,
+/// so tests can move time forward instead of sleeping
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+#[cfg(test)]
+pub use fake::FakeClock;
+
+#[cfg(test)]
+mod fake {
+    use std::sync::Mutex;
+
+    use axum_sessions::async_session::chrono::{DateTime, Duration, Utc};
+
+    use super::Clock;
+
+    pub struct FakeClock(Mutex<DateTime<Utc>>);
+
+    impl FakeClock {
+        pub fn new(now: DateTime<Utc>) -> Self {
+            Self(Mutex::new(now))
+        }
+
+        pub fn advance(&self, by: Duration) {
+            *self.0.lock().unwrap() += by;
+        }
+    }
+
+    impl Clock for FakeClock {
+        fn now(&self) -> DateTime<Utc> {
+            *self.0.lock().unwrap()
+        }
+    }
+}
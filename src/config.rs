@@ -1,10 +1,11 @@
-use std::{env, str::FromStr};
+use std::{env, path::PathBuf, str::FromStr};
 
 /// this file/struct contains environment variables for the app.
 /// the goal is to provide structure, so it's easier to know
 /// what variables are required, as well as centralize how they're provided
 use dotenv::dotenv;
 use log::warn;
+use url::Url;
 
 use crate::errors::Errors;
 
@@ -14,6 +15,48 @@ pub struct Config {
     pub db_token: Option<String>,
     pub stage: Stage,
     pub log_level: log::Level,
+    /// signs session cookies; must be at least 64 bytes and the same on every replica
+    pub session_secret: String,
+    pub webauthn: WebauthnConfig,
+    /// argon2 memory (KiB), time and parallelism costs for password hashes
+    pub argon2_params: argon2::Params,
+    /// where the app is reachable, for links sent outside the browser
+    pub public_url: Url,
+    pub password_reset_ttl_mins: i64,
+    /// lets anyone register without an invitation; meant for local development
+    pub open_registration: bool,
+    pub invitation_ttl_days: i64,
+    /// notifications are appended here if set, and emailed otherwise
+    pub notifier_file: Option<PathBuf>,
+    pub mail: MailConfig,
+    /// a header the proxy in front of the app puts the client's address in, e.g.
+    /// `X-Forwarded-For`. without one, logins are throttled by the proxy's address
+    pub client_ip_header: Option<String>,
+}
+
+/// outbound email; sent over smtp in prod, and written to `dir` everywhere else
+#[derive(Debug)]
+pub struct MailConfig {
+    /// the From address, e.g. `Lochstep <noreply@example.com>`
+    pub from: String,
+    pub smtp_host: Option<String>,
+    pub smtp_port: u16,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+    /// upgrade the smtp connection with STARTTLS; only disable this for a local stand-in
+    pub smtp_starttls: bool,
+    pub dir: PathBuf,
+}
+
+/// the webauthn relying party this deployment presents itself as
+#[derive(Debug)]
+pub struct WebauthnConfig {
+    /// effective domain passkeys are bound to, e.g. `example.com`
+    pub rp_id: String,
+    /// human-readable name shown by authenticators
+    pub rp_name: String,
+    /// origins allowed to perform ceremonies; the first is the primary origin
+    pub rp_origins: Vec<Url>,
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -37,24 +80,279 @@ impl FromStr for Stage {
 }
 
 impl Config {
-    pub fn from_env() -> Self {
+    /// reads every setting, and reports every variable that's missing or malformed rather
+    /// than only the first, so they can all be fixed at once
+    pub fn from_env() -> Result<Self, Errors> {
         if let Err(e) = dotenv() {
             warn!("failed to load dotenv: {}", e);
         }
-        Config {
-            db_url: env::var("DB_URL").expect("error loading env.DB_URL"),
+        let mut env = Env::default();
+
+        let db_url = env.required("DB_URL");
+        let db_token = env.required("DB_TOKEN");
+        // one of crate::config::Stage
+        let stage = env.required("STAGE");
+        let log_level = env.parsed("LOG_LEVEL", log::Level::Info.as_str());
+        let session_secret: Option<String> = env.required("SESSION_SECRET");
+        if session_secret
+            .as_ref()
+            .is_some_and(|secret| secret.len() < 64)
+        {
+            env.problem("SESSION_SECRET must be at least 64 bytes".to_string());
+        }
+        let argon2_params = argon2::Params::new(
+            env.or("ARGON2_MEMORY_KIB", argon2::Params::DEFAULT_M_COST),
+            env.or("ARGON2_ITERATIONS", argon2::Params::DEFAULT_T_COST),
+            env.or("ARGON2_PARALLELISM", argon2::Params::DEFAULT_P_COST),
+            None,
+        );
+        if let Err(e) = &argon2_params {
+            env.problem(format!("ARGON2_* don't make valid argon2 params: {}", e));
+        }
+        let public_url = env.parsed("PUBLIC_URL", "http://localhost:8080");
+        let webauthn = WebauthnConfig::from_env(&mut env);
+        let mail = MailConfig::from_env(&mut env);
+        let password_reset_ttl_mins = env.or("PASSWORD_RESET_TTL_MINS", 60);
+        let open_registration = env.or("OPEN_REGISTRATION", false);
+        let invitation_ttl_days = env.or("INVITATION_TTL_DAYS", 7);
+
+        let (
+            Some(db_url),
+            Some(db_token),
+            Some(stage),
+            Some(log_level),
+            Some(session_secret),
+            Ok(argon2_params),
+            Some(public_url),
+            true,
+        ) = (
+            db_url,
+            db_token,
+            stage,
+            log_level,
+            session_secret,
+            argon2_params,
+            public_url,
+            env.problems.is_empty(),
+        )
+        else {
+            return Err(Errors::ConfigError(env.problems));
+        };
+
+        Ok(Config {
+            db_url,
+            db_token: Some(db_token),
+            stage,
+            log_level,
+            session_secret,
+            webauthn,
+            argon2_params,
+            public_url,
+            password_reset_ttl_mins,
+            open_registration,
+            invitation_ttl_days,
+            notifier_file: env::var("NOTIFIER_FILE").ok().map(PathBuf::from),
+            mail,
+            client_ip_header: env::var("CLIENT_IP_HEADER").ok(),
+        })
+    }
+
+    /// checks settings that can't be validated while parsing, so bad config fails at startup
+    pub fn validate(&self) -> Result<(), Errors> {
+        if self.open_registration && self.stage == Stage::Prod {
+            warn!("OPEN_REGISTRATION is set in prod; anyone can create an account");
+        }
+        self.webauthn.validate()?;
+        self.mail.validate(&self.stage)
+    }
+}
+
+impl MailConfig {
+    fn from_env(env: &mut Env) -> Self {
+        MailConfig {
+            from: env::var("MAIL_FROM").unwrap_or("Lochstep <noreply@localhost>".to_string()),
+            smtp_host: env::var("SMTP_HOST").ok(),
+            smtp_port: env.or("SMTP_PORT", 587),
+            smtp_username: env::var("SMTP_USERNAME").ok(),
+            smtp_password: env::var("SMTP_PASSWORD").ok(),
+            smtp_starttls: env.or("SMTP_STARTTLS", true),
+            dir: env::var("MAIL_DIR").unwrap_or("mail".to_string()).into(),
+        }
+    }
+
+    /// prod needs an smtp server, and every stage needs a From address that parses
+    pub fn validate(&self, stage: &Stage) -> Result<(), Errors> {
+        if let Err(e) = self.from.parse::<lettre::message::Mailbox>() {
+            return Err(Errors::MailConfigError(format!(
+                "MAIL_FROM {:?} is not a valid address: {}",
+                self.from, e
+            )));
+        }
+        if *stage == Stage::Prod && self.smtp_host.is_none() {
+            return Err(Errors::MailConfigError(
+                "SMTP_HOST must be set in prod".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// reads variables from the environment, noting what's wrong with them as it goes rather
+/// than stopping at the first problem
+#[derive(Default)]
+struct Env {
+    problems: Vec<String>,
+}
+
+impl Env {
+    fn problem(&mut self, problem: String) {
+        self.problems.push(problem);
+    }
+
+    /// a variable that has to be set
+    fn required<T: FromStr>(&mut self, key: &str) -> Option<T> {
+        match env::var(key) {
+            Ok(val) => self.parse(key, &val),
+            Err(_) => {
+                self.problem(format!("{} is not set", key));
+                None
+            }
+        }
+    }
+
+    /// parses an optional variable, or `default` when it's unset
+    fn parsed<T: FromStr>(&mut self, key: &str, default: &str) -> Option<T> {
+        let val = env::var(key).unwrap_or(default.to_string());
+        self.parse(key, &val)
+    }
+
+    /// parses an optional variable, falling back to `default` when it's unset
+    fn or<T: FromStr>(&mut self, key: &str, default: T) -> T {
+        match env::var(key) {
+            Ok(val) => self.parse(key, &val).unwrap_or(default),
+            Err(_) => default,
+        }
+    }
+
+    fn parse<T: FromStr>(&mut self, key: &str, val: &str) -> Option<T> {
+        let parsed = val.parse().ok();
+        if parsed.is_none() {
+            self.problem(format!("{} can't be parsed from {:?}", key, val));
+        }
+        parsed
+    }
+}
+
+impl WebauthnConfig {
+    fn from_env(env: &mut Env) -> Self {
+        let rp_id = env::var("WEBAUTHN_RP_ID").unwrap_or("localhost".to_string());
+        let rp_origins = env::var("WEBAUTHN_RP_ORIGINS")
+            .unwrap_or(format!("https://{}", rp_id))
+            .split(',')
+            .filter_map(|origin| env.parse("WEBAUTHN_RP_ORIGINS", origin.trim()))
+            .collect();
+
+        WebauthnConfig {
+            rp_id,
+            rp_name: env::var("WEBAUTHN_RP_NAME").unwrap_or("Lochstep".to_string()),
+            rp_origins,
+        }
+    }
 
-            db_token: Some(env::var("DB_TOKEN").expect("error loading env.DB_TOKEN")),
+    /// every origin must be https (or localhost), and its host must be the rp id or a subdomain of it
+    pub fn validate(&self) -> Result<(), Errors> {
+        let invalid = |reason: String| Err(Errors::WebauthnConfigError(reason));
+
+        if self.rp_id.is_empty() || self.rp_id.contains([':', '/']) {
+            return invalid(format!(
+                "WEBAUTHN_RP_ID must be a bare domain name, got {:?}",
+                self.rp_id
+            ));
+        }
+        if self.rp_origins.is_empty() {
+            return invalid("WEBAUTHN_RP_ORIGINS must list at least one origin".to_string());
+        }
+
+        for origin in &self.rp_origins {
+            let host = origin.host_str().unwrap_or_default();
+            if origin.scheme() != "https" && host != "localhost" {
+                return invalid(format!("origin {} must use https", origin));
+            }
+            if host != self.rp_id && !host.ends_with(&format!(".{}", self.rp_id)) {
+                return invalid(format!(
+                    "origin {} does not match WEBAUTHN_RP_ID {}",
+                    origin, self.rp_id
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn webauthn_config(rp_id: &str, origins: &[&str]) -> WebauthnConfig {
+        WebauthnConfig {
+            rp_id: rp_id.to_string(),
+            rp_name: "Lochstep".to_string(),
+            rp_origins: origins.iter().map(|o| Url::parse(o).unwrap()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_env_collects_problems() {
+        env::set_var("LOCHSTEP_TEST_PORT", "eighty");
+        env::set_var("LOCHSTEP_TEST_URL", "not a url");
+        let mut env = Env::default();
+
+        assert_eq!(env.required::<String>("LOCHSTEP_TEST_MISSING"), None);
+        assert_eq!(env.or("LOCHSTEP_TEST_PORT", 587u16), 587);
+        assert_eq!(env.or("LOCHSTEP_TEST_UNSET", 7), 7);
+        assert_eq!(
+            env.parsed::<Url>("LOCHSTEP_TEST_URL", "http://localhost"),
+            None
+        );
+        assert_eq!(
+            env.parsed::<Url>("LOCHSTEP_TEST_UNSET", "http://localhost"),
+            Url::parse("http://localhost").ok()
+        );
+        assert_eq!(
+            env.problems,
+            [
+                "LOCHSTEP_TEST_MISSING is not set",
+                "LOCHSTEP_TEST_PORT can't be parsed from \"eighty\"",
+                "LOCHSTEP_TEST_URL can't be parsed from \"not a url\"",
+            ]
+        );
+    }
 
-            stage: env::var("STAGE")
-                .expect("error loading env.STAGE")
-                .parse()
-                .expect("error parsing env.STAGE (does it match one of crate::config::Stage?)"),
+    #[test]
+    fn test_webauthn_config_validation() {
+        assert!(webauthn_config("example.com", &["https://example.com"])
+            .validate()
+            .is_ok());
+        assert!(
+            webauthn_config("example.com", &["https://app.example.com:8443"])
+                .validate()
+                .is_ok()
+        );
+        assert!(webauthn_config("localhost", &["http://localhost:8080"])
+            .validate()
+            .is_ok());
 
-            log_level: env::var("LOG_LEVEL")
-                .unwrap_or(log::Level::Info.to_string())
-                .parse()
-                .expect("error parsing env.LOG_LEVEL"),
+        for config in [
+            webauthn_config("example.com", &["https://example.org"]),
+            webauthn_config("example.com", &["https://notexample.com"]),
+            webauthn_config("example.com", &["http://example.com"]),
+            webauthn_config("https://example.com", &["https://example.com"]),
+            webauthn_config("example.com", &[]),
+        ] {
+            assert!(matches!(
+                config.validate(),
+                Err(Errors::WebauthnConfigError(_))
+            ));
         }
     }
 }
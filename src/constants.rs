@@ -1,3 +1,37 @@
 pub mod session_keys {
     pub const AUTH_STATE: &str = "auth_state";
+    pub const PASSKEY_AUTH_STATE: &str = "passkey_auth_state";
+}
+
+pub mod proposals {
+    pub const DEFAULT_VOTING_DAYS: i64 = 7;
+    pub const CLOSE_EXPIRED_INTERVAL_SECS: u64 = 60;
+    /// times to re-tally a proposal being closed while votes keep coming in
+    pub const CLOSE_ATTEMPTS: usize = 3;
+}
+
+pub mod sessions {
+    pub const CLEANUP_INTERVAL_SECS: u64 = 60 * 60;
+}
+
+pub mod login {
+    /// failed attempts allowed before backoff kicks in
+    pub const FREE_ATTEMPTS: u32 = 3;
+    pub const BACKOFF_BASE_SECS: i64 = 1;
+    pub const MAX_BACKOFF_SECS: i64 = 5 * 60;
+    /// failed attempts that lock a username or address out entirely
+    pub const LOCKOUT_THRESHOLD: u32 = 10;
+    pub const LOCKOUT_SECS: i64 = 15 * 60;
+    /// failures older than this are forgotten
+    pub const FAILURE_WINDOW_SECS: i64 = 60 * 60;
+}
+
+pub mod mail {
+    pub const OUTBOX_INTERVAL_SECS: u64 = 30;
+    /// the first retry waits this long, and each one after waits twice as long
+    pub const RETRY_BASE_SECS: i64 = 60;
+    pub const MAX_ATTEMPTS: u32 = 8;
+    /// a worker that claimed an email and hasn't reported back in this long is assumed to
+    /// have died, and someone else may send it
+    pub const CLAIM_TIMEOUT_SECS: i64 = 10 * 60;
 }
@@ -0,0 +1,68 @@
+use axum::{
+    extract::Path,
+    response::{ErrorResponse, Html},
+    Extension, Json,
+};
+use hyper::StatusCode;
+use log::info;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    controllers::auth::permissions::{Admin, RequireRole, Steward},
+    errors::Errors,
+    handle_error,
+    models::roles::Role,
+    state::AppState,
+    views,
+};
+
+#[derive(Serialize, Deserialize)]
+pub struct RoleForm {
+    pub(crate) role: Role,
+}
+
+pub async fn list_members(
+    Extension(app): Extension<AppState>,
+    RequireRole { user, .. }: RequireRole<Admin>,
+) -> Result<Html<String>, ErrorResponse> {
+    let members = app
+        .users
+        .all_users()
+        .await
+        .map_err(|e| handle_error("Error fetching members", e))?;
+    views::members(app.templates, user.username, members)
+        .map_err(|e| handle_error("Error rendering members", e))
+}
+
+pub async fn set_role(
+    Extension(app): Extension<AppState>,
+    RequireRole { user, .. }: RequireRole<Admin>,
+    Path(username): Path<String>,
+    Json(req): Json<RoleForm>,
+) -> Result<(StatusCode, String), ErrorResponse> {
+    match app.users.set_role(&username, req.role).await {
+        Ok(()) => {
+            info!("{} made {} {}", user.username, username, req.role);
+            Ok((StatusCode::OK, format!("{} is now {}.", username, req.role)))
+        }
+        // admins can see every member anyway, so there's nothing to hide here
+        Err(Errors::DbUserNotFound(_)) => Err(handle_error(
+            "Error setting role",
+            Errors::NotFound("No such member"),
+        )),
+        Err(e) => Err(handle_error("Error setting role", e)),
+    }
+}
+
+pub async fn list_lockouts(
+    Extension(app): Extension<AppState>,
+    _: RequireRole<Steward>,
+) -> Result<Html<String>, ErrorResponse> {
+    let lockouts = app
+        .login_attempts
+        .lockouts()
+        .await
+        .map_err(|e| handle_error("Error fetching lockouts", e))?;
+    views::lockouts(app.templates, lockouts)
+        .map_err(|e| handle_error("Error rendering lockouts", e))
+}
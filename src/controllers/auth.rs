@@ -1,25 +1,57 @@
+use std::net::SocketAddr;
+
 use axum::{
+    extract::{ConnectInfo, Query},
     response::{ErrorResponse, Html},
     Extension, Json,
 };
-use axum_sessions::extractors::WritableSession;
-use hyper::StatusCode;
-use log::debug;
+use axum_sessions::{async_session::chrono, extractors::WritableSession};
+use hyper::{HeaderMap, StatusCode};
+use log::{debug, error};
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::{
-    constants::session_keys::AUTH_STATE, errors::Errors, handle_error, models, state::AppState,
+    constants::session_keys::AUTH_STATE,
+    controllers::auth::current_user::OptionalUser,
+    errors::Errors,
+    handle_error,
+    models::{login_attempts::Subject, repositories::UserRepository},
+    state::AppState,
     views,
 };
 
-#[cfg(passkey)]
-mod passkey_auth;
+pub mod current_user;
+#[cfg(feature = "passkey")]
+pub mod passkey_auth;
+pub mod password_reset;
+pub mod permissions;
+
+pub(crate) type Redirect = (StatusCode, [(&'static str, &'static str); 1], &'static str);
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct AuthState {
     pub username: String,
     pub userid: Uuid,
+    /// unix timestamp in milliseconds, compared against the user's `sessionsRevokedAt`
+    #[serde(default)]
+    pub logged_in_at: i64,
+}
+
+impl AuthState {
+    pub fn new(username: String, userid: Uuid) -> Self {
+        Self {
+            username,
+            userid,
+            logged_in_at: chrono::offset::Utc::now().timestamp_millis(),
+        }
+    }
+
+    /// whether this session was created before the user last logged out everywhere
+    pub async fn is_revoked(&self, users: &dyn UserRepository) -> Result<bool, Errors> {
+        let revoked_at = users.sessions_revoked_at(self.userid).await?;
+        Ok(self.logged_in_at < revoked_at)
+    }
 }
 
 #[derive(Serialize, Deserialize)]
@@ -28,18 +60,103 @@ pub struct Login {
     pub(crate) password: String,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct Registration {
+    pub(crate) username: String,
+    pub(crate) password: String,
+    /// optional, but needed for password resets and other notifications.
+    /// ignored when registering with an invitation, which was sent to an address already
+    #[serde(default)]
+    pub(crate) email: Option<String>,
+    /// the token from an invitation link; required unless registration is open
+    #[serde(default)]
+    pub(crate) invite: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct RegistrationQuery {
+    invite: Option<String>,
+}
+
+/// 400s if `email` isn't a valid address
+pub(crate) fn validate_email(email: &str) -> Result<(), ErrorResponse> {
+    match email.parse::<lettre::Address>() {
+        Ok(_) => Ok(()),
+        Err(e) => Err(handle_error("Invalid email", Errors::MailAddressError(e))),
+    }
+}
+
+pub(crate) fn invitation_required() -> ErrorResponse {
+    handle_error("Error registering", Errors::InvitationRequired)
+}
+
+/// the page an invitation link opens
+pub async fn get_registration(
+    Extension(app): Extension<AppState>,
+    Query(query): Query<RegistrationQuery>,
+) -> Result<Html<String>, ErrorResponse> {
+    let invite = query.invite.unwrap_or_default();
+    let email = match app
+        .invitations
+        .get_invitation_email(&invite, app.clock.now())
+        .await
+    {
+        Ok(email) => Some(email),
+        Err(Errors::InvalidInvitation) => None,
+        Err(e) => return Err(handle_error("Error checking invitation", e)),
+    };
+    views::register(app.templates, invite, email)
+        .map_err(|e| handle_error("Error rendering registration page", e))
+}
+
 pub(crate) async fn create_password_registration(
     Extension(app): Extension<AppState>,
     mut session: WritableSession,
-    Json(req): Json<Login>,
+    Json(req): Json<Registration>,
 ) -> Result<(StatusCode, String), ErrorResponse> {
     debug!("creating password registration");
     session.remove(AUTH_STATE);
+
+    // htmx sends an empty field rather than leaving it out
+    let invite = req.invite.as_deref().filter(|i| !i.is_empty());
+    let email = match invite {
+        Some(invite) => {
+            match app
+                .invitations
+                .consume_invitation(invite, app.clock.now())
+                .await
+            {
+                Ok(email) => Some(email),
+                Err(e) => return Err(handle_error("Error checking invitation", e)),
+            }
+        }
+        None if app.open_registration => {
+            let email = req
+                .email
+                .as_deref()
+                .map(str::trim)
+                .filter(|e| !e.is_empty());
+            if let Some(email) = email {
+                validate_email(email)?;
+            }
+            email.map(str::to_string)
+        }
+        None => return Err(invitation_required()),
+    };
+
     // check if user exists in db, if so login
     // add user/pw to db
-    if let Err(e) =
-        models::users::create_user_with_password(&app.db, &req.username, &req.password).await
+    if let Err(e) = app
+        .users
+        .create_user_with_password(&app.argon2, &req.username, &req.password, email.as_deref())
+        .await
     {
+        // let them try again with another username
+        if let Some(invite) = invite {
+            if let Err(e) = app.invitations.release_invitation(invite).await {
+                error!("error releasing invitation: {:?}", e);
+            }
+        }
         return Err(handle_error("Error creating user", e));
     }
     Ok((StatusCode::ACCEPTED, "Success! Please login.".to_string()))
@@ -47,31 +164,57 @@ pub(crate) async fn create_password_registration(
 
 pub async fn login(
     Extension(app): Extension<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
     mut session: WritableSession,
     Json(req): Json<Login>,
 ) -> Result<(StatusCode, Html<String>), ErrorResponse> {
     // check that username and password are present
-    if (req.username.is_empty()) && (req.password.is_empty()) {
+    if req.username.is_empty() || req.password.is_empty() {
         debug!("login attempt error, empty username or password");
-        return Err((
-            StatusCode::BAD_REQUEST,
-            "Username and password are required".to_string(),
-        )
-            .into());
+        return Err(Errors::LoginErrorUsernameOrPasswordMissing.into());
+    }
+
+    // throttle repeated failures from this username or address
+    let address = client_address(&app, &headers, connect_info);
+    let subjects = [Subject::Username(&req.username), Subject::Address(&address)];
+    let now = app.clock.now();
+    match app.login_attempts.retry_at(&subjects, now).await {
+        Ok(Some(retry_at)) => return Err(too_many_attempts((retry_at - now).num_seconds())),
+        Ok(None) => (),
+        Err(e) => return Err(handle_error("Error checking login attempts", e)),
     }
 
     // validate password
-    let uuid =
-        match models::passwords::validate_password(&app.db, &req.username, &req.password).await {
-            Ok(uuid) => uuid,
-            Err(e) => return Err(handle_error("Error validating password", e)),
-        };
+    let uuid = match app
+        .users
+        .validate_password(&app.argon2, &req.username, &req.password)
+        .await
+    {
+        Ok(uuid) => uuid,
+        // both respond the same way, so this can't be used to find out which usernames exist
+        Err(e @ (Errors::DbUserNotFound(_) | Errors::DbNoHashMatch(_))) => {
+            debug!("failed login attempt for {} from {}", req.username, address);
+            if let Err(e) = app.login_attempts.record_failure(&subjects, now).await {
+                return Err(handle_error("Error recording login attempt", e));
+            }
+            return Err(e.into());
+        }
+        Err(e @ Errors::UserDisabled(_)) => return Err(e.into()),
+        Err(e) => return Err(handle_error("Error validating password", e)),
+    };
+    // only the username's failures are forgotten. the address's decay on their own, otherwise
+    // a client with one account could log into it between guesses at everyone else's
+    if let Err(e) = app
+        .login_attempts
+        .clear(Subject::Username(&req.username))
+        .await
+    {
+        return Err(handle_error("Error clearing login attempts", e));
+    }
 
     // create session
-    let auth_state = AuthState {
-        username: req.username,
-        userid: uuid,
-    };
+    let auth_state = AuthState::new(req.username, uuid);
     if let Err(e) = session.insert(AUTH_STATE, auth_state) {
         return Err(handle_error(
             "Error creating session",
@@ -84,3 +227,61 @@ pub async fn login(
         Err(e) => Err(handle_error("Error rendering login template", e)),
     }
 }
+
+/// the address a request came from, for throttling. behind a proxy every connection is the
+/// proxy's, so the address is read from `client_ip_header` if one is configured; only its last
+/// entry, which the proxy added, is trusted, since anything before it came from the client
+pub(crate) fn client_address(
+    app: &AppState,
+    headers: &HeaderMap,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+) -> String {
+    let forwarded = app
+        .client_ip_header
+        .as_deref()
+        .and_then(|name| headers.get(name))
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.rsplit(',').next())
+        .map(str::trim)
+        .filter(|address| !address.is_empty());
+    match (forwarded, connect_info) {
+        (Some(address), _) => address.to_string(),
+        (None, Some(ConnectInfo(addr))) => addr.ip().to_string(),
+        (None, None) => "unknown".to_string(),
+    }
+}
+
+pub(crate) fn too_many_attempts(retry_after_secs: i64) -> ErrorResponse {
+    handle_error(
+        "Rate limited",
+        Errors::TooManyAttempts {
+            retry_after: retry_after_secs,
+        },
+    )
+}
+
+fn redirect_home() -> Redirect {
+    (StatusCode::OK, [("HX-Redirect", "/")], "/")
+}
+
+pub async fn logout(mut session: WritableSession) -> Redirect {
+    debug!("logging out");
+    session.destroy();
+    redirect_home()
+}
+
+/// ends this session, and invalidates every other session the user has open
+pub async fn logout_everywhere(
+    Extension(app): Extension<AppState>,
+    OptionalUser(user): OptionalUser,
+    mut session: WritableSession,
+) -> Result<Redirect, ErrorResponse> {
+    if let Some(user) = user {
+        debug!("logging out {} everywhere", user.username);
+        if let Err(e) = app.users.revoke_sessions(user.userid).await {
+            return Err(handle_error("Error revoking sessions", e));
+        }
+    }
+    session.destroy();
+    Ok(redirect_home())
+}
@@ -0,0 +1,126 @@
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use axum_sessions::extractors::ReadableSession;
+use http::{header, request::Parts};
+use hyper::StatusCode;
+use log::debug;
+use uuid::Uuid;
+
+use crate::{
+    constants::session_keys::AUTH_STATE, controllers::auth::AuthState, errors::Errors,
+    handle_error, models::roles::Role, state::AppState,
+};
+
+/// the logged in user, loaded from the session and checked against the `users` table.
+///
+/// rejects with a redirect to the login page for pages a browser navigated to, and a 401
+/// for json and htmx requests. use `OptionalUser` where logging in is optional.
+/// it reads the session, so it has to come before a `WritableSession` in a handler's arguments
+#[derive(Debug, Clone)]
+pub struct CurrentUser {
+    pub userid: Uuid,
+    pub username: String,
+    pub role: Role,
+}
+
+/// the logged in user, or `None` if nobody is. unlike `Option<CurrentUser>`, which treats
+/// every rejection as being logged out, anything going wrong is still an error
+#[derive(Debug, Clone)]
+pub struct OptionalUser(pub Option<CurrentUser>);
+
+impl CurrentUser {
+    /// `None` if there's no session, or it no longer counts
+    async fn load<S>(parts: &mut Parts, state: &S) -> Result<Option<Self>, Response>
+    where
+        S: Send + Sync,
+    {
+        let Extension(app) = Extension::<AppState>::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+        let session = ReadableSession::from_request_parts(parts, state)
+            .await
+            .map_err(IntoResponse::into_response)?;
+
+        let auth = match session.get::<AuthState>(AUTH_STATE) {
+            Some(auth) => auth,
+            None => return Ok(None),
+        };
+        // release the session before anything else in the handler wants to write to it
+        drop(session);
+
+        match auth.is_revoked(app.users.as_ref()).await {
+            Ok(false) => (),
+            Ok(true) => {
+                debug!("rejecting revoked session for {}", auth.username);
+                return Ok(None);
+            }
+            Err(e) => return Err(handle_error("Error validating session", e).into_response()),
+        }
+
+        match app.users.get_user(auth.userid).await {
+            Ok(user) if user.disabled => {
+                debug!("rejecting session for disabled user {}", auth.username);
+                Ok(None)
+            }
+            Ok(user) => Ok(Some(Self {
+                userid: auth.userid,
+                username: user.username,
+                role: user.role,
+            })),
+            Err(Errors::DbUserNotFound(_)) => {
+                debug!("rejecting session for deleted user {}", auth.userid);
+                Ok(None)
+            }
+            Err(e) => Err(handle_error("Error fetching user", e).into_response()),
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for CurrentUser
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        match Self::load(parts, state).await? {
+            Some(user) => Ok(user),
+            None => Err(unauthenticated(parts)),
+        }
+    }
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for OptionalUser
+where
+    S: Send + Sync,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        CurrentUser::load(parts, state).await.map(OptionalUser)
+    }
+}
+
+/// whether a browser navigated here, rather than htmx or an api client making a request
+fn wants_html(parts: &Parts) -> bool {
+    let accepts_html = parts
+        .headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .map_or(false, |accept| accept.contains("text/html"));
+    accepts_html && !parts.headers.contains_key("HX-Request")
+}
+
+fn unauthenticated(parts: &Parts) -> Response {
+    if wants_html(parts) {
+        (StatusCode::SEE_OTHER, [(header::LOCATION, "/")]).into_response()
+    } else {
+        Errors::Unauthenticated.into_response()
+    }
+}
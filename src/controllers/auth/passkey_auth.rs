@@ -1,8 +1,31 @@
+use axum::{
+    extract::Path,
+    response::{ErrorResponse, Html},
+    routing::{get, post, put},
+    Extension, Json, Router,
+};
+use axum_sessions::extractors::WritableSession;
+use hyper::StatusCode;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 use webauthn_rs::prelude::{
-    CreationChallengeResponse, PasskeyRegistration, RegisterPublicKeyCredential,
+    CreationChallengeResponse, PasskeyAuthentication, PasskeyRegistration, PublicKeyCredential,
+    RegisterPublicKeyCredential, RequestChallengeResponse,
+};
+
+use crate::{
+    constants::session_keys::{AUTH_STATE, PASSKEY_AUTH_STATE},
+    controllers::auth::{
+        current_user::{CurrentUser, OptionalUser},
+        invitation_required, AuthState, Redirect,
+    },
+    errors::Errors,
+    handle_error,
+    state::AppState,
+    views,
 };
 
-#[cfg(passkey)]
 #[derive(Serialize, Deserialize, Debug)]
 pub struct SessionRegistrationState {
     pub username: String,
@@ -10,11 +33,32 @@ pub struct SessionRegistrationState {
     reg_state: PasskeyRegistration,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SessionAuthenticationState {
+    pub username: String,
+    pub userid: Uuid,
+    auth_state: PasskeyAuthentication,
+}
+
 #[derive(Serialize, Deserialize)]
-pub struct PasskeyRegistrationOptionsRequest {
+pub struct PasskeyAuthenticationOptionsRequest {
     username: String,
 }
 
+/// `username` is only needed to register a new account; logged in users add keys to their own
+#[derive(Serialize, Deserialize)]
+pub struct PasskeyRegistrationOptionsRequest {
+    #[serde(default)]
+    username: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct KeyForm {
+    pub(crate) nickname: String,
+}
+
+const KEYS_PAGE: &str = "/auth/passkey/keys";
+
 #[derive(Serialize, Deserialize)]
 pub struct PasskeyRegistrationOptions {
     id: Uuid,
@@ -22,28 +66,55 @@ pub struct PasskeyRegistrationOptions {
     registration_result: CreationChallengeResponse,
 }
 
+pub fn get_router() -> Router {
+    Router::new()
+        .route(
+            "/registration/options",
+            post(get_passkey_registration_options),
+        )
+        .route("/registration/create", post(create_passkey_registration))
+        .route(
+            "/authentication/options",
+            post(get_passkey_authentication_options),
+        )
+        .route(
+            "/authentication/finish",
+            post(finish_passkey_authentication),
+        )
+        .route("/keys", get(list_keys))
+        .route("/keys/:credential_id", put(rename_key).delete(remove_key))
+}
+
 pub async fn get_passkey_registration_options(
     Extension(app): Extension<AppState>,
+    OptionalUser(user): OptionalUser,
     mut session: WritableSession,
     Json(req): Json<PasskeyRegistrationOptionsRequest>,
 ) -> Result<(StatusCode, Json<CreationChallengeResponse>), ErrorResponse> {
-    let userid = match models::users::create_user(&app.db, &req.username).await {
-        Ok(id) => id,
-        Err(e) => return Err((StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into()),
+    let (userid, username) = match (user, req.username) {
+        (Some(user), _) => (user.userid, user.username),
+        // invitees register with a password, and can add passkeys once they're in
+        (None, Some(_)) if !app.open_registration => return Err(invitation_required()),
+        (None, Some(username)) if !username.trim().is_empty() => {
+            match app.users.create_user(&username).await {
+                Ok(id) => (id, username),
+                Err(e) => return Err(handle_error("Error creating user", e)),
+            }
+        }
+        (None, _) => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "A username is required".to_string(),
+            )
+                .into())
+        }
     };
 
     let challenge =
-        match generate_passkey_registration_challenge(&app, &mut session, &userid, &req.username)
-            .await
+        match generate_passkey_registration_challenge(&app, &mut session, &userid, &username).await
         {
             Ok(challenge) => challenge,
-            Err(e) => {
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    format!("error generating challenge: {}", e),
-                )
-                    .into())
-            }
+            Err(e) => return Err(handle_error("Error generating challenge", e)),
         };
 
     Ok((StatusCode::OK, Json(challenge)))
@@ -54,7 +125,7 @@ async fn generate_passkey_registration_challenge(
     session: &mut WritableSession,
     userid: &Uuid,
     username: &str,
-) -> Result<CreationChallengeResponse, Error> {
+) -> Result<CreationChallengeResponse, Errors> {
     // log::info!("Start register");
     // We get the username from the URL, but you could get this via form submission or
     // some other process. In some parts of Webauthn, you could also use this as a "display name"
@@ -82,21 +153,22 @@ async fn generate_passkey_registration_challenge(
 
     // If the user has any other credentials, we exclude these here so they can't be duplicate registered.
     // It also hints to the browser that only new credentials should be "blinked" for interaction.
-    // TODO: exclude existing credentials
-    // let exclude_credentials = {
-    //     let users_guard = app_state.users.lock().await;
-    //     users_guard
-    //         .keys
-    //         .get(&user_unique_id)
-    //         .map(|keys| keys.iter().map(|sk| sk.cred_id().clone()).collect())
-    // };
+    let exclude_credentials = app
+        .keys
+        .get_keys(*userid)
+        .await?
+        .iter()
+        .map(|key| key.cred_id().clone())
+        .collect();
 
-    let res = match app
-        .webauthn
-        .start_passkey_registration(*userid, username, username, None)
-    {
+    let res = match app.webauthn.start_passkey_registration(
+        *userid,
+        username,
+        username,
+        Some(exclude_credentials),
+    ) {
         Ok((ccr, reg_state)) => {
-            // Note that due to the session store in use being a server side memory store, this is
+            // Note that due to the session store in use being a server side store, this is
             // safe to store the reg_state into the session since it is not client controlled and
             // not open to replay attacks. If this was a cookie store, this would be UNSAFE.
             session
@@ -108,7 +180,7 @@ async fn generate_passkey_registration_challenge(
                         reg_state,
                     },
                 )
-                .expect("Failed to insert");
+                .map_err(Errors::SessionError)?;
             log::info!("Registration Successful!");
             ccr
         }
@@ -118,7 +190,7 @@ async fn generate_passkey_registration_challenge(
                 e
             );
             debug!("{}", err_msg);
-            return Err(Box::new(e));
+            return Err(Errors::WebauthnError(e));
         }
     };
 
@@ -148,20 +220,15 @@ pub async fn create_passkey_registration(
         .finish_passkey_registration(&reg, &session_res.reg_state)
     {
         Ok(sk) => {
-            //TODO: This is where we would store the credential in a db, or persist them in some other way.
-            // users_guard
-            //     .keys
-            //     .entry(user_unique_id)
-            //     .and_modify(|keys| keys.push(sk.clone()))
-            //     .or_insert_with(|| vec![sk.clone()]);
+            // name it after how many keys the user has; they can rename it later
+            let nickname = match app.keys.list_keys(session_res.userid).await {
+                Ok(keys) => format!("Passkey {}", keys.len() + 1),
+                Err(e) => return Err(handle_error("Error fetching passkeys", e)),
+            };
 
             // save key to db
-            if let Err(e) = models::keys::add_key(&app.db, session_res.userid, sk).await {
-                return Err((
-                    StatusCode::INTERNAL_SERVER_ERROR,
-                    format!("error saving passkey to db: {}", e),
-                )
-                    .into());
+            if let Err(e) = app.keys.add_key(session_res.userid, sk, &nickname).await {
+                return Err(handle_error("Error saving passkey", e));
             };
 
             log::info!("saved new key for user {:?}", session_res.username);
@@ -179,3 +246,168 @@ pub async fn create_passkey_registration(
 
     Ok(())
 }
+
+pub async fn get_passkey_authentication_options(
+    Extension(app): Extension<AppState>,
+    mut session: WritableSession,
+    Json(req): Json<PasskeyAuthenticationOptionsRequest>,
+) -> Result<(StatusCode, Json<RequestChallengeResponse>), ErrorResponse> {
+    // Remove any previous authentication that may have occured from the session.
+    session.remove(PASSKEY_AUTH_STATE);
+
+    let userid = match app.users.get_user_id(&req.username).await {
+        Ok(id) => id,
+        Err(e) => return Err(handle_error("Error finding user", e)),
+    };
+    let keys = match app.keys.get_keys(userid).await {
+        Ok(keys) if keys.is_empty() => {
+            return Err(handle_error(
+                "Error starting passkey authentication",
+                Errors::DbNoKeysForUser(req.username),
+            ))
+        }
+        Ok(keys) => keys,
+        Err(e) => return Err(handle_error("Error fetching passkeys", e)),
+    };
+
+    let (rcr, auth_state) = match app.webauthn.start_passkey_authentication(&keys) {
+        Ok(res) => res,
+        Err(e) => {
+            debug!("error starting passkey authentication: {:?}", e);
+            return Err((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                format!("error generating challenge: {}", e),
+            )
+                .into());
+        }
+    };
+
+    // As with registration, this is only safe because the session store is server side.
+    if let Err(e) = session.insert(
+        PASSKEY_AUTH_STATE,
+        SessionAuthenticationState {
+            username: req.username,
+            userid,
+            auth_state,
+        },
+    ) {
+        return Err(handle_error(
+            "Error saving authentication state",
+            Errors::SessionError(e),
+        ));
+    }
+
+    Ok((StatusCode::OK, Json(rcr)))
+}
+
+pub async fn finish_passkey_authentication(
+    Extension(app): Extension<AppState>,
+    mut session: WritableSession,
+    Json(auth): Json<PublicKeyCredential>,
+) -> Result<(StatusCode, Html<String>), ErrorResponse> {
+    let session_state: SessionAuthenticationState = match session.get(PASSKEY_AUTH_STATE) {
+        Some(state) => state,
+        None => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                "No authentication in progress.".to_string(),
+            )
+                .into())
+        }
+    };
+
+    session.remove(PASSKEY_AUTH_STATE);
+
+    let auth_result = match app
+        .webauthn
+        .finish_passkey_authentication(&auth, &session_state.auth_state)
+    {
+        Ok(auth_result) => auth_result,
+        Err(e) => {
+            debug!("error finishing passkey authentication: {:?}", e);
+            return Err((
+                StatusCode::UNAUTHORIZED,
+                format!("error finishing authentication: {}", e),
+            )
+                .into());
+        }
+    };
+
+    match app.users.get_user(session_state.userid).await {
+        Ok(user) if user.disabled => {
+            return Err(Errors::UserDisabled(session_state.username).into());
+        }
+        Ok(_) => (),
+        Err(e) => return Err(handle_error("Error fetching user", e)),
+    }
+
+    // keep the stored credential's counter in step with the authenticator
+    if let Err(e) = app
+        .keys
+        .record_key_use(session_state.userid, &auth_result)
+        .await
+    {
+        return Err(handle_error("Error updating passkey", e));
+    }
+
+    // create session, same as a password login
+    let auth_state = AuthState::new(session_state.username, session_state.userid);
+    if let Err(e) = session.insert(AUTH_STATE, auth_state) {
+        return Err(handle_error(
+            "Error creating session",
+            Errors::SessionError(e),
+        ));
+    }
+
+    match views::login_success(app.templates) {
+        Ok(res) => Ok((StatusCode::ACCEPTED, res)),
+        Err(e) => Err(handle_error("Error rendering login template", e)),
+    }
+}
+
+pub async fn list_keys(
+    Extension(app): Extension<AppState>,
+    user: CurrentUser,
+) -> Result<Html<String>, ErrorResponse> {
+    let keys = app
+        .keys
+        .list_keys(user.userid)
+        .await
+        .map_err(|e| handle_error("Error fetching passkeys", e))?;
+    views::keys(app.templates, user.username, keys)
+        .map_err(|e| handle_error("Error rendering passkeys", e))
+}
+
+pub async fn rename_key(
+    Extension(app): Extension<AppState>,
+    user: CurrentUser,
+    Path(credential_id): Path<String>,
+    Json(req): Json<KeyForm>,
+) -> Result<Redirect, ErrorResponse> {
+    let nickname = req.nickname.trim();
+    if nickname.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            "A nickname is required".to_string(),
+        )
+            .into());
+    }
+
+    app.keys
+        .rename_key(user.userid, &credential_id, nickname)
+        .await
+        .map_err(|e| handle_error("Error renaming passkey", e))?;
+    Ok((StatusCode::OK, [("HX-Redirect", KEYS_PAGE)], KEYS_PAGE))
+}
+
+pub async fn remove_key(
+    Extension(app): Extension<AppState>,
+    user: CurrentUser,
+    Path(credential_id): Path<String>,
+) -> Result<Redirect, ErrorResponse> {
+    app.keys
+        .remove_key(user.userid, &credential_id)
+        .await
+        .map_err(|e| handle_error("Error removing passkey", e))?;
+    Ok((StatusCode::OK, [("HX-Redirect", KEYS_PAGE)], KEYS_PAGE))
+}
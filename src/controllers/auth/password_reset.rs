@@ -0,0 +1,129 @@
+use std::net::SocketAddr;
+
+use axum::{
+    extract::{ConnectInfo, Query},
+    response::{ErrorResponse, Html},
+    Extension, Json,
+};
+use hyper::{HeaderMap, StatusCode};
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+
+use super::{client_address, redirect_home, too_many_attempts, Redirect};
+use crate::{
+    errors::Errors, handle_error, models::login_attempts::Subject, notifier::Notification,
+    state::AppState, views,
+};
+
+#[derive(Serialize, Deserialize)]
+pub struct ForgotPasswordForm {
+    pub(crate) username: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ResetPasswordForm {
+    pub(crate) token: String,
+    pub(crate) password: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordQuery {
+    token: Option<String>,
+}
+
+pub async fn get_forgot_password(
+    Extension(app): Extension<AppState>,
+) -> Result<Html<String>, ErrorResponse> {
+    views::forgot_password(app.templates)
+        .map_err(|e| handle_error("Error rendering forgot password page", e))
+}
+
+/// sends a reset link to the account's owner. responds the same whether or not the
+/// account exists, so this can't be used to find out which usernames are taken
+pub async fn forgot_password(
+    Extension(app): Extension<AppState>,
+    connect_info: Option<ConnectInfo<SocketAddr>>,
+    headers: HeaderMap,
+    Json(req): Json<ForgotPasswordForm>,
+) -> Result<(StatusCode, String), ErrorResponse> {
+    // every request can send an email, so each one counts against the username and address,
+    // throttled the same way failed logins are but apart from them
+    let address = client_address(&app, &headers, connect_info);
+    let subjects = [
+        Subject::ResetUsername(&req.username),
+        Subject::ResetAddress(&address),
+    ];
+    let now = app.clock.now();
+    match app.login_attempts.retry_at(&subjects, now).await {
+        Ok(Some(retry_at)) => return Err(too_many_attempts((retry_at - now).num_seconds())),
+        Ok(None) => (),
+        Err(e) => return Err(handle_error("Error checking reset requests", e)),
+    }
+    if let Err(e) = app.login_attempts.record_failure(&subjects, now).await {
+        return Err(handle_error("Error recording reset request", e));
+    }
+
+    match app.users.get_user_id(&req.username).await {
+        Ok(userid) => {
+            let token = app
+                .password_resets
+                .create_reset_token(userid, now, app.password_reset_ttl)
+                .await
+                .map_err(|e| handle_error("Error creating reset token", e))?;
+
+            let mut link = app
+                .public_url
+                .join("/auth/password/reset")
+                .map_err(|e| handle_error("Error building reset link", Errors::UrlError(e)))?;
+            link.query_pairs_mut().append_pair("token", &token);
+
+            let notification = Notification::PasswordReset {
+                username: req.username,
+                link,
+                expires_at: now + app.password_reset_ttl,
+            };
+            if let Err(e) = app.notifier.notify(notification).await {
+                error!("error sending password reset: {:?}", e);
+            }
+        }
+        Err(Errors::DbUserNotFound(username)) => {
+            debug!("password reset requested for unknown user {}", username)
+        }
+        Err(e) => return Err(handle_error("Error finding user", e)),
+    }
+
+    Ok((
+        StatusCode::ACCEPTED,
+        "If that account exists, a reset link is on its way.".to_string(),
+    ))
+}
+
+pub async fn get_reset_password(
+    Extension(app): Extension<AppState>,
+    Query(query): Query<ResetPasswordQuery>,
+) -> Result<Html<String>, ErrorResponse> {
+    views::reset_password(app.templates, query.token.unwrap_or_default())
+        .map_err(|e| handle_error("Error rendering reset password page", e))
+}
+
+/// sets a new password with a reset token, then logs the user out everywhere
+pub async fn reset_password(
+    Extension(app): Extension<AppState>,
+    Json(req): Json<ResetPasswordForm>,
+) -> Result<Redirect, ErrorResponse> {
+    if req.password.is_empty() {
+        return Err(handle_error(
+            "Error resetting password",
+            Errors::InvalidInput("A password is required"),
+        ));
+    }
+
+    if let Err(e) = app
+        .password_resets
+        .reset_password(&app.argon2, &req.token, &req.password, app.clock.now())
+        .await
+    {
+        return Err(handle_error("Error resetting password", e));
+    }
+    Ok(redirect_home())
+}
@@ -0,0 +1,78 @@
+use std::marker::PhantomData;
+
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    response::{IntoResponse, Response},
+    Extension,
+};
+use http::request::Parts;
+use hyper::StatusCode;
+use log::debug;
+
+use crate::{
+    controllers::auth::current_user::CurrentUser, handle_error, models::roles::Role,
+    state::AppState, views,
+};
+
+/// a role a handler can demand with `RequireRole`
+pub trait RequiredRole {
+    const ROLE: Role;
+}
+
+pub struct Steward;
+pub struct Admin;
+
+impl RequiredRole for Steward {
+    const ROLE: Role = Role::Steward;
+}
+
+impl RequiredRole for Admin {
+    const ROLE: Role = Role::Admin;
+}
+
+/// extracts the logged in user if their role is at least `R`, e.g. `RequireRole<Admin>`.
+/// rejects like `CurrentUser` if nobody is logged in, and with a rendered 403 page if they aren't allowed
+pub struct RequireRole<R: RequiredRole> {
+    pub user: CurrentUser,
+    _role: PhantomData<R>,
+}
+
+#[async_trait]
+impl<S, R> FromRequestParts<S> for RequireRole<R>
+where
+    S: Send + Sync,
+    R: RequiredRole,
+{
+    type Rejection = Response;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        let user = CurrentUser::from_request_parts(parts, state).await?;
+        if user.role < R::ROLE {
+            debug!("{} ({}) needs to be {}", user.username, user.role, R::ROLE);
+            let Extension(app) = Extension::<AppState>::from_request_parts(parts, state)
+                .await
+                .map_err(IntoResponse::into_response)?;
+            return Err(forbidden(&app, R::ROLE));
+        }
+        Ok(Self {
+            user,
+            _role: PhantomData,
+        })
+    }
+}
+
+fn forbidden(app: &AppState, needed: Role) -> Response {
+    let message = format!("You need to be {} to do that.", article(needed));
+    match views::error(app.templates.clone(), StatusCode::FORBIDDEN, &message) {
+        Ok(page) => (StatusCode::FORBIDDEN, page).into_response(),
+        Err(e) => handle_error("Error rendering error page", e).into_response(),
+    }
+}
+
+fn article(role: Role) -> String {
+    match role {
+        Role::Admin => format!("an {}", role),
+        _ => format!("a {}", role),
+    }
+}
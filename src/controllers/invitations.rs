@@ -0,0 +1,68 @@
+use axum::{
+    response::{ErrorResponse, Html},
+    Extension, Json,
+};
+use hyper::StatusCode;
+use log::debug;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    controllers::auth::{current_user::CurrentUser, validate_email},
+    errors::Errors,
+    handle_error,
+    notifier::Notification,
+    state::AppState,
+    views,
+};
+
+#[derive(Serialize, Deserialize)]
+pub struct InvitationForm {
+    pub(crate) email: String,
+}
+
+pub async fn get_invitations(
+    Extension(app): Extension<AppState>,
+    _: CurrentUser,
+) -> Result<Html<String>, ErrorResponse> {
+    views::invitations(app.templates)
+        .map_err(|e| handle_error("Error rendering invitations page", e))
+}
+
+/// emails a single-use registration link to `email`, on behalf of the logged in member
+pub async fn create_invitation(
+    Extension(app): Extension<AppState>,
+    user: CurrentUser,
+    Json(req): Json<InvitationForm>,
+) -> Result<(StatusCode, String), ErrorResponse> {
+    let email = req.email.trim();
+    validate_email(email)?;
+
+    let now = app.clock.now();
+    let token = app
+        .invitations
+        .create_invitation(user.userid, email, now, app.invitation_ttl)
+        .await
+        .map_err(|e| handle_error("Error creating invitation", e))?;
+
+    let mut link = app
+        .public_url
+        .join("/auth/register")
+        .map_err(|e| handle_error("Error building invitation link", Errors::UrlError(e)))?;
+    link.query_pairs_mut().append_pair("invite", &token);
+
+    debug!("{} invited {}", user.username, email);
+    let notification = Notification::Invitation {
+        email: email.to_string(),
+        invited_by: user.username,
+        link,
+        expires_at: now + app.invitation_ttl,
+    };
+    if let Err(e) = app.notifier.notify(notification).await {
+        return Err(handle_error("Error sending invitation", e));
+    }
+
+    Ok((
+        StatusCode::ACCEPTED,
+        format!("Invitation sent to {}.", email),
+    ))
+}
@@ -0,0 +1,399 @@
+use axum::{
+    extract::Path,
+    response::{ErrorResponse, Html},
+    Extension, Json,
+};
+use axum_sessions::async_session::chrono;
+use hyper::StatusCode;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    constants::proposals::DEFAULT_VOTING_DAYS,
+    controllers::auth::{
+        current_user::CurrentUser,
+        permissions::{Admin, RequireRole},
+    },
+    errors::Errors,
+    handle_error,
+    models::{
+        proposals::{Proposal, ProposalStatus},
+        roles::Role,
+        votes::{DecisionRule, Threshold, VoteChoice},
+    },
+    state::AppState,
+    views,
+};
+
+#[derive(Serialize, Deserialize)]
+pub struct ProposalForm {
+    pub(crate) title: String,
+    pub(crate) description: String,
+    pub(crate) threshold: Option<Threshold>,
+    // htmx's json-enc sends every form field as a string
+    pub(crate) quorum: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct VoteForm {
+    pub(crate) choice: VoteChoice,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct OpenProposalForm {
+    // htmx's json-enc sends every form field as a string
+    pub(crate) voting_days: Option<String>,
+}
+
+type Redirect = (StatusCode, [(&'static str, String); 1], String);
+
+fn validate_form(form: &ProposalForm) -> Result<DecisionRule, ErrorResponse> {
+    if form.title.trim().is_empty() {
+        return Err(handle_error(
+            "Proposal form error",
+            Errors::InvalidInput("A title is required"),
+        ));
+    }
+
+    let quorum = match form.quorum.as_deref().map(str::trim) {
+        None | Some("") => 0,
+        Some(quorum) => match quorum.parse::<u32>() {
+            Ok(quorum) if quorum <= 100 => quorum,
+            _ => {
+                return Err(handle_error(
+                    "Proposal form error",
+                    Errors::InvalidInput("Quorum must be a percentage between 0 and 100"),
+                ))
+            }
+        },
+    };
+
+    Ok(DecisionRule {
+        threshold: form.threshold.unwrap_or(Threshold::Majority),
+        quorum,
+    })
+}
+
+fn redirect_to(status: StatusCode, id: i64) -> Redirect {
+    let location = format!("/proposals/{}", id);
+    (status, [("HX-Redirect", location.clone())], location)
+}
+
+async fn fetch_proposal(app: &AppState, id: i64) -> Result<Proposal, ErrorResponse> {
+    let proposal = app
+        .proposals
+        .get_proposal(id)
+        .await
+        .map_err(|e| handle_error("Error fetching proposal", e))?;
+
+    // voting may have ended since the background task last ran
+    if proposal.status == ProposalStatus::Open && !proposal.is_open(app.clock.now()) {
+        app.proposals
+            .close_proposal(id)
+            .await
+            .map_err(|e| handle_error("Error closing proposal", e))?;
+        return app
+            .proposals
+            .get_proposal(id)
+            .await
+            .map_err(|e| handle_error("Error fetching proposal", e));
+    }
+    Ok(proposal)
+}
+
+async fn fetch_authored_proposal(
+    app: &AppState,
+    user: &CurrentUser,
+    id: i64,
+) -> Result<Proposal, ErrorResponse> {
+    let proposal = fetch_proposal(app, id).await?;
+    if proposal.author_id != user.userid {
+        return Err(handle_error(
+            "Error changing proposal",
+            Errors::Forbidden("Only the author can change a proposal"),
+        ));
+    }
+    Ok(proposal)
+}
+
+pub async fn list_proposals(
+    Extension(app): Extension<AppState>,
+    user: CurrentUser,
+) -> Result<Html<String>, ErrorResponse> {
+    let proposals = match app.proposals.all_proposals().await {
+        Ok(proposals) => proposals,
+        Err(e) => return Err(handle_error("Error fetching proposals", e)),
+    };
+    views::proposals(app.templates, user.username, proposals)
+        .map_err(|e| handle_error("Error rendering proposals", e))
+}
+
+pub async fn get_proposal(
+    Extension(app): Extension<AppState>,
+    user: CurrentUser,
+    Path(id): Path<i64>,
+) -> Result<Html<String>, ErrorResponse> {
+    let proposal = fetch_proposal(&app, id).await?;
+    let tally = match app
+        .proposals
+        .tally_proposal(proposal.id, &proposal.decision_rule)
+        .await
+    {
+        Ok(tally) => tally,
+        Err(e) => return Err(handle_error("Error tallying votes", e)),
+    };
+    let my_vote = match app.proposals.get_user_vote(proposal.id, user.userid).await {
+        Ok(vote) => vote,
+        Err(e) => return Err(handle_error("Error fetching vote", e)),
+    };
+    let transitions = match app.proposals.get_transitions(proposal.id).await {
+        Ok(transitions) => transitions,
+        Err(e) => return Err(handle_error("Error fetching proposal history", e)),
+    };
+    let can_vote = proposal.is_open(app.clock.now());
+    let is_author = proposal.author_id == user.userid;
+    let can_close = user.role >= Role::Admin;
+    views::proposal(
+        app.templates,
+        proposal,
+        transitions,
+        tally,
+        my_vote,
+        can_vote,
+        is_author,
+        can_close,
+    )
+    .map_err(|e| handle_error("Error rendering proposal", e))
+}
+
+pub async fn create_proposal(
+    Extension(app): Extension<AppState>,
+    user: CurrentUser,
+    Json(req): Json<ProposalForm>,
+) -> Result<Redirect, ErrorResponse> {
+    let rule = validate_form(&req)?;
+
+    match app
+        .proposals
+        .create_proposal(user.userid, &req.title, &req.description, &rule)
+        .await
+    {
+        Ok(id) => Ok(redirect_to(StatusCode::CREATED, id)),
+        Err(e) => Err(handle_error("Error creating proposal", e)),
+    }
+}
+
+pub async fn update_proposal(
+    Extension(app): Extension<AppState>,
+    user: CurrentUser,
+    Path(id): Path<i64>,
+    Json(req): Json<ProposalForm>,
+) -> Result<Redirect, ErrorResponse> {
+    let rule = validate_form(&req)?;
+
+    let proposal = fetch_authored_proposal(&app, &user, id).await?;
+    if proposal.status != ProposalStatus::Draft {
+        return Err(handle_error(
+            "Error updating proposal",
+            Errors::ProposalNotInStatus(id, ProposalStatus::Draft),
+        ));
+    }
+
+    match app
+        .proposals
+        .update_proposal(id, &req.title, &req.description, &rule)
+        .await
+    {
+        Ok(()) => Ok(redirect_to(StatusCode::OK, id)),
+        Err(e) => Err(handle_error("Error updating proposal", e)),
+    }
+}
+
+pub async fn open_proposal(
+    Extension(app): Extension<AppState>,
+    user: CurrentUser,
+    Path(id): Path<i64>,
+    Json(req): Json<OpenProposalForm>,
+) -> Result<Redirect, ErrorResponse> {
+    fetch_authored_proposal(&app, &user, id).await?;
+
+    let voting_days = match req.voting_days.as_deref().map(str::trim) {
+        None | Some("") => DEFAULT_VOTING_DAYS,
+        Some(days) => match days.parse::<i64>() {
+            Ok(days) if days > 0 => days,
+            _ => {
+                return Err(handle_error(
+                    "Error opening proposal",
+                    Errors::InvalidInput("The voting period must be a positive number of days"),
+                ))
+            }
+        },
+    };
+    let closes_at = app.clock.now() + chrono::Duration::days(voting_days);
+
+    match app.proposals.open_proposal(id, closes_at).await {
+        Ok(()) => Ok(redirect_to(StatusCode::OK, id)),
+        Err(e) => Err(handle_error("Error opening proposal", e)),
+    }
+}
+
+/// ends voting before the deadline; only admins may cut a vote short
+pub async fn close_proposal(
+    Extension(app): Extension<AppState>,
+    _: RequireRole<Admin>,
+    Path(id): Path<i64>,
+) -> Result<Redirect, ErrorResponse> {
+    fetch_proposal(&app, id).await?;
+
+    match app.proposals.close_proposal(id).await {
+        Ok(_) => Ok(redirect_to(StatusCode::OK, id)),
+        Err(e) => Err(handle_error("Error closing proposal", e)),
+    }
+}
+
+pub async fn withdraw_proposal(
+    Extension(app): Extension<AppState>,
+    user: CurrentUser,
+    Path(id): Path<i64>,
+) -> Result<Redirect, ErrorResponse> {
+    fetch_authored_proposal(&app, &user, id).await?;
+
+    match app.proposals.withdraw_proposal(id).await {
+        Ok(()) => Ok(redirect_to(StatusCode::OK, id)),
+        Err(e) => Err(handle_error("Error withdrawing proposal", e)),
+    }
+}
+
+pub async fn cast_vote(
+    Extension(app): Extension<AppState>,
+    user: CurrentUser,
+    Path(id): Path<i64>,
+    Json(req): Json<VoteForm>,
+) -> Result<Redirect, ErrorResponse> {
+    let now = app.clock.now();
+    let proposal = fetch_proposal(&app, id).await?;
+    if !proposal.is_open(now) {
+        return Err(handle_error(
+            "Error casting vote",
+            Errors::ProposalNotInStatus(id, ProposalStatus::Open),
+        ));
+    }
+
+    match app
+        .proposals
+        .cast_vote(id, user.userid, req.choice, now)
+        .await
+    {
+        Ok(()) => Ok(redirect_to(StatusCode::OK, id)),
+        Err(e) => Err(handle_error("Error casting vote", e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::response::IntoResponse;
+
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        clock::FakeClock, models::repositories::ProposalRepository, tests::init_memory_state,
+    };
+
+    async fn member(app: &AppState, username: &str) -> CurrentUser {
+        app.users
+            .create_user_with_password(&app.argon2, username, "pw", None)
+            .await
+            .unwrap();
+        let userid = app.users.get_user_id(username).await.unwrap();
+        let user = app.users.get_user(userid).await.unwrap();
+        CurrentUser {
+            userid,
+            username: user.username,
+            role: user.role,
+        }
+    }
+
+    fn form(title: &str) -> Json<ProposalForm> {
+        Json(ProposalForm {
+            title: title.to_string(),
+            description: "description".to_string(),
+            threshold: None,
+            quorum: None,
+        })
+    }
+
+    #[tokio::test]
+    async fn test_proposal_handlers() {
+        let (mut app, repository) = init_memory_state().await.unwrap();
+        let clock = Arc::new(FakeClock::new(chrono::offset::Utc::now()));
+        app.clock = clock.clone();
+        let author = member(&app, "author").await;
+        let voter = member(&app, "voter").await;
+
+        let (status, _, location) =
+            create_proposal(Extension(app.clone()), author.clone(), form("title"))
+                .await
+                .unwrap();
+        assert_eq!(status, StatusCode::CREATED);
+        assert_eq!(location, "/proposals/1");
+        assert!(
+            create_proposal(Extension(app.clone()), author.clone(), form(" "))
+                .await
+                .is_err()
+        );
+
+        // only the author can change their proposal
+        let forbidden = update_proposal(Extension(app.clone()), voter.clone(), Path(1), form("x"))
+            .await
+            .unwrap_err();
+        assert_eq!(forbidden.into_response().status(), StatusCode::FORBIDDEN);
+        let mut edited = form("new title");
+        edited.threshold = Some(Threshold::Consensus);
+        edited.quorum = Some("50".to_string());
+        update_proposal(Extension(app.clone()), author.clone(), Path(1), edited)
+            .await
+            .unwrap();
+        let rule = repository.get_proposal(1).await.unwrap().decision_rule;
+        assert_eq!(rule.threshold, Threshold::Consensus);
+        assert_eq!(rule.quorum, 50);
+
+        // votes can only be cast once it's open
+        let vote = || {
+            Json(VoteForm {
+                choice: VoteChoice::Yes,
+            })
+        };
+        let closed = cast_vote(Extension(app.clone()), voter.clone(), Path(1), vote())
+            .await
+            .unwrap_err();
+        assert_eq!(closed.into_response().status(), StatusCode::CONFLICT);
+        let open = Json(OpenProposalForm { voting_days: None });
+        open_proposal(Extension(app.clone()), author.clone(), Path(1), open)
+            .await
+            .unwrap();
+        cast_vote(Extension(app.clone()), voter.clone(), Path(1), vote())
+            .await
+            .unwrap();
+        assert_eq!(
+            repository.get_user_vote(1, voter.userid).await.unwrap(),
+            Some(VoteChoice::Yes)
+        );
+
+        let Html(page) = get_proposal(Extension(app.clone()), voter.clone(), Path(1))
+            .await
+            .unwrap();
+        assert!(page.contains("new title"));
+        let proposal = repository.get_proposal(1).await.unwrap();
+        assert_eq!(proposal.status, ProposalStatus::Open);
+        assert_eq!(proposal.author_name.as_deref(), Some("author"));
+
+        // once the deadline passes, voting ends and the proposal is resolved
+        clock.advance(chrono::Duration::days(DEFAULT_VOTING_DAYS));
+        let late = cast_vote(Extension(app.clone()), voter, Path(1), vote())
+            .await
+            .unwrap_err();
+        assert_eq!(late.into_response().status(), StatusCode::CONFLICT);
+        let proposal = repository.get_proposal(1).await.unwrap();
+        assert_eq!(proposal.status, ProposalStatus::Passed);
+    }
+}
@@ -1,8 +1,18 @@
 use std::fmt::Display;
 
+use axum::{
+    response::{IntoResponse, Response},
+    Json,
+};
+use hyper::{header, http::HeaderValue, StatusCode};
+use log::{debug, error};
+use serde::Serialize;
+
+use crate::models::proposals::ProposalStatus;
+
 #[derive(Debug)]
 pub enum Errors {
-    DbStoredSaltParsingError(argon2::password_hash::Error),
+    DbStoredHashParsingError(argon2::password_hash::Error),
     DbStoredUuidParsingError(anyhow::Error),
     DbStoredUuidWrongTypeError(),
     DbStoredUsernameWrongTypeError(),
@@ -10,21 +20,479 @@ pub enum Errors {
     UuidParsingError(uuid::Error),
     GetHashError(argon2::password_hash::Error),
     DbFetchError(anyhow::Error),
+    /// a query failed somewhere `?` was used without saying whether it was a fetch or an insert
+    DbError(anyhow::Error),
+    DbMigrationError {
+        id: usize,
+        source: anyhow::Error,
+    },
+    /// migrations the database has had were edited, or are missing from this build
+    DbMigrationDrift {
+        changed: Vec<usize>,
+        unknown: Vec<usize>,
+    },
+    IrreversibleMigration {
+        id: usize,
+        name: &'static str,
+    },
+    MigrationOutOfOrder {
+        position: usize,
+        name: &'static str,
+    },
+    /// how many migrations the database hasn't had yet
+    PendingMigrations(usize),
     DbUserNotFound(String),
     DbNoHashMatch(String),
     DbInsertError(anyhow::Error),
     DbMissingUuid(String),
+    DbProposalNotFound(i64),
+    IllegalProposalTransition {
+        from: ProposalStatus,
+        to: ProposalStatus,
+    },
+    ProposalNotInStatus(i64, ProposalStatus),
+    /// votes kept coming in while the proposal was being closed
+    VotesChanged(i64),
+    ProposalStatusParseError(String),
+    DbStoredProposalParsingError(anyhow::Error),
+    DbStoredKeyParsingError(serde_json::Error),
+    DbNoKeysForUser(String),
+    DbKeyNotFound(String),
+    CannotRemoveLastCredential,
+    UserDisabled(String),
+    InvalidResetToken,
+    InvalidInvitation,
+    NotifierError(std::io::Error),
+    UrlError(url::ParseError),
     DbInitializationError(anyhow::Error),
     LoginErrorUsernameOrPasswordMissing,
     RenderingError(String, tera::Error),
     SessionError(serde_json::Error),
     UserAlreadyExists(String),
     StageParseError,
+    /// every environment variable that was missing or malformed
+    ConfigError(Vec<String>),
+    WebauthnConfigError(String),
+    MailConfigError(String),
+    MailAddressError(lettre::address::AddressError),
+    MailBuildError(lettre::error::Error),
+    SmtpError(lettre::transport::smtp::Error),
+    MailFileError(lettre::transport::file::Error),
+    MailDirError(std::io::Error),
+    UserHasNoEmail(String),
+    ThresholdParseError(String),
+    RoleParseError(String),
+    CannotRemoveLastAdmin,
+    Unauthenticated,
+    /// a form field the client sent is missing or malformed; says which, and how
+    InvalidInput(&'static str),
+    Forbidden(&'static str),
+    NotFound(&'static str),
+    InvitationRequired,
+    TooManyAttempts {
+        retry_after: i64,
+    },
+    VoteChoiceParseError(String),
+    #[cfg(feature = "passkey")]
+    WebauthnError(webauthn_rs::prelude::WebauthnError),
     Default,
 }
 
+/// for logs and operators; `public_message` is what clients see
 impl Display for Errors {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(format!("{:?}", self).as_str())
+        match self {
+            Errors::DbStoredHashParsingError(_) => write!(f, "stored password hash is invalid"),
+            Errors::DbStoredUuidParsingError(_) => write!(f, "error reading a stored uuid"),
+            Errors::DbStoredUuidWrongTypeError() => write!(f, "stored uuid is not text"),
+            Errors::DbStoredUsernameWrongTypeError() => write!(f, "stored username is not text"),
+            Errors::DbFetchLatestMigrationError(_) => {
+                write!(f, "error fetching the latest migration")
+            }
+            Errors::UuidParsingError(_) => write!(f, "error parsing uuid"),
+            Errors::GetHashError(_) => write!(f, "error hashing password"),
+            Errors::DbFetchError(_) => write!(f, "error fetching from the database"),
+            Errors::DbError(_) => write!(f, "database error"),
+            Errors::DbMigrationError { id, .. } => write!(f, "error running migration {}", id),
+            Errors::DbMigrationDrift { changed, unknown } => {
+                write!(f, "the database's migrations don't match this build")?;
+                if !changed.is_empty() {
+                    write!(f, "; {:?} changed after they were applied", changed)?;
+                }
+                if !unknown.is_empty() {
+                    write!(f, "; {:?} were applied but aren't in this build", unknown)?;
+                }
+                write!(f, ". add a new migration instead of editing an old one")
+            }
+            Errors::IrreversibleMigration { id, name } => {
+                write!(f, "migration {} ({}) can't be rolled back", id, name)
+            }
+            Errors::MigrationOutOfOrder { position, name } => write!(
+                f,
+                "migration {} is number {}; migrations must be numbered 1, 2, 3... with no gaps",
+                name, position
+            ),
+            Errors::PendingMigrations(count) => write!(
+                f,
+                "{} migrations haven't been applied; run `lochstep migrate up` first",
+                count
+            ),
+            Errors::DbUserNotFound(username) => write!(f, "user {} not found", username),
+            Errors::DbNoHashMatch(username) => write!(f, "wrong password for {}", username),
+            Errors::DbInsertError(_) => write!(f, "error writing to the database"),
+            Errors::DbMissingUuid(username) => write!(f, "user {} has no id", username),
+            Errors::DbProposalNotFound(id) => write!(f, "proposal {} not found", id),
+            Errors::UserDisabled(username) => write!(f, "user {} is disabled", username),
+            Errors::IllegalProposalTransition { from, to } => {
+                write!(f, "a {} proposal can't become {}", from, to)
+            }
+            Errors::ProposalNotInStatus(id, status) => {
+                write!(f, "proposal {} is not {}", id, status)
+            }
+            Errors::VotesChanged(id) => write!(f, "votes on proposal {} changed while closing", id),
+            Errors::ProposalStatusParseError(s) => write!(f, "unknown proposal status {:?}", s),
+            Errors::DbStoredProposalParsingError(_) => write!(f, "error reading a stored proposal"),
+            Errors::DbStoredKeyParsingError(_) => write!(f, "error reading a stored passkey"),
+            Errors::DbNoKeysForUser(userid) => write!(f, "user {} has no passkeys", userid),
+            Errors::DbKeyNotFound(id) => write!(f, "passkey {} not found", id),
+            Errors::CannotRemoveLastCredential => {
+                write!(f, "can't remove a user's only credential")
+            }
+            Errors::InvalidResetToken => write!(f, "invalid or expired reset token"),
+            Errors::InvalidInvitation => write!(f, "invalid or expired invitation"),
+            Errors::NotifierError(_) => write!(f, "error writing notification"),
+            Errors::UrlError(_) => write!(f, "error building url"),
+            Errors::DbInitializationError(_) => write!(f, "error initializing the database"),
+            Errors::LoginErrorUsernameOrPasswordMissing => {
+                write!(f, "username or password missing")
+            }
+            Errors::RenderingError(template, _) => write!(f, "error rendering {}", template),
+            Errors::SessionError(_) => write!(f, "error reading session"),
+            Errors::UserAlreadyExists(username) => write!(f, "user {} already exists", username),
+            Errors::StageParseError => write!(f, "unknown stage"),
+            Errors::ConfigError(problems) => write!(f, "invalid config: {}", problems.join("; ")),
+            Errors::WebauthnConfigError(reason) => write!(f, "invalid webauthn config: {}", reason),
+            Errors::MailConfigError(reason) => write!(f, "invalid mail config: {}", reason),
+            Errors::MailAddressError(_) => write!(f, "invalid email address"),
+            Errors::MailBuildError(_) => write!(f, "error building email"),
+            Errors::SmtpError(_) => write!(f, "error sending email over smtp"),
+            Errors::MailFileError(_) => write!(f, "error writing email to file"),
+            Errors::MailDirError(_) => write!(f, "error creating mail directory"),
+            Errors::UserHasNoEmail(username) => write!(f, "{} has no email address", username),
+            Errors::ThresholdParseError(s) => write!(f, "unknown threshold {:?}", s),
+            Errors::RoleParseError(s) => write!(f, "unknown role {:?}", s),
+            Errors::CannotRemoveLastAdmin => write!(f, "can't demote the only admin"),
+            Errors::Unauthenticated => write!(f, "not logged in"),
+            Errors::InvalidInput(reason) => write!(f, "invalid input: {}", reason),
+            Errors::Forbidden(reason) => write!(f, "forbidden: {}", reason),
+            Errors::NotFound(reason) => write!(f, "not found: {}", reason),
+            Errors::InvitationRequired => write!(f, "registering without an invitation"),
+            Errors::TooManyAttempts { retry_after } => {
+                write!(f, "too many attempts, retry after {}s", retry_after)
+            }
+            Errors::VoteChoiceParseError(s) => write!(f, "unknown vote choice {:?}", s),
+            #[cfg(feature = "passkey")]
+            Errors::WebauthnError(_) => write!(f, "webauthn error"),
+            Errors::Default => write!(f, "unknown error"),
+        }
+    }
+}
+
+impl std::error::Error for Errors {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Errors::DbStoredHashParsingError(e) | Errors::GetHashError(e) => Some(e),
+            Errors::DbStoredUuidParsingError(e)
+            | Errors::DbFetchLatestMigrationError(e)
+            | Errors::DbFetchError(e)
+            | Errors::DbError(e)
+            | Errors::DbMigrationError { source: e, .. }
+            | Errors::DbInsertError(e)
+            | Errors::DbStoredProposalParsingError(e)
+            | Errors::DbInitializationError(e) => Some(e.as_ref()),
+            Errors::UuidParsingError(e) => Some(e),
+            Errors::DbStoredKeyParsingError(e) | Errors::SessionError(e) => Some(e),
+            Errors::NotifierError(e) | Errors::MailDirError(e) => Some(e),
+            Errors::UrlError(e) => Some(e),
+            Errors::RenderingError(_, e) => Some(e),
+            Errors::MailAddressError(e) => Some(e),
+            Errors::MailBuildError(e) => Some(e),
+            Errors::SmtpError(e) => Some(e),
+            Errors::MailFileError(e) => Some(e),
+            #[cfg(feature = "passkey")]
+            Errors::WebauthnError(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<anyhow::Error> for Errors {
+    fn from(e: anyhow::Error) -> Self {
+        Errors::DbError(e)
+    }
+}
+
+impl From<uuid::Error> for Errors {
+    fn from(e: uuid::Error) -> Self {
+        Errors::UuidParsingError(e)
+    }
+}
+
+impl From<url::ParseError> for Errors {
+    fn from(e: url::ParseError) -> Self {
+        Errors::UrlError(e)
+    }
+}
+
+impl From<lettre::address::AddressError> for Errors {
+    fn from(e: lettre::address::AddressError) -> Self {
+        Errors::MailAddressError(e)
+    }
+}
+
+impl From<lettre::error::Error> for Errors {
+    fn from(e: lettre::error::Error) -> Self {
+        Errors::MailBuildError(e)
+    }
+}
+
+impl From<lettre::transport::smtp::Error> for Errors {
+    fn from(e: lettre::transport::smtp::Error) -> Self {
+        Errors::SmtpError(e)
+    }
+}
+
+impl From<lettre::transport::file::Error> for Errors {
+    fn from(e: lettre::transport::file::Error) -> Self {
+        Errors::MailFileError(e)
+    }
+}
+
+#[cfg(feature = "passkey")]
+impl From<webauthn_rs::prelude::WebauthnError> for Errors {
+    fn from(e: webauthn_rs::prelude::WebauthnError) -> Self {
+        Errors::WebauthnError(e)
+    }
+}
+
+/// what a client is told about an error: a stable code to match on, and a message for people
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorBody {
+    pub code: &'static str,
+    pub message: String,
+}
+
+impl Errors {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Errors::LoginErrorUsernameOrPasswordMissing
+            | Errors::InvalidResetToken
+            | Errors::InvalidInvitation
+            | Errors::ProposalStatusParseError(_)
+            | Errors::ThresholdParseError(_)
+            | Errors::VoteChoiceParseError(_)
+            | Errors::RoleParseError(_)
+            | Errors::MailAddressError(_)
+            | Errors::InvalidInput(_) => StatusCode::BAD_REQUEST,
+            Errors::Unauthenticated | Errors::DbUserNotFound(_) | Errors::DbNoHashMatch(_) => {
+                StatusCode::UNAUTHORIZED
+            }
+            Errors::UserDisabled(_) | Errors::Forbidden(_) | Errors::InvitationRequired => {
+                StatusCode::FORBIDDEN
+            }
+            Errors::DbProposalNotFound(_)
+            | Errors::DbKeyNotFound(_)
+            | Errors::DbNoKeysForUser(_)
+            | Errors::NotFound(_) => StatusCode::NOT_FOUND,
+            Errors::UserAlreadyExists(_)
+            | Errors::IllegalProposalTransition { .. }
+            | Errors::ProposalNotInStatus(..)
+            | Errors::VotesChanged(_)
+            | Errors::CannotRemoveLastCredential
+            | Errors::CannotRemoveLastAdmin
+            | Errors::UserHasNoEmail(_) => StatusCode::CONFLICT,
+            Errors::TooManyAttempts { .. } => StatusCode::TOO_MANY_REQUESTS,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// stable and machine-readable; clients can match on these, so don't rename them
+    pub fn code(&self) -> &'static str {
+        match self {
+            Errors::LoginErrorUsernameOrPasswordMissing => "credentials_missing",
+            Errors::InvalidResetToken => "invalid_reset_token",
+            Errors::InvalidInvitation => "invalid_invitation",
+            Errors::ProposalStatusParseError(_)
+            | Errors::ThresholdParseError(_)
+            | Errors::VoteChoiceParseError(_)
+            | Errors::RoleParseError(_)
+            | Errors::MailAddressError(_)
+            | Errors::InvalidInput(_) => "invalid_input",
+            Errors::Unauthenticated => "unauthenticated",
+            Errors::Forbidden(_) => "forbidden",
+            Errors::NotFound(_) => "not_found",
+            Errors::InvitationRequired => "invitation_required",
+            Errors::TooManyAttempts { .. } => "too_many_attempts",
+            // the same for unknown users and wrong passwords, so usernames can't be discovered
+            Errors::DbUserNotFound(_) | Errors::DbNoHashMatch(_) => "invalid_credentials",
+            Errors::UserDisabled(_) => "account_disabled",
+            Errors::DbProposalNotFound(_) => "proposal_not_found",
+            Errors::DbKeyNotFound(_) | Errors::DbNoKeysForUser(_) => "passkey_not_found",
+            Errors::UserAlreadyExists(_) => "username_taken",
+            Errors::IllegalProposalTransition { .. } | Errors::ProposalNotInStatus(..) => {
+                "illegal_transition"
+            }
+            Errors::VotesChanged(_) => "votes_changed",
+            Errors::CannotRemoveLastCredential => "last_credential",
+            Errors::CannotRemoveLastAdmin => "last_admin",
+            Errors::UserHasNoEmail(_) => "no_email",
+            _ => "internal_error",
+        }
+    }
+
+    /// safe to show anyone; only includes details the client sent or is allowed to see
+    pub fn public_message(&self) -> String {
+        match self {
+            Errors::LoginErrorUsernameOrPasswordMissing => {
+                "Username and password are required".to_string()
+            }
+            Errors::InvalidResetToken => "This reset link is invalid or has expired".to_string(),
+            Errors::InvalidInvitation => "This invitation is invalid or has expired".to_string(),
+            Errors::ProposalStatusParseError(s)
+            | Errors::ThresholdParseError(s)
+            | Errors::VoteChoiceParseError(s)
+            | Errors::RoleParseError(s) => format!("{:?} isn't a valid choice", s),
+            Errors::MailAddressError(_) => "That email address isn't valid".to_string(),
+            Errors::Unauthenticated => "You must be logged in to do that.".to_string(),
+            Errors::InvalidInput(message)
+            | Errors::Forbidden(message)
+            | Errors::NotFound(message) => message.to_string(),
+            Errors::InvitationRequired => "Membership is by invitation only".to_string(),
+            Errors::TooManyAttempts { .. } => {
+                "Too many attempts, please try again later".to_string()
+            }
+            Errors::DbUserNotFound(_) | Errors::DbNoHashMatch(_) => {
+                "Invalid username or password".to_string()
+            }
+            Errors::UserDisabled(_) => "This account has been disabled".to_string(),
+            Errors::DbProposalNotFound(id) => format!("Proposal {} not found", id),
+            Errors::DbKeyNotFound(_) | Errors::DbNoKeysForUser(_) => {
+                "Passkey not found".to_string()
+            }
+            Errors::UserAlreadyExists(username) => format!("{} is already taken", username),
+            Errors::IllegalProposalTransition { from, to } => {
+                format!("A {} proposal can't become {}", from, to)
+            }
+            Errors::ProposalNotInStatus(id, status) => {
+                format!("Proposal {} is not {}", id, status)
+            }
+            Errors::VotesChanged(id) => {
+                format!(
+                    "Votes on proposal {} are still coming in, please try again",
+                    id
+                )
+            }
+            Errors::CannotRemoveLastCredential => {
+                "You can't remove your only passkey without setting a password first".to_string()
+            }
+            Errors::CannotRemoveLastAdmin => "Make someone else an admin first".to_string(),
+            Errors::UserHasNoEmail(_) => "There's no email address on file".to_string(),
+            _ => "Something went wrong, please try again later".to_string(),
+        }
+    }
+
+    pub fn body(&self) -> ErrorBody {
+        ErrorBody {
+            code: self.code(),
+            message: self.public_message(),
+        }
+    }
+}
+
+/// responds with the error's status and a json `ErrorBody`, logging the details instead of
+/// sending them. the body is also attached as an extension, so `routes::render_errors`
+/// can swap it for html when htmx made the request
+impl IntoResponse for Errors {
+    fn into_response(self) -> Response {
+        self.log(None);
+        self.respond()
+    }
+}
+
+impl Errors {
+    /// logs the full error, with `context` about what was being done if there is any
+    pub fn log(&self, context: Option<&str>) {
+        let context = context.map(|c| format!("{}: ", c)).unwrap_or_default();
+        if self.status().is_server_error() {
+            error!("{}{:?}", context, self);
+        } else {
+            debug!("{}{:?}", context, self);
+        }
+    }
+
+    /// the response, without logging
+    pub fn respond(&self) -> Response {
+        let body = self.body();
+        let mut response = (self.status(), Json(body.clone())).into_response();
+        if let Errors::TooManyAttempts { retry_after } = self {
+            response
+                .headers_mut()
+                .insert(header::RETRY_AFTER, HeaderValue::from(*retry_after));
+        }
+        response.extensions_mut().insert(body);
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_bodies() {
+        // internals are logged, never sent
+        let internal = Errors::DbFetchError(anyhow::anyhow!("no such table: users"));
+        assert_eq!(internal.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(internal.code(), "internal_error");
+        assert!(!internal.public_message().contains("users"));
+
+        // unknown users and wrong passwords look exactly alike
+        let unknown = Errors::DbUserNotFound("alice".to_string());
+        let wrong = Errors::DbNoHashMatch("bob".to_string());
+        assert_eq!(unknown.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(unknown.status(), wrong.status());
+        assert_eq!(
+            serde_json::to_string(&unknown.body()).unwrap(),
+            serde_json::to_string(&wrong.body()).unwrap()
+        );
+
+        assert_eq!(
+            Errors::DbProposalNotFound(1).status(),
+            StatusCode::NOT_FOUND
+        );
+        assert_eq!(
+            Errors::UserAlreadyExists("alice".to_string()).status(),
+            StatusCode::CONFLICT
+        );
+        assert_eq!(Errors::InvalidResetToken.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn test_error_sources() {
+        use std::error::Error;
+
+        let e: Errors = uuid::Uuid::parse_str("not a uuid").unwrap_err().into();
+        assert!(matches!(e, Errors::UuidParsingError(_)));
+        assert_eq!(e.to_string(), "error parsing uuid");
+        assert!(e.source().is_some());
+
+        let e = Errors::DbMigrationError {
+            id: 3,
+            source: anyhow::anyhow!("near \"(\": syntax error"),
+        };
+        assert_eq!(e.to_string(), "error running migration 3");
+        assert!(e.source().unwrap().to_string().contains("syntax error"));
+
+        assert!(Errors::InvalidInvitation.source().is_none());
     }
 }
@@ -0,0 +1,264 @@
+use std::sync::Arc;
+
+use axum_sessions::async_session::{
+    async_trait,
+    chrono::{DateTime, Duration, Utc},
+};
+use lettre::{
+    message::{Mailbox, MultiPart},
+    Message,
+};
+use log::{error, info, warn};
+use serde::Serialize;
+use tera::{Context, Tera};
+
+use crate::{
+    config::{Config, Stage},
+    constants::mail::{MAX_ATTEMPTS, RETRY_BASE_SECS},
+    errors::Errors,
+    models::repositories::OutboxRepository,
+};
+
+mod file;
+mod smtp;
+
+pub use file::FileMailer;
+pub use smtp::SmtpMailer;
+
+/// a rendered email, ready to queue or send
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub text: String,
+    pub html: String,
+}
+
+impl Email {
+    /// renders `emails/{template}.txt` and `emails/{template}.html` with the same context
+    pub fn render<T: Serialize>(
+        templates: &Tera,
+        template: &str,
+        to: String,
+        subject: String,
+        ctx: &T,
+    ) -> Result<Self, Errors> {
+        let ctx = Context::from_serialize(ctx)
+            .map_err(|e| Errors::RenderingError(template.to_string(), e))?;
+        let render = |ext: &str| {
+            let name = format!("emails/{}.{}", template, ext);
+            templates
+                .render(&name, &ctx)
+                .map_err(|e| Errors::RenderingError(name, e))
+        };
+        Ok(Self {
+            to,
+            subject,
+            text: render("txt")?,
+            html: render("html")?,
+        })
+    }
+
+    fn to_message(&self, from: &Mailbox) -> Result<Message, Errors> {
+        Message::builder()
+            .from(from.clone())
+            .to(self.to.parse()?)
+            .subject(&self.subject)
+            .multipart(MultiPart::alternative_plain_html(
+                self.text.clone(),
+                self.html.clone(),
+            ))
+            .map_err(Errors::MailBuildError)
+    }
+}
+
+/// delivers an email immediately; callers should usually queue it in the outbox instead
+#[async_trait]
+pub trait Mailer: Send + Sync {
+    async fn send(&self, email: &Email) -> Result<(), Errors>;
+}
+
+/// smtp in prod, and files on disk for local development and tests
+pub fn init_mailer(config: &Config) -> Result<Arc<dyn Mailer>, Errors> {
+    let from: Mailbox = config.mail.from.parse()?;
+    match config.stage {
+        Stage::Prod => Ok(Arc::new(SmtpMailer::new(&config.mail, from)?)),
+        Stage::Local | Stage::Test => Ok(Arc::new(FileMailer::new(&config.mail.dir, from)?)),
+    }
+}
+
+/// how long to wait before retrying an email that has failed `attempts` times
+fn retry_delay(attempts: u32) -> Duration {
+    Duration::seconds(RETRY_BASE_SECS.saturating_mul(1 << (attempts.max(1) - 1).min(16)))
+}
+
+/// sends every queued email that's due, rescheduling failures with backoff until
+/// `MAX_ATTEMPTS`, and returns how many were sent. each email is claimed before it's sent,
+/// so replicas flushing the same outbox don't send it twice
+pub async fn flush_outbox(
+    outbox: &dyn OutboxRepository,
+    mailer: &dyn Mailer,
+    now: DateTime<Utc>,
+) -> Result<usize, Errors> {
+    let mut sent = 0;
+    for due in outbox.due(now).await? {
+        let Some(queued) = outbox.claim(due.id, now).await? else {
+            continue;
+        };
+        match mailer.send(&queued.email).await {
+            Ok(()) => {
+                outbox.mark_sent(queued.id, now).await?;
+                sent += 1;
+            }
+            Err(e) => {
+                let attempts = queued.attempts + 1;
+                let retry_at = if attempts < MAX_ATTEMPTS {
+                    warn!("error sending email {}, will retry: {:?}", queued.id, e);
+                    Some(now + retry_delay(attempts))
+                } else {
+                    error!(
+                        "giving up on email {} to {}: {}",
+                        queued.id, queued.email.to, e
+                    );
+                    None
+                };
+                outbox
+                    .mark_failed(queued.id, attempts, &e.to_string(), retry_at)
+                    .await?;
+            }
+        }
+    }
+    if sent > 0 {
+        info!("sent {} queued emails", sent);
+    }
+    Ok(sent)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::{constants::mail::CLAIM_TIMEOUT_SECS, models::repositories::LibsqlRepository};
+
+    /// fails the first `failures` sends, then records the rest
+    struct FlakyMailer {
+        failures: Mutex<u32>,
+        sent: Mutex<Vec<Email>>,
+    }
+
+    #[async_trait]
+    impl Mailer for FlakyMailer {
+        async fn send(&self, email: &Email) -> Result<(), Errors> {
+            let mut failures = self.failures.lock().unwrap();
+            if *failures > 0 {
+                *failures -= 1;
+                return Err(Errors::MailDirError(
+                    std::io::ErrorKind::ConnectionRefused.into(),
+                ));
+            }
+            self.sent.lock().unwrap().push(email.clone());
+            Ok(())
+        }
+    }
+
+    fn email() -> Email {
+        Email {
+            to: "test@example.com".to_string(),
+            subject: "hello".to_string(),
+            text: "hello".to_string(),
+            html: "<p>hello</p>".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_render() {
+        let templates = Tera::new("src/ui/templates/**/*.{html,txt}").unwrap();
+        let email = Email::render(
+            &templates,
+            "password_reset",
+            "test@example.com".to_string(),
+            "Reset your password".to_string(),
+            &serde_json::json!({
+                "username": "<test>",
+                "link": "http://localhost:8080/auth/password/reset?token=abc",
+                "expires_at": "soon",
+            }),
+        )
+        .unwrap();
+        assert!(email.text.contains("<test>"));
+        assert!(email.html.contains("&lt;test&gt;"));
+        assert!(email.html.contains("token=abc"));
+    }
+
+    #[tokio::test]
+    async fn test_outbox_retries() {
+        let client = Arc::new(libsql_client::Client::in_memory().unwrap());
+        crate::models::init_db(&client).await.unwrap();
+        let outbox = LibsqlRepository::new(client);
+        let mailer = FlakyMailer {
+            failures: Mutex::new(1),
+            sent: Mutex::new(vec![]),
+        };
+        let now = Utc::now();
+        outbox.enqueue(&email(), now).await.unwrap();
+
+        // a failed send waits out its backoff before being retried
+        assert_eq!(flush_outbox(&outbox, &mailer, now).await.unwrap(), 0);
+        assert_eq!(flush_outbox(&outbox, &mailer, now).await.unwrap(), 0);
+        let later = now + retry_delay(1);
+        assert_eq!(flush_outbox(&outbox, &mailer, later).await.unwrap(), 1);
+        assert_eq!(*mailer.sent.lock().unwrap(), vec![email()]);
+
+        // and sent mail isn't sent again
+        assert_eq!(flush_outbox(&outbox, &mailer, later).await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_outbox_claims() {
+        let client = Arc::new(libsql_client::Client::in_memory().unwrap());
+        crate::models::init_db(&client).await.unwrap();
+        let outbox = LibsqlRepository::new(client);
+        let mailer = FlakyMailer {
+            failures: Mutex::new(0),
+            sent: Mutex::new(vec![]),
+        };
+        let now = Utc::now();
+        outbox.enqueue(&email(), now).await.unwrap();
+        let id = outbox.due(now).await.unwrap()[0].id;
+
+        // an email another worker is sending is left to it, and can only be claimed once
+        assert!(outbox.claim(id, now).await.unwrap().is_some());
+        assert!(outbox.claim(id, now).await.unwrap().is_none());
+        assert_eq!(flush_outbox(&outbox, &mailer, now).await.unwrap(), 0);
+
+        // unless it never reports back
+        let later = now + Duration::seconds(CLAIM_TIMEOUT_SECS);
+        assert_eq!(flush_outbox(&outbox, &mailer, later).await.unwrap(), 1);
+        assert_eq!(*mailer.sent.lock().unwrap(), vec![email()]);
+        assert!(outbox.claim(id, later).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_outbox_gives_up() {
+        let client = Arc::new(libsql_client::Client::in_memory().unwrap());
+        crate::models::init_db(&client).await.unwrap();
+        let outbox = LibsqlRepository::new(client);
+        let mailer = FlakyMailer {
+            failures: Mutex::new(MAX_ATTEMPTS),
+            sent: Mutex::new(vec![]),
+        };
+        let mut now = Utc::now();
+        outbox.enqueue(&email(), now).await.unwrap();
+
+        for attempts in 1..=MAX_ATTEMPTS {
+            assert_eq!(flush_outbox(&outbox, &mailer, now).await.unwrap(), 0);
+            now = now + retry_delay(attempts);
+        }
+        assert!(outbox
+            .due(now + Duration::days(365))
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
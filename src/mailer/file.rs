@@ -0,0 +1,63 @@
+use std::path::Path;
+
+use axum_sessions::async_session::async_trait;
+use lettre::{message::Mailbox, AsyncFileTransport, AsyncTransport, Tokio1Executor};
+
+use super::{Email, Mailer};
+use crate::errors::Errors;
+
+/// drops each email into a directory as an `.eml` file, for local development and tests
+pub struct FileMailer {
+    transport: AsyncFileTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl FileMailer {
+    pub fn new(dir: &Path, from: Mailbox) -> Result<Self, Errors> {
+        std::fs::create_dir_all(dir).map_err(Errors::MailDirError)?;
+        Ok(Self {
+            transport: AsyncFileTransport::new(dir),
+            from,
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for FileMailer {
+    async fn send(&self, email: &Email) -> Result<(), Errors> {
+        let message = email.to_message(&self.from)?;
+        self.transport
+            .send(message)
+            .await
+            .map(|_| ())
+            .map_err(Errors::MailFileError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_file_mailer() {
+        let dir = std::env::temp_dir().join(format!("lochstep-mail-{}", uuid::Uuid::new_v4()));
+        let mailer =
+            FileMailer::new(&dir, "Lochstep <noreply@localhost>".parse().unwrap()).unwrap();
+        mailer
+            .send(&Email {
+                to: "test@example.com".to_string(),
+                subject: "hello".to_string(),
+                text: "plain hello".to_string(),
+                html: "<p>html hello</p>".to_string(),
+            })
+            .await
+            .unwrap();
+
+        let files: Vec<_> = std::fs::read_dir(&dir).unwrap().collect();
+        assert_eq!(files.len(), 1);
+        let message = std::fs::read_to_string(files[0].as_ref().unwrap().path()).unwrap();
+        assert!(message.contains("To: test@example.com"));
+        assert!(message.contains("plain hello"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,129 @@
+use axum_sessions::async_session::async_trait;
+use lettre::{
+    message::Mailbox, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport, Tokio1Executor,
+};
+
+use super::{Email, Mailer};
+use crate::{config::MailConfig, errors::Errors};
+
+pub struct SmtpMailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+}
+
+impl SmtpMailer {
+    pub fn new(config: &MailConfig, from: Mailbox) -> Result<Self, Errors> {
+        let host = config
+            .smtp_host
+            .as_deref()
+            .ok_or(Errors::MailConfigError("SMTP_HOST is not set".to_string()))?;
+        let builder = match config.smtp_starttls {
+            true => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)?,
+            false => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+        }
+        .port(config.smtp_port);
+        let builder = match (&config.smtp_username, &config.smtp_password) {
+            (Some(username), Some(password)) => {
+                builder.credentials(Credentials::new(username.clone(), password.clone()))
+            }
+            _ => builder,
+        };
+
+        Ok(Self {
+            transport: builder.build(),
+            from,
+        })
+    }
+}
+
+#[async_trait]
+impl Mailer for SmtpMailer {
+    async fn send(&self, email: &Email) -> Result<(), Errors> {
+        let message = email.to_message(&self.from)?;
+        self.transport
+            .send(message)
+            .await
+            .map(|_| ())
+            .map_err(Errors::SmtpError)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+
+    use super::*;
+
+    /// accepts one connection and speaks just enough smtp to take a message,
+    /// returning everything sent after DATA
+    async fn smtp_stand_in(listener: TcpListener) -> String {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        writer.write_all(b"220 localhost ready\r\n").await.unwrap();
+
+        let mut data = String::new();
+        let mut in_data = false;
+        while let Some(line) = lines.next_line().await.unwrap() {
+            if in_data {
+                if line == "." {
+                    in_data = false;
+                    writer.write_all(b"250 queued\r\n").await.unwrap();
+                } else {
+                    data.push_str(&line);
+                    data.push('\n');
+                }
+                continue;
+            }
+            let reply: &[u8] = match line.split_whitespace().next().unwrap_or_default() {
+                "EHLO" | "HELO" => b"250 localhost\r\n",
+                "DATA" => {
+                    in_data = true;
+                    b"354 go ahead\r\n"
+                }
+                "QUIT" => {
+                    writer.write_all(b"221 bye\r\n").await.unwrap();
+                    break;
+                }
+                _ => b"250 ok\r\n",
+            };
+            writer.write_all(reply).await.unwrap();
+        }
+        data
+    }
+
+    #[tokio::test]
+    async fn test_smtp_mailer() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = tokio::spawn(smtp_stand_in(listener));
+
+        let config = MailConfig {
+            from: "Lochstep <noreply@localhost>".to_string(),
+            smtp_host: Some("127.0.0.1".to_string()),
+            smtp_port: port,
+            smtp_username: None,
+            smtp_password: None,
+            smtp_starttls: false,
+            dir: "mail".into(),
+        };
+        let mailer = SmtpMailer::new(&config, config.from.parse().unwrap()).unwrap();
+        mailer
+            .send(&Email {
+                to: "test@example.com".to_string(),
+                subject: "hello".to_string(),
+                text: "plain hello".to_string(),
+                html: "<p>html hello</p>".to_string(),
+            })
+            .await
+            .unwrap();
+
+        let data = server.await.unwrap();
+        assert!(data.contains("Subject: hello"));
+        assert!(data.contains("plain hello"));
+    }
+}
@@ -1,28 +1,43 @@
 use axum::{response::ErrorResponse, Extension, Router};
-use axum_sessions::{async_session::MemoryStore, SameSite, SessionLayer};
+use axum_sessions::{async_session::chrono::Utc, SameSite, SessionLayer};
+use cli::Command;
 use errors::Errors;
-use hyper::StatusCode;
 use log::{error, info};
-use rand::prelude::*;
 use simple_logger::SimpleLogger;
 use state::AppState;
-use std::path::{Path, PathBuf};
+use std::{
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
 use tera::Tera;
 use tower_http::services::ServeDir;
 
 use crate::{
     config::{Config, Stage},
+    constants::{
+        mail::OUTBOX_INTERVAL_SECS, proposals::CLOSE_EXPIRED_INTERVAL_SECS,
+        sessions::CLEANUP_INTERVAL_SECS,
+    },
+    mailer::Mailer,
+    models::{
+        passwords,
+        repositories::{LibsqlRepository, OutboxRepository, ProposalRepository},
+        sessions::{DynSessionStore, LibsqlSessionStore},
+    },
     state::get_app_port,
 };
 
-#[cfg(passkey)]
-use crate::state::init_webauthn;
-
+mod cli;
+mod clock;
 mod config;
 mod constants;
 mod controllers;
 mod errors;
+mod mailer;
 mod models;
+mod notifier;
 mod routes;
 mod state;
 mod views;
@@ -34,9 +49,55 @@ type Error = Box<dyn std::error::Error>;
 
 #[tokio::main]
 async fn main() -> Result<(), Error> {
-    let config: Config = Config::from_env();
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let command = match Command::parse(&args) {
+        Ok(command) => command,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    // before reading the environment, so it works without one
+    if command == Command::Help {
+        println!("{}", cli::USAGE);
+        return Ok(());
+    }
+    // it reads the environment itself, so it can report everything wrong with it
+    if command == Command::CheckConfig {
+        match cli::check_config().await {
+            Ok(output) => {
+                println!("{}", output);
+                return Ok(());
+            }
+            Err(problems) => {
+                eprintln!("{}", problems.join("\n"));
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let config: Config = Config::from_env()?;
     init_logger(&config).expect("error initializing logger");
+    config.validate()?;
 
+    let db_client = init_db_client(&config).await?;
+
+    let output = match command {
+        Command::Serve => return serve_app(config, db_client).await,
+        Command::Migrate(command) => cli::migrate(&db_client, command).await?,
+        Command::User(command) => {
+            let argon2 = passwords::hasher(config.argon2_params.clone());
+            cli::user(&db_client, &argon2, command).await?
+        }
+        Command::Help | Command::CheckConfig => {
+            unreachable!("handled before reading the environment")
+        }
+    };
+    println!("{}", output);
+    Ok(())
+}
+
+async fn serve_app(config: Config, db_client: libsql_client::Client) -> Result<(), Error> {
     let ui_dir = Path::new("src").join("ui");
     info!("ui dir exists? {}", ui_dir.exists());
 
@@ -47,20 +108,30 @@ async fn main() -> Result<(), Error> {
     };
     let static_dir: PathBuf = ui_dir.join("static");
 
-    let db_client = init_db_client(&config)
-        .await
-        .expect("error initializing db client");
-
-    models::init_db(&db_client).await.unwrap();
+    if let Err(e) = models::init_db(&db_client).await {
+        error!("refusing to start: {}", e);
+        return Err(e);
+    }
 
-    let state: AppState = AppState::new(db_client, templates);
+    let db = Arc::new(db_client);
+    let session_store = LibsqlSessionStore::new(db.clone());
+    let state: AppState = AppState::new(
+        &config,
+        templates,
+        DynSessionStore::new(session_store.clone()),
+        Arc::new(LibsqlRepository::new(db)),
+    )?;
     info!("done intializing appstate");
 
+    spawn_proposal_closer(state.proposals.clone());
+    spawn_outbox_worker(state.outbox.clone(), state.mailer.clone());
+    spawn_session_cleanup(session_store);
+
     let router = routes::router::init()
         .await
         .expect("error initializing router")
         .nest_service("/static", ServeDir::new(static_dir))
-        .layer(init_session_layer(&config))
+        .layer(init_session_layer(&config, state.sessions.clone()))
         .layer(Extension(state));
 
     let port = get_app_port();
@@ -70,23 +141,60 @@ async fn main() -> Result<(), Error> {
     Ok(())
 }
 
-fn init_session_layer(config: &Config) -> SessionLayer<MemoryStore> {
-    info!("initializing session memorystore");
-    let store = MemoryStore::new();
-    let secret1 = thread_rng().gen::<[u8; 32]>(); // MUST be at least 64 bytes!
-    let secret2 = thread_rng().gen::<[u8; 32]>(); // MUST be at least 64 bytes!
-    let secret = [secret1, secret2].concat();
-
-    SessionLayer::new(store, &secret)
+fn init_session_layer(config: &Config, store: DynSessionStore) -> SessionLayer<DynSessionStore> {
+    info!("initializing session store");
+    SessionLayer::new(store, config.session_secret.as_bytes())
         .with_cookie_name("sid")
         .with_same_site_policy(SameSite::Lax)
         .with_secure(config.stage == Stage::Prod)
 }
 
+/// periodically closes proposals whose voting deadline has passed
+fn spawn_proposal_closer(proposals: Arc<dyn ProposalRepository>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(CLOSE_EXPIRED_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+            if let Err(e) = proposals.close_expired_proposals(Utc::now()).await {
+                error!("error closing expired proposals: {:?}", e);
+            }
+        }
+    });
+}
+
+/// periodically sends queued emails, including ones left over from before a restart
+fn spawn_outbox_worker(outbox: Arc<dyn OutboxRepository>, mailer: Arc<dyn Mailer>) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(OUTBOX_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+            if let Err(e) = mailer::flush_outbox(outbox.as_ref(), mailer.as_ref(), Utc::now()).await
+            {
+                error!("error flushing outbox: {:?}", e);
+            }
+        }
+    });
+}
+
+/// periodically deletes expired sessions from the session store
+fn spawn_session_cleanup(store: LibsqlSessionStore) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(CLEANUP_INTERVAL_SECS));
+        loop {
+            interval.tick().await;
+            match store.cleanup().await {
+                Ok(removed) => info!("removed {} expired sessions", removed),
+                Err(e) => error!("error cleaning up expired sessions: {}", e),
+            }
+        }
+    });
+}
+
 fn init_templates(ui_dir: &Path) -> Result<Tera, Error> {
     info!("initializing templates...");
     let templates_dir = ui_dir.join("templates");
-    let templates_pattern = format!("{}/**/*.html", templates_dir.display());
+    // plaintext email bodies are .txt, so they aren't html-escaped
+    let templates_pattern = format!("{}/**/*.{{html,txt}}", templates_dir.display());
     let mut templates =
         Tera::parse(templates_pattern.as_str()).expect("Error parsing templates directory");
     templates
@@ -100,7 +208,10 @@ async fn init_db_client(config: &Config) -> Result<libsql_client::Client, Error>
     let db_url: &str = config.db_url.as_str();
     let auth_token: Option<String> = config.db_token.clone();
 
-    let url = url::Url::parse(db_url).expect("error parsing turso db url");
+    let url = match url::Url::parse(db_url) {
+        Ok(url) => url,
+        Err(e) => return Err(format!("error parsing DB_URL {:?}: {}", db_url, e).into()),
+    };
     let config = libsql_client::Config { url, auth_token };
 
     match libsql_client::Client::from_config(config).await {
@@ -121,7 +232,7 @@ fn init_logger(config: &Config) -> Result<(), log::SetLoggerError> {
 async fn serve(router: Router, port: String) -> Result<(), Error> {
     info!("router initialized, listening on :{}", port);
     match axum::Server::bind(&format!("0.0.0.0:{}", port).parse().unwrap())
-        .serve(router.into_make_service())
+        .serve(router.into_make_service_with_connect_info::<SocketAddr>())
         .await
     {
         Ok(_) => Ok(()),
@@ -129,10 +240,10 @@ async fn serve(router: Router, port: String) -> Result<(), Error> {
     }
 }
 
+/// logs `e` with `err_msg` for context, and responds with only what's safe for the client to see
 pub fn handle_error(err_msg: &str, e: Errors) -> ErrorResponse {
-    let err_msg = format!("{}: {}", err_msg, e);
-    error!("{}", err_msg);
-    (StatusCode::INTERNAL_SERVER_ERROR, err_msg).into()
+    e.log(Some(err_msg));
+    e.respond().into()
 }
 
 // #[shuttle_runtime::main]
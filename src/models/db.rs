@@ -1,5 +1,122 @@
-// migrations, vec of (id, stmt) tuples
-// on app load, db.select latest migration
-// get migrations_to_do slice starting at latest_migration + 1
-// for each stmt, execute
-// update migrations table with latest migration
+use libsql_client::{Client, ResultSet, Row, Statement, Value};
+use log::debug;
+
+use crate::errors::Errors;
+
+/// a statement and its bound parameters; the only way models talk to the database.
+/// sql has to be a `&'static str`, so values can't be spliced into it and must be bound instead
+#[derive(Debug, Clone)]
+pub struct Query {
+    sql: &'static str,
+    params: Vec<Value>,
+}
+
+impl Query {
+    pub fn new(sql: &'static str) -> Self {
+        Self {
+            sql,
+            params: vec![],
+        }
+    }
+
+    /// binds the next `?` in the statement
+    pub fn bind(mut self, value: impl Into<Value>) -> Self {
+        self.params.push(value.into());
+        self
+    }
+
+    /// binds the next `?` to `value`, or to NULL if there isn't one
+    pub fn bind_opt(self, value: Option<impl Into<Value>>) -> Self {
+        match value {
+            Some(value) => self.bind(value),
+            None => self.bind(Value::Null),
+        }
+    }
+
+    fn statement(self) -> Statement {
+        let stmt = Statement::with_args(self.sql, &self.params);
+        debug!("stmt: {}", stmt);
+        stmt
+    }
+
+    /// every row the query returns
+    pub async fn fetch(self, db: &Client) -> Result<Vec<Row>, Errors> {
+        let rs = db
+            .execute(self.statement())
+            .await
+            .map_err(Errors::DbFetchError)?;
+        Ok(rs.rows)
+    }
+
+    /// the first row the query returns, if there is one
+    pub async fn fetch_one(self, db: &Client) -> Result<Option<Row>, Errors> {
+        Ok(self.fetch(db).await?.into_iter().next())
+    }
+
+    /// runs a write, returning how many rows it touched and any rows it RETURNs
+    pub async fn execute(self, db: &Client) -> Result<ResultSet, Errors> {
+        db.execute(self.statement())
+            .await
+            .map_err(Errors::DbInsertError)
+    }
+}
+
+/// runs `queries` in order, in one transaction, returning each one's result
+pub async fn batch(
+    db: &Client,
+    queries: impl IntoIterator<Item = Query>,
+) -> Result<Vec<ResultSet>, Errors> {
+    let stmts: Vec<Statement> = queries.into_iter().map(Query::statement).collect();
+    db.batch(stmts).await.map_err(Errors::DbInsertError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_bound_parameters() {
+        let client = Client::in_memory().unwrap();
+        Query::new("CREATE TABLE t (name TEXT, n INT);")
+            .execute(&client)
+            .await
+            .unwrap();
+
+        // values are never interpreted as sql, however they're quoted
+        let names = ["plain", "o'brien", "\"double\"", "x'); DROP TABLE t; --"];
+        batch(
+            &client,
+            names.iter().map(|name| {
+                Query::new("INSERT INTO t (name, n) VALUES (?, ?);")
+                    .bind(*name)
+                    .bind(1_i64)
+            }),
+        )
+        .await
+        .unwrap();
+        Query::new("INSERT INTO t (name, n) VALUES (?, ?);")
+            .bind("nothing")
+            .bind_opt(None::<i64>)
+            .execute(&client)
+            .await
+            .unwrap();
+
+        let rows = Query::new("SELECT name FROM t WHERE n = ? ORDER BY rowid;")
+            .bind(1_i64)
+            .fetch(&client)
+            .await
+            .unwrap();
+        let stored: Vec<&str> = rows
+            .iter()
+            .map(|row| row.try_column("name").unwrap())
+            .collect();
+        assert_eq!(stored, names);
+
+        let row = Query::new("SELECT COUNT(*) AS c FROM t WHERE n IS NULL;")
+            .fetch_one(&client)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(row.try_column::<i64>("c").unwrap(), 1);
+    }
+}
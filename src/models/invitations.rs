@@ -0,0 +1,147 @@
+use axum_sessions::async_session::chrono::{DateTime, Duration, Utc};
+use libsql_client::{Client, Row};
+use uuid::Uuid;
+
+use super::{
+    db::{batch, Query},
+    tokens::{generate_token, hash_token},
+};
+use crate::errors::Errors;
+
+/// invites `email` to register, valid until `now + ttl`, replacing any unused invitations for it.
+/// returns the plaintext token, which is never stored.
+pub async fn create_invitation(
+    db: &Client,
+    invited_by: Uuid,
+    email: &str,
+    now: DateTime<Utc>,
+    ttl: Duration,
+) -> Result<String, Errors> {
+    let token = generate_token();
+    batch(
+        db,
+        [
+            Query::new("DELETE FROM invitations WHERE email = ? AND usedAt IS NULL;").bind(email),
+            Query::new(
+                "INSERT INTO invitations (tokenHash, email, invitedBy, createdAt, expiresAt)
+                    VALUES (?, ?, ?, ?, ?);",
+            )
+            .bind(hash_token(&token))
+            .bind(email)
+            .bind(invited_by.urn().to_string())
+            .bind(now.to_rfc3339())
+            .bind((now + ttl).timestamp()),
+        ],
+    )
+    .await?;
+    Ok(token)
+}
+
+/// the email an unused, unexpired invitation was sent to, without using it up
+pub async fn get_invitation_email(
+    db: &Client,
+    token: &str,
+    now: DateTime<Utc>,
+) -> Result<String, Errors> {
+    let rows = Query::new(
+        "SELECT email FROM invitations
+            WHERE tokenHash = ? AND usedAt IS NULL AND expiresAt > ?;",
+    )
+    .bind(hash_token(token))
+    .bind(now.timestamp())
+    .fetch(db)
+    .await?;
+    email_from(&rows)
+}
+
+/// marks `token` as used and returns the email it was sent to,
+/// if it exists, hasn't expired and hasn't been used
+pub async fn consume_invitation(
+    db: &Client,
+    token: &str,
+    now: DateTime<Utc>,
+) -> Result<String, Errors> {
+    let rs = Query::new(
+        "UPDATE invitations SET usedAt = ?
+            WHERE tokenHash = ? AND usedAt IS NULL AND expiresAt > ?
+            RETURNING email;",
+    )
+    .bind(now.to_rfc3339())
+    .bind(hash_token(token))
+    .bind(now.timestamp())
+    .execute(db)
+    .await?;
+    email_from(&rs.rows)
+}
+
+/// makes a consumed invitation usable again, for when registering with it failed
+pub async fn release_invitation(db: &Client, token: &str) -> Result<(), Errors> {
+    Query::new("UPDATE invitations SET usedAt = NULL WHERE tokenHash = ?;")
+        .bind(hash_token(token))
+        .execute(db)
+        .await
+        .map(|_| ())
+}
+
+fn email_from(rows: &[Row]) -> Result<String, Errors> {
+    rows.first()
+        .ok_or(Errors::InvalidInvitation)?
+        .try_column::<&str>("email")
+        .map(str::to_string)
+        .map_err(Errors::DbFetchError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_invitations() {
+        let client = libsql_client::Client::in_memory().unwrap();
+        crate::models::init_db(&client).await.unwrap();
+        let inviter = Uuid::new_v4();
+        let now = Utc::now();
+        let ttl = Duration::days(7);
+        let email = "new@example.com";
+
+        // checking an invitation doesn't use it up, but consuming it does
+        let token = create_invitation(&client, inviter, email, now, ttl)
+            .await
+            .unwrap();
+        assert_eq!(
+            get_invitation_email(&client, &token, now).await.unwrap(),
+            email
+        );
+        assert_eq!(
+            consume_invitation(&client, &token, now).await.unwrap(),
+            email
+        );
+        assert!(matches!(
+            consume_invitation(&client, &token, now).await,
+            Err(Errors::InvalidInvitation)
+        ));
+
+        // unless it's released again
+        release_invitation(&client, &token).await.unwrap();
+        assert!(consume_invitation(&client, &token, now).await.is_ok());
+
+        // invitations expire
+        let token = create_invitation(&client, inviter, email, now, ttl)
+            .await
+            .unwrap();
+        assert!(matches!(
+            get_invitation_email(&client, &token, now + ttl).await,
+            Err(Errors::InvalidInvitation)
+        ));
+
+        // inviting the same address again replaces the old invitation
+        let old = create_invitation(&client, inviter, email, now, ttl)
+            .await
+            .unwrap();
+        let new = create_invitation(&client, inviter, email, now, ttl)
+            .await
+            .unwrap();
+        assert!(consume_invitation(&client, &old, now).await.is_err());
+        assert!(consume_invitation(&client, &new, now).await.is_ok());
+    }
+}
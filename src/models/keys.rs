@@ -1,15 +1,164 @@
+use axum_sessions::async_session::chrono;
 use libsql_client::Client;
-use log::debug;
+use serde::Serialize;
 use uuid::Uuid;
-use webauthn_rs::prelude::Passkey;
-
-pub async fn add_key(db: &Client, uuid: Uuid, key: Passkey) -> Result<(), crate::Error> {
-    let stmt = format!(
-        "INSERT INTO keys (userid, pubkey) VALUES (\"{}\", \"{}\");",
-        uuid,
-        serde_json::to_string(&key).expect("error serializing PassKey json")
-    );
-    debug!("stmt: {}", stmt);
-    db.execute(stmt).await.expect("error adding key to db");
+use webauthn_rs::prelude::{AuthenticationResult, CredentialID, Passkey};
+
+use crate::{errors::Errors, models::db::Query};
+
+/// a registered passkey as shown to its owner; the credential itself stays server side
+#[derive(Serialize, Debug)]
+pub struct Key {
+    pub credential_id: String,
+    pub nickname: String,
+    pub created_at: String,
+    pub last_used_at: Option<String>,
+}
+
+impl Key {
+    fn from_db_row(row: &libsql_client::Row) -> Result<Self, Errors> {
+        Ok(Self {
+            credential_id: row
+                .try_column::<&str>("credentialId")
+                .map_err(Errors::DbFetchError)?
+                .to_string(),
+            nickname: row
+                .try_column::<&str>("nickname")
+                .map_err(Errors::DbFetchError)?
+                .to_string(),
+            created_at: row
+                .try_column::<&str>("createdAt")
+                .map_err(Errors::DbFetchError)?
+                .to_string(),
+            last_used_at: row
+                .try_column::<&str>("lastUsedAt")
+                .ok()
+                .map(str::to_string),
+        })
+    }
+}
+
+fn credential_id(cred_id: &CredentialID) -> String {
+    cred_id.to_string()
+}
+
+pub async fn add_key(
+    db: &Client,
+    userid: Uuid,
+    key: Passkey,
+    nickname: &str,
+) -> Result<(), Errors> {
+    Query::new(
+        "INSERT INTO keys (userid, pubkey, credentialId, nickname, createdAt) VALUES (?, ?, ?, ?, ?);",
+    )
+    .bind(userid.urn().to_string())
+    .bind(serde_json::to_string(&key).map_err(Errors::DbStoredKeyParsingError)?)
+    .bind(credential_id(key.cred_id()))
+    .bind(nickname)
+    .bind(chrono::offset::Utc::now().to_rfc3339())
+    .execute(db)
+    .await
+    .map(|_| ())
+}
+
+pub async fn get_keys(db: &Client, userid: Uuid) -> Result<Vec<Passkey>, Errors> {
+    Query::new("SELECT pubkey FROM keys WHERE userid = ?;")
+        .bind(userid.urn().to_string())
+        .fetch(db)
+        .await?
+        .iter()
+        .map(|row| {
+            let key: &str = row.try_column("pubkey").map_err(Errors::DbFetchError)?;
+            serde_json::from_str(key).map_err(Errors::DbStoredKeyParsingError)
+        })
+        .collect()
+}
+
+pub async fn list_keys(db: &Client, userid: Uuid) -> Result<Vec<Key>, Errors> {
+    Query::new(
+        "SELECT credentialId, nickname, createdAt, lastUsedAt FROM keys
+            WHERE userid = ? ORDER BY createdAt;",
+    )
+    .bind(userid.urn().to_string())
+    .fetch(db)
+    .await?
+    .iter()
+    .map(Key::from_db_row)
+    .collect()
+}
+
+pub async fn rename_key(
+    db: &Client,
+    userid: Uuid,
+    credential_id: &str,
+    nickname: &str,
+) -> Result<(), Errors> {
+    let rs = Query::new("UPDATE keys SET nickname = ? WHERE userid = ? AND credentialId = ?;")
+        .bind(nickname)
+        .bind(userid.urn().to_string())
+        .bind(credential_id)
+        .execute(db)
+        .await?;
+    match rs.rows_affected {
+        0 => Err(Errors::DbKeyNotFound(credential_id.to_string())),
+        _ => Ok(()),
+    }
+}
+
+/// deletes one of `userid`'s passkeys, unless it's the only way they have left to log in.
+/// the check is part of the delete, so removing two keys at once can't leave them with none
+pub async fn remove_key(db: &Client, userid: Uuid, credential_id: &str) -> Result<(), Errors> {
+    let userid = userid.urn().to_string();
+    let rs = Query::new(
+        "DELETE FROM keys WHERE userid = ? AND credentialId = ?
+            AND ((SELECT COUNT(*) FROM keys WHERE userid = ?) > 1
+                OR (SELECT hash FROM users WHERE id = ?) IS NOT NULL);",
+    )
+    .bind(userid.as_str())
+    .bind(credential_id)
+    .bind(userid.as_str())
+    .bind(userid.as_str())
+    .execute(db)
+    .await?;
+    if rs.rows_affected > 0 {
+        return Ok(());
+    }
+
+    let exists = Query::new("SELECT id FROM keys WHERE userid = ? AND credentialId = ?;")
+        .bind(userid.as_str())
+        .bind(credential_id)
+        .fetch_one(db)
+        .await?
+        .is_some();
+    match exists {
+        true => Err(Errors::CannotRemoveLastCredential),
+        false => Err(Errors::DbKeyNotFound(credential_id.to_string())),
+    }
+}
+
+/// applies a successful authentication to the stored credential it used,
+/// so its signature counter keeps moving forward and its last use is recorded.
+pub async fn record_key_use(
+    db: &Client,
+    userid: Uuid,
+    auth_result: &AuthenticationResult,
+) -> Result<(), Errors> {
+    let now = chrono::offset::Utc::now().to_rfc3339();
+    for mut key in get_keys(db, userid).await? {
+        if key.cred_id() != auth_result.cred_id() {
+            continue;
+        }
+        key.update_credential(auth_result);
+
+        Query::new(
+            "UPDATE keys SET pubkey = ?, lastUsedAt = ? WHERE userid = ? AND credentialId = ?;",
+        )
+        .bind(serde_json::to_string(&key).map_err(Errors::DbStoredKeyParsingError)?)
+        .bind(now.as_str())
+        .bind(userid.urn().to_string())
+        .bind(credential_id(key.cred_id()))
+        .execute(db)
+        .await?;
+    }
     Ok(())
 }
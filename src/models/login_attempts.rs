@@ -0,0 +1,353 @@
+use axum_sessions::async_session::chrono::{self, DateTime, TimeZone, Utc};
+use libsql_client::Client;
+use log::warn;
+use serde::Serialize;
+
+use crate::{
+    constants::login::{
+        BACKOFF_BASE_SECS, FAILURE_WINDOW_SECS, FREE_ATTEMPTS, LOCKOUT_SECS, LOCKOUT_THRESHOLD,
+        MAX_BACKOFF_SECS,
+    },
+    errors::Errors,
+    models::db::{batch, Query},
+};
+
+/// what failed logins are counted against; a username and the address a guess came from
+/// are throttled independently, so neither spraying one account nor many accounts works.
+/// password reset requests are counted the same way, apart from logins
+#[derive(Debug, Clone, Copy)]
+pub enum Subject<'a> {
+    Username(&'a str),
+    Address(&'a str),
+    ResetUsername(&'a str),
+    ResetAddress(&'a str),
+}
+
+impl<'a> Subject<'a> {
+    pub(super) fn scope(&self) -> &'static str {
+        match self {
+            Subject::Username(_) => "username",
+            Subject::Address(_) => "address",
+            Subject::ResetUsername(_) => "reset_username",
+            Subject::ResetAddress(_) => "reset_address",
+        }
+    }
+
+    pub(super) fn value(&self) -> &'a str {
+        match self {
+            Subject::Username(value)
+            | Subject::Address(value)
+            | Subject::ResetUsername(value)
+            | Subject::ResetAddress(value) => value,
+        }
+    }
+}
+
+/// recent failures for one subject, with times as unix seconds
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Failures {
+    pub(super) failures: u32,
+    last_failure_at: i64,
+    locked_until: i64,
+}
+
+impl Failures {
+    fn from_db_row(row: &libsql_client::Row) -> Result<Self, Errors> {
+        Ok(Self {
+            failures: row
+                .try_column::<usize>("failures")
+                .map_err(Errors::DbFetchError)? as u32,
+            last_failure_at: row
+                .try_column("lastFailureAt")
+                .map_err(Errors::DbFetchError)?,
+            locked_until: row
+                .try_column("lockedUntil")
+                .map_err(Errors::DbFetchError)?,
+        })
+    }
+
+    /// forgets failures once a lockout has been served or they've aged out of the window
+    fn current(self, now: i64) -> Self {
+        let lockout_served = self.locked_until != 0 && self.locked_until <= now;
+        let stale = self.locked_until <= now && now - self.last_failure_at > FAILURE_WINDOW_SECS;
+        if lockout_served || stale {
+            Self::default()
+        } else {
+            self
+        }
+    }
+
+    /// one more failure at `now`, the same way `record_failure`'s upsert counts it
+    pub(super) fn fail(self, now: i64) -> Self {
+        let current = self.current(now);
+        let failures = current.failures + 1;
+        let locked_until = if failures >= LOCKOUT_THRESHOLD {
+            now + LOCKOUT_SECS
+        } else {
+            current.locked_until
+        };
+        Self {
+            failures,
+            last_failure_at: now,
+            locked_until,
+        }
+    }
+
+    /// when this subject may next try to log in, if it can't right now
+    pub(super) fn retry_at(&self, now: i64) -> Option<i64> {
+        let current = self.current(now);
+        let retry_at = current
+            .locked_until
+            .max(current.last_failure_at + backoff(current.failures));
+        (retry_at > now).then_some(retry_at)
+    }
+}
+
+/// seconds to wait after `failures` failed attempts: nothing for the first few,
+/// then doubling from `BACKOFF_BASE_SECS` up to `MAX_BACKOFF_SECS`
+fn backoff(failures: u32) -> i64 {
+    if failures <= FREE_ATTEMPTS {
+        return 0;
+    }
+    let doublings = (failures - FREE_ATTEMPTS - 1).min(32);
+    BACKOFF_BASE_SECS
+        .saturating_mul(1 << doublings)
+        .min(MAX_BACKOFF_SECS)
+}
+
+async fn get_failures(db: &Client, subject: Subject<'_>) -> Result<Failures, Errors> {
+    let row = Query::new(
+        "SELECT failures, lastFailureAt, lockedUntil FROM login_failures
+            WHERE scope = ? AND subject = ?;",
+    )
+    .bind(subject.scope())
+    .bind(subject.value())
+    .fetch_one(db)
+    .await?;
+    match row {
+        Some(row) => Failures::from_db_row(&row),
+        None => Ok(Failures::default()),
+    }
+}
+
+/// the latest time any of `subjects` is throttled until, or `None` if a login may be attempted now
+pub async fn retry_at(
+    db: &Client,
+    subjects: &[Subject<'_>],
+    now: DateTime<Utc>,
+) -> Result<Option<DateTime<Utc>>, Errors> {
+    let mut latest = None;
+    for subject in subjects {
+        let retry_at = get_failures(db, *subject).await?.retry_at(now.timestamp());
+        latest = latest.max(retry_at);
+    }
+    Ok(latest.map(|secs| Utc.timestamp_opt(secs, 0).unwrap()))
+}
+
+/// counts a failed login against each subject, locking out any that reach `LOCKOUT_THRESHOLD`.
+/// the count is worked out by the upsert itself, the same way `Failures::current` forgets old
+/// failures, so concurrent guesses can't overwrite each other's
+pub async fn record_failure(
+    db: &Client,
+    subjects: &[Subject<'_>],
+    now: DateTime<Utc>,
+) -> Result<(), Errors> {
+    let locked_until = now + chrono::Duration::seconds(LOCKOUT_SECS);
+    for subject in subjects {
+        // ?1 scope, ?2 subject, ?3 now, ?4 the lockout threshold, ?5 how long a lockout lasts
+        // and ?6 how long failures are remembered
+        let fail = Query::new(
+            "INSERT INTO login_failures (scope, subject, failures, lastFailureAt, lockedUntil)
+                VALUES (?1, ?2, 1, ?3, CASE WHEN 1 >= ?4 THEN ?3 + ?5 ELSE 0 END)
+                ON CONFLICT (scope, subject) DO UPDATE SET
+                    failures = CASE
+                        WHEN lockedUntil <= ?3 AND (lockedUntil != 0 OR ?3 - lastFailureAt > ?6)
+                        THEN 1 ELSE failures + 1 END,
+                    lastFailureAt = ?3,
+                    lockedUntil = CASE
+                        WHEN lockedUntil <= ?3 AND (lockedUntil != 0 OR ?3 - lastFailureAt > ?6)
+                        THEN CASE WHEN 1 >= ?4 THEN ?3 + ?5 ELSE 0 END
+                        WHEN failures + 1 >= ?4 THEN ?3 + ?5
+                        ELSE lockedUntil END
+                RETURNING failures;",
+        )
+        .bind(subject.scope())
+        .bind(subject.value())
+        .bind(now.timestamp())
+        .bind(LOCKOUT_THRESHOLD as i64)
+        .bind(LOCKOUT_SECS)
+        .bind(FAILURE_WINDOW_SECS);
+        // only the failure that reaches the threshold is logged as a lockout
+        let log = Query::new(
+            "INSERT INTO lockouts (scope, subject, failures, lockedAt, lockedUntil)
+                SELECT scope, subject, failures, ?, ? FROM login_failures
+                WHERE scope = ? AND subject = ? AND failures = ?;",
+        )
+        .bind(now.to_rfc3339())
+        .bind(locked_until.to_rfc3339())
+        .bind(subject.scope())
+        .bind(subject.value())
+        .bind(LOCKOUT_THRESHOLD as i64);
+
+        let results = batch(db, [fail, log]).await?;
+        let failures = match results.first().and_then(|rs| rs.rows.first()) {
+            Some(row) => row
+                .try_column::<usize>("failures")
+                .map_err(Errors::DbFetchError)?,
+            None => 0,
+        };
+        if failures == LOCKOUT_THRESHOLD as usize {
+            warn!("locking out {} {}", subject.scope(), subject.value());
+        }
+    }
+    Ok(())
+}
+
+/// a past lockout, kept for stewards to review after it's expired
+#[derive(Debug, Clone, Serialize)]
+pub struct Lockout {
+    pub scope: String,
+    pub subject: String,
+    pub failures: u32,
+    pub locked_at: String,
+    pub locked_until: String,
+}
+
+impl Lockout {
+    fn from_db_row(row: &libsql_client::Row) -> Result<Self, Errors> {
+        let text = |column: &str| {
+            row.try_column::<&str>(column)
+                .map(str::to_string)
+                .map_err(Errors::DbFetchError)
+        };
+        Ok(Self {
+            scope: text("scope")?,
+            subject: text("subject")?,
+            failures: row
+                .try_column::<usize>("failures")
+                .map_err(Errors::DbFetchError)? as u32,
+            locked_at: text("lockedAt")?,
+            locked_until: text("lockedUntil")?,
+        })
+    }
+}
+
+/// every lockout, most recent first
+pub async fn lockouts(db: &Client) -> Result<Vec<Lockout>, Errors> {
+    Query::new(
+        "SELECT scope, subject, failures, lockedAt, lockedUntil FROM lockouts
+            ORDER BY lockedAt DESC;",
+    )
+    .fetch(db)
+    .await?
+    .iter()
+    .map(Lockout::from_db_row)
+    .collect()
+}
+
+/// forgets a subject's failures, after it logs in successfully
+pub async fn clear(db: &Client, subject: Subject<'_>) -> Result<(), Errors> {
+    Query::new("DELETE FROM login_failures WHERE scope = ? AND subject = ?;")
+        .bind(subject.scope())
+        .bind(subject.value())
+        .execute(db)
+        .await
+        .map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum_sessions::async_session::chrono::Duration;
+
+    use super::*;
+    use crate::clock::{Clock, FakeClock};
+
+    #[test]
+    fn test_backoff() {
+        assert_eq!(backoff(FREE_ATTEMPTS), 0);
+        assert_eq!(backoff(FREE_ATTEMPTS + 1), BACKOFF_BASE_SECS);
+        assert_eq!(backoff(FREE_ATTEMPTS + 2), BACKOFF_BASE_SECS * 2);
+        assert_eq!(backoff(FREE_ATTEMPTS + 3), BACKOFF_BASE_SECS * 4);
+        assert_eq!(backoff(u32::MAX), MAX_BACKOFF_SECS);
+    }
+
+    #[tokio::test]
+    async fn test_lockout() {
+        let client = libsql_client::Client::in_memory().unwrap();
+        crate::models::init_db(&client).await.unwrap();
+        let clock = FakeClock::new(Utc.timestamp_opt(1_700_000_000, 0).unwrap());
+        let subjects = [Subject::Username("alice"), Subject::Address("10.0.0.1")];
+
+        for _ in 0..FREE_ATTEMPTS {
+            assert_eq!(
+                retry_at(&client, &subjects, clock.now()).await.unwrap(),
+                None
+            );
+            record_failure(&client, &subjects, clock.now())
+                .await
+                .unwrap();
+        }
+
+        // backoff starts once the free attempts are used up,
+        // and applies to the username whichever address guesses next
+        record_failure(&client, &subjects, clock.now())
+            .await
+            .unwrap();
+        let other_address = [Subject::Username("alice"), Subject::Address("10.0.0.2")];
+        assert_eq!(
+            retry_at(&client, &other_address, clock.now())
+                .await
+                .unwrap(),
+            Some(clock.now() + Duration::seconds(BACKOFF_BASE_SECS))
+        );
+        clock.advance(Duration::seconds(BACKOFF_BASE_SECS));
+        assert_eq!(
+            retry_at(&client, &subjects, clock.now()).await.unwrap(),
+            None
+        );
+
+        for _ in FREE_ATTEMPTS + 1..LOCKOUT_THRESHOLD {
+            record_failure(&client, &subjects, clock.now())
+                .await
+                .unwrap();
+        }
+        let locked_until = clock.now() + Duration::seconds(LOCKOUT_SECS);
+        assert_eq!(
+            retry_at(&client, &other_address, clock.now())
+                .await
+                .unwrap(),
+            Some(locked_until)
+        );
+        let lockouts = lockouts(&client).await.unwrap();
+        assert_eq!(lockouts.len(), subjects.len());
+        assert!(lockouts.iter().all(|l| l.failures == LOCKOUT_THRESHOLD));
+
+        // the lockout expires, and a successful login clears the username but not the address
+        clock.advance(Duration::seconds(LOCKOUT_SECS));
+        assert_eq!(
+            retry_at(&client, &subjects, clock.now()).await.unwrap(),
+            None
+        );
+        record_failure(&client, &subjects, clock.now())
+            .await
+            .unwrap();
+        let address = get_failures(&client, Subject::Address("10.0.0.1"))
+            .await
+            .unwrap();
+        assert_eq!(address.failures, 1);
+        assert_eq!(address.locked_until, 0);
+        clear(&client, Subject::Username("alice")).await.unwrap();
+        assert_eq!(
+            get_failures(&client, Subject::Username("alice"))
+                .await
+                .unwrap(),
+            Failures::default()
+        );
+        assert_eq!(
+            get_failures(&client, Subject::Address("10.0.0.1"))
+                .await
+                .unwrap(),
+            address
+        );
+    }
+}
@@ -1,76 +1,426 @@
+use std::fmt::{self, Display, Formatter};
+
 use axum_sessions::async_session::chrono;
-use libsql_client::{args, Statement};
+use sha2::{Digest, Sha256};
 
-use crate::errors::Errors;
+use crate::{
+    errors::Errors,
+    models::db::{batch, Query},
+};
 
 mod queries;
 
-// this array should only ever be added to; never changed
-pub static MIGRATIONS: [&str; 4] = [
-    queries::CREATE_MIGRATIONS_TABLE,
-    queries::CREATE_USERS_TABLE,
-    queries::CREATE_KEYS_TABLE,
-    queries::CREATE_PROPOSALS_TABLE,
+/// one schema change, from `migrations/<name>.up.sql` and, if it can be undone,
+/// `migrations/<name>.down.sql`. either can hold several statements. `up` is checksummed
+/// once it's been run, so it must never be edited
+#[derive(Debug, Clone, Copy)]
+pub struct Migration {
+    pub name: &'static str,
+    pub up: &'static str,
+    pub down: Option<&'static str>,
+}
+
+/// a migration or its undoing as `migrate_db` or `rollback` would run it
+#[derive(Debug, Clone, Copy)]
+pub struct Step {
+    pub id: usize,
+    pub name: &'static str,
+    pub sql: &'static str,
+}
+
+impl Display for Step {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "-- {}\n{}", self.name, self.sql.trim())
+    }
+}
+
+/// embeds a migration's sql files in the binary
+macro_rules! migration {
+    ($name:literal) => {
+        Migration {
+            name: $name,
+            up: include_str!(concat!("../../migrations/", $name, ".up.sql")),
+            down: None,
+        }
+    };
+    ($name:literal, reversible) => {
+        Migration {
+            name: $name,
+            up: include_str!(concat!("../../migrations/", $name, ".up.sql")),
+            down: Some(include_str!(concat!(
+                "../../migrations/",
+                $name,
+                ".down.sql"
+            ))),
+        }
+    };
+}
+
+// this array should only ever be added to, and the files it names never changed
+pub static MIGRATIONS: [Migration; 30] = [
+    migration!("0001_create_migrations_table"),
+    migration!("0002_create_users_table", reversible),
+    migration!("0003_create_keys_table", reversible),
+    migration!("0004_create_proposals_table", reversible),
+    migration!("0005_add_proposals_decision_rule", reversible),
+    migration!("0006_add_proposals_quorum", reversible),
+    migration!("0007_create_votes_table", reversible),
+    migration!("0008_add_proposals_status", reversible),
+    migration!("0009_add_proposals_closes_at", reversible),
+    migration!("0010_create_proposal_transitions_table", reversible),
+    migration!("0011_add_users_sessions_revoked_at", reversible),
+    migration!("0012_create_sessions_table", reversible),
+    migration!("0013_add_keys_pubkey", reversible),
+    migration!("0014_add_keys_credential_id", reversible),
+    migration!("0015_add_keys_nickname", reversible),
+    migration!("0016_add_keys_created_at", reversible),
+    migration!("0017_add_keys_last_used_at", reversible),
+    migration!("0018_create_login_failures_table", reversible),
+    migration!("0019_create_lockouts_table", reversible),
+    migration!("0020_create_password_resets_table", reversible),
+    migration!("0021_create_outbox_table", reversible),
+    migration!("0022_add_users_email", reversible),
+    migration!("0023_create_invitations_table", reversible),
+    migration!("0024_add_users_role", reversible),
+    migration!("0025_promote_first_user_to_admin", reversible),
+    migration!("0026_fix_keys_and_proposals", reversible),
+    migration!("0027_add_users_disabled_at", reversible),
+    migration!("0028_enforce_user_references", reversible),
+    migration!("0029_add_outbox_claimed_at", reversible),
+    migration!("0030_enforce_vote_references", reversible),
 ];
 
+/// migrations are numbered by their file names, which have to count up from 1 with no gaps
+/// or repeats, or the ids already recorded in the database would stop lining up with them
+pub fn validate(migrations: &[Migration]) -> Result<(), Errors> {
+    for (i, migration) in migrations.iter().enumerate() {
+        let number = migration
+            .name
+            .split('_')
+            .next()
+            .and_then(|number| number.parse::<usize>().ok());
+        if number != Some(i + 1) {
+            return Err(Errors::MigrationOutOfOrder {
+                position: i + 1,
+                name: migration.name,
+            });
+        }
+    }
+    Ok(())
+}
+
+/// checks the migrations the database has already had still match `migrations`, then runs
+/// the rest in order, returning how many were run
 pub async fn migrate_db(
     client: &libsql_client::Client,
-    migrations: &Vec<&str>,
+    migrations: &[Migration],
 ) -> Result<usize, Errors> {
-    let mut migrations_executed: usize = 0;
-    let latest_migration = get_latest(client).await;
-    let mut i = latest_migration.unwrap_or(0);
-
-    while i < migrations.len() {
-        let query = migrations[i];
-        exec_migration(client, query).await.unwrap();
-        add(client, i + 1, query)
-            .await
-            .expect("error updating migrations table");
+    let steps = plan_migrate(client, migrations).await?;
+    for step in steps.iter() {
+        let now = chrono::offset::Utc::now().to_rfc3339();
+        let record = Query::new(queries::ADD_MIGRATION)
+            .bind(step.id as i64)
+            .bind(now)
+            .bind(step.sql)
+            .bind(checksum(step.sql));
+        apply(client, step, record).await?;
+    }
+    Ok(steps.len())
+}
 
-        i += 1;
-        migrations_executed += 1;
+/// the up steps `migrate_db` would run, in the order it would run them
+pub async fn plan_migrate(
+    client: &libsql_client::Client,
+    migrations: &[Migration],
+) -> Result<Vec<Step>, Errors> {
+    prepare(client, migrations).await?;
+    verify(client, migrations).await?;
+
+    let latest = get_latest(client).await?;
+    Ok(migrations
+        .iter()
+        .enumerate()
+        .skip(latest)
+        .map(|(i, migration)| Step {
+            id: i + 1,
+            name: migration.name,
+            sql: migration.up,
+        })
+        .collect())
+}
+
+/// every migration, and when it was applied, if it has been
+pub async fn status(
+    client: &libsql_client::Client,
+    migrations: &[Migration],
+) -> Result<Vec<(Step, Option<String>)>, Errors> {
+    prepare(client, migrations).await?;
+    verify(client, migrations).await?;
+
+    let mut applied = std::collections::HashMap::new();
+    for row in Query::new(queries::GET_MIGRATION_DATES)
+        .fetch(client)
+        .await?
+        .iter()
+    {
+        let id: usize = row.try_column("id").map_err(Errors::DbFetchError)?;
+        let date: &str = row.try_column("date").map_err(Errors::DbFetchError)?;
+        applied.insert(id, date.to_string());
     }
+    Ok(migrations
+        .iter()
+        .enumerate()
+        .map(|(i, migration)| {
+            let step = Step {
+                id: i + 1,
+                name: migration.name,
+                sql: migration.up,
+            };
+            (step, applied.remove(&step.id))
+        })
+        .collect())
+}
 
-    Ok(migrations_executed)
+/// undoes every migration after `to`, newest first, returning how many were undone
+pub async fn rollback(
+    client: &libsql_client::Client,
+    migrations: &[Migration],
+    to: usize,
+) -> Result<usize, Errors> {
+    let steps = plan_rollback(client, migrations, to).await?;
+    for step in steps.iter() {
+        let record = Query::new(queries::REMOVE_MIGRATION).bind(step.id as i64);
+        apply(client, step, record).await?;
+    }
+    Ok(steps.len())
 }
 
-async fn get_latest(client: &libsql_client::Client) -> Result<usize, Errors> {
-    client
-        .execute(queries::GET_LATEST_MIGRATION)
-        .await
-        .map(|rs: libsql_client::ResultSet| {
-            rs.rows
-                .first()
-                .map(|row: &libsql_client::Row| row.try_get(0).unwrap())
-                .unwrap_or(0)
+/// the down steps `rollback` would run, in the order it would run them. nothing is undone
+/// unless every one of them can be
+pub async fn plan_rollback(
+    client: &libsql_client::Client,
+    migrations: &[Migration],
+    to: usize,
+) -> Result<Vec<Step>, Errors> {
+    prepare(client, migrations).await?;
+    verify(client, migrations).await?;
+
+    let latest = get_latest(client).await?;
+    (to + 1..=latest)
+        .rev()
+        .map(|id| {
+            let migration = &migrations[id - 1];
+            match migration.down {
+                Some(sql) => Ok(Step {
+                    id,
+                    name: migration.name,
+                    sql,
+                }),
+                None => Err(Errors::IrreversibleMigration {
+                    id,
+                    name: migration.name,
+                }),
+            }
         })
-        .map_err(Errors::DbFetchLatestMigrationError)
+        .collect()
 }
 
-async fn exec_migration(client: &libsql_client::Client, query: &str) -> Result<(), Errors> {
-    client
-        .execute(query)
-        .await
-        .unwrap_or_else(|e| panic!("error initializing db on query {query}: {e}"));
+/// the bookkeeping table belongs to the migrator rather than to any one migration, so it's
+/// made sure of here: created on a fresh database, and given checksums on one migrated before
+/// they were recorded, worked out from the sql that was stored alongside each migration.
+/// the first of `migrations` is the one that creates the table
+async fn prepare(client: &libsql_client::Client, migrations: &[Migration]) -> Result<(), Errors> {
+    if let Some(bookkeeping) = migrations.first() {
+        Query::new(bookkeeping.up).execute(client).await?;
+    }
+    let has_checksums = Query::new(queries::HAS_MIGRATION_CHECKSUMS)
+        .fetch_one(client)
+        .await?
+        .is_some();
+    if !has_checksums {
+        Query::new(queries::ADD_MIGRATION_CHECKSUMS)
+            .execute(client)
+            .await?;
+    }
 
+    let unchecked = Query::new(queries::GET_MIGRATIONS_WITHOUT_CHECKSUMS)
+        .fetch(client)
+        .await?;
+    let backfill = unchecked
+        .iter()
+        .map(|row| {
+            let id: i64 = row.try_column("id").map_err(Errors::DbFetchError)?;
+            let query: &str = row.try_column("query").map_err(Errors::DbFetchError)?;
+            Ok(Query::new(queries::SET_MIGRATION_CHECKSUM)
+                .bind(checksum(query))
+                .bind(id))
+        })
+        .collect::<Result<Vec<Query>, Errors>>()?;
+    if !backfill.is_empty() {
+        batch(client, backfill).await?;
+    }
     Ok(())
 }
 
-async fn add(client: &libsql_client::Client, id: usize, query: &str) -> Result<(), Errors> {
-    let now = chrono::offset::Utc::now().to_rfc3339();
-    let stmt = Statement::with_args(queries::ADD_MIGRATION, args![id, now, query]);
-    client
-        .execute(stmt)
+/// an applied migration that's since been edited or removed means the schema isn't what the
+/// code expects, and carrying on would only make that worse
+async fn verify(client: &libsql_client::Client, migrations: &[Migration]) -> Result<(), Errors> {
+    let rows = Query::new(queries::GET_MIGRATION_CHECKSUMS)
+        .fetch(client)
+        .await?;
+    let mut changed = vec![];
+    let mut unknown = vec![];
+    for row in rows.iter() {
+        let id: usize = row.try_column("id").map_err(Errors::DbFetchError)?;
+        let stored: &str = row.try_column("checksum").map_err(Errors::DbFetchError)?;
+        match id.checked_sub(1).and_then(|i| migrations.get(i)) {
+            Some(migration) if checksum(migration.up) == stored => (),
+            Some(_) => changed.push(id),
+            None => unknown.push(id),
+        }
+    }
+
+    if changed.is_empty() && unknown.is_empty() {
+        Ok(())
+    } else {
+        Err(Errors::DbMigrationDrift { changed, unknown })
+    }
+}
+
+async fn get_latest(client: &libsql_client::Client) -> Result<usize, Errors> {
+    let rs = client
+        .execute(queries::GET_LATEST_MIGRATION)
         .await
-        .map(|_| ())
-        .map_err(Errors::DbInsertError)
+        .map_err(Errors::DbFetchLatestMigrationError)?;
+    match rs.rows.first() {
+        Some(row) => row.try_get(0).map_err(Errors::DbFetchLatestMigrationError),
+        None => Ok(0),
+    }
+}
+
+/// runs a step and updates the bookkeeping in the same transaction, so the schema and the
+/// record of it never disagree. migrations are schema changes with nothing to bind, so
+/// they're run as they are
+async fn apply(client: &libsql_client::Client, step: &Step, record: Query) -> Result<(), Errors> {
+    let mut queries: Vec<Query> = statements(step.sql).into_iter().map(Query::new).collect();
+    queries.push(record);
+    match batch(client, queries).await {
+        Ok(_) => Ok(()),
+        Err(Errors::DbInsertError(source)) => Err(Errors::DbMigrationError {
+            id: step.id,
+            source,
+        }),
+        Err(e) => Err(e),
+    }
+}
+
+/// splits a migration on the semicolons that end its statements, skipping ones in quotes or
+/// comments, and ones inside a trigger's body, which only ends at its END
+fn statements(sql: &'static str) -> Vec<&'static str> {
+    let bytes = sql.as_bytes();
+    let mut statements = vec![];
+    let mut start = 0;
+    // where the current statement's sql begins, once it has any
+    let mut first: Option<usize> = None;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            quote @ (b'\'' | b'"' | b'`') => {
+                first.get_or_insert(i);
+                i += 1;
+                while i < bytes.len() && bytes[i] != quote {
+                    i += 1;
+                }
+            }
+            b'-' if bytes.get(i + 1) == Some(&b'-') => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+            }
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                i += 2;
+                while i < bytes.len() && !(bytes[i] == b'*' && bytes.get(i + 1) == Some(&b'/')) {
+                    i += 1;
+                }
+                i += 1;
+            }
+            b';' => match first {
+                Some(from) if in_trigger_body(&sql[from..i]) => (),
+                Some(_) => {
+                    statements.push(sql[start..=i].trim());
+                    start = i + 1;
+                    first = None;
+                }
+                None => start = i + 1,
+            },
+            c if !c.is_ascii_whitespace() => {
+                first.get_or_insert(i);
+            }
+            _ => (),
+        }
+        i += 1;
+    }
+    if first.is_some() {
+        statements.push(sql[start..].trim());
+    }
+    statements
+}
+
+/// whether a semicolon after `statement` ends one of the statements in a trigger's body
+/// rather than the trigger itself
+fn in_trigger_body(statement: &str) -> bool {
+    let words: Vec<String> = statement
+        .split_whitespace()
+        .map(|word| word.to_ascii_uppercase())
+        .collect();
+    let creates_trigger = match words.as_slice() {
+        [create, trigger, ..] if create == "CREATE" && trigger == "TRIGGER" => true,
+        [create, temp, trigger, ..] => {
+            create == "CREATE" && (temp == "TEMP" || temp == "TEMPORARY") && trigger == "TRIGGER"
+        }
+        _ => false,
+    };
+    creates_trigger && words.last().map(String::as_str) != Some("END")
+}
+
+/// files end in a newline the old inline migrations didn't, so surrounding whitespace is left
+/// out; that keeps the checksums of databases migrated before them the same
+fn checksum(query: &str) -> String {
+    format!("{:x}", Sha256::digest(query.trim().as_bytes()))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn migration(up: &'static str, down: Option<&'static str>) -> Migration {
+        Migration {
+            name: "test",
+            up,
+            down,
+        }
+    }
+
+    /// every table and its columns, for comparing schemas
+    async fn schema(client: &libsql_client::Client) -> Vec<(String, String)> {
+        Query::new(
+            "SELECT m.name AS tbl, p.name AS col FROM sqlite_master m
+                JOIN pragma_table_info(m.name) p
+                WHERE m.type = 'table' ORDER BY tbl, col;",
+        )
+        .fetch(client)
+        .await
+        .unwrap()
+        .iter()
+        .map(|row| {
+            (
+                row.try_column::<&str>("tbl").unwrap().to_string(),
+                row.try_column::<&str>("col").unwrap().to_string(),
+            )
+        })
+        .collect()
+    }
+
     #[tokio::test]
     async fn test_migrate_db() {
         let client = libsql_client::Client::in_memory().unwrap();
@@ -78,16 +428,541 @@ mod tests {
         assert!(get_latest(&client).await.is_err());
 
         let num_executions = migrate_db(&client, &migrations).await.unwrap();
-        assert_eq!(num_executions, 4);
-        assert_eq!(get_latest(&client).await.unwrap(), 4);
+        assert_eq!(num_executions, MIGRATIONS.len());
+        assert_eq!(get_latest(&client).await.unwrap(), MIGRATIONS.len());
 
         let num_executions = migrate_db(&client, &migrations).await.unwrap();
         assert_eq!(num_executions, 0);
-        assert_eq!(get_latest(&client).await.unwrap(), 4);
+        assert_eq!(get_latest(&client).await.unwrap(), MIGRATIONS.len());
+
+        let migrated = schema(&client).await;
+        // every migration but the first can be undone, and redone to the same schema
+        let steps = plan_rollback(&client, &MIGRATIONS, 1).await.unwrap();
+        let ids: Vec<usize> = steps.iter().map(|step| step.id).collect();
+        assert_eq!(ids, (2..=MIGRATIONS.len()).rev().collect::<Vec<_>>());
+        assert_eq!(steps[0].sql, MIGRATIONS[MIGRATIONS.len() - 1].down.unwrap());
+        // planning is a dry run
+        assert_eq!(get_latest(&client).await.unwrap(), MIGRATIONS.len());
+        assert_eq!(schema(&client).await, migrated);
+
+        let undone = rollback(&client, &MIGRATIONS, 1).await.unwrap();
+        assert_eq!(undone, MIGRATIONS.len() - 1);
+        assert_eq!(get_latest(&client).await.unwrap(), 1);
+        assert!(schema(&client)
+            .await
+            .iter()
+            .all(|(table, _)| table == "migrations"));
+
+        let steps = plan_migrate(&client, &MIGRATIONS).await.unwrap();
+        assert_eq!(steps.len(), MIGRATIONS.len() - 1);
+        assert_eq!(steps[0].sql, MIGRATIONS[1].up);
+        assert_eq!(get_latest(&client).await.unwrap(), 1);
 
-        migrations.push("CREATE TABLE IF NOT EXISTS test_table (id INT PRIMARY KEY);");
+        let redone = migrate_db(&client, &MIGRATIONS).await.unwrap();
+        assert_eq!(redone, MIGRATIONS.len() - 1);
+        assert_eq!(schema(&client).await, migrated);
+
+        // each step on its own round trips too
+        for id in (2..=MIGRATIONS.len()).rev() {
+            assert_eq!(rollback(&client, &MIGRATIONS, id - 1).await.unwrap(), 1);
+            assert_eq!(migrate_db(&client, &MIGRATIONS).await.unwrap(), 1);
+            assert_eq!(schema(&client).await, migrated);
+        }
+
+        // rolling back to where the database already is does nothing
+        assert_eq!(
+            rollback(&client, &MIGRATIONS, MIGRATIONS.len())
+                .await
+                .unwrap(),
+            0
+        );
+
+        migrations.push(migration(
+            "CREATE TABLE IF NOT EXISTS test_table (id INT PRIMARY KEY);",
+            Some("DROP TABLE test_table;"),
+        ));
         let num_executions = migrate_db(&client, &migrations).await.unwrap();
         assert_eq!(num_executions, 1);
-        assert_eq!(get_latest(&client).await.unwrap(), 5);
+        assert_eq!(get_latest(&client).await.unwrap(), MIGRATIONS.len() + 1);
+
+        // a broken migration is an error, not a panic, and isn't recorded as run
+        migrations.push(migration("CREATE TABLE oops (;", None));
+        let id = migrations.len();
+        assert!(matches!(
+            migrate_db(&client, &migrations).await,
+            Err(Errors::DbMigrationError { id: failed, .. }) if failed == id
+        ));
+        assert_eq!(get_latest(&client).await.unwrap(), id - 1);
+        migrations.pop();
+
+        // a migration whose bookkeeping can't be written is rolled back with it; this one
+        // takes the row its own record needs, so if it weren't undone the row would remain
+        migrations.push(migration(
+            "INSERT INTO migrations (id) SELECT MAX(id) + 1 FROM migrations;",
+            None,
+        ));
+        let id = migrations.len();
+        assert!(matches!(
+            migrate_db(&client, &migrations).await,
+            Err(Errors::DbMigrationError { id: failed, .. }) if failed == id
+        ));
+        assert_eq!(get_latest(&client).await.unwrap(), id - 1);
+        migrations.pop();
+
+        // editing a migration that's already been run stops everything
+        let mut edited = migrations.clone();
+        edited[3].up = "CREATE TABLE IF NOT EXISTS proposals (id INT PRIMARY KEY);";
+        assert!(matches!(
+            migrate_db(&client, &edited).await,
+            Err(Errors::DbMigrationDrift { changed, unknown })
+                if changed == vec![4] && unknown.is_empty()
+        ));
+
+        // and so does running code that's older than the database
+        let older = &migrations[..MIGRATIONS.len()];
+        assert!(matches!(
+            migrate_db(&client, older).await,
+            Err(Errors::DbMigrationDrift { changed, unknown })
+                if changed.is_empty() && unknown == vec![MIGRATIONS.len() + 1]
+        ));
+
+        // names and downs aren't part of what was applied, so changing them isn't drift
+        let mut renamed = migrations.clone();
+        renamed[1].name = "renamed";
+        renamed[1].down = None;
+        assert_eq!(migrate_db(&client, &renamed).await.unwrap(), 0);
+
+        // a migration can hold several statements, and they're applied and undone together
+        let mut migrations = MIGRATIONS.to_vec();
+        migrations.push(migration(
+            "-- two tables; and a semicolon in a comment
+            CREATE TABLE IF NOT EXISTS first (id INT PRIMARY KEY, note TEXT DEFAULT ';');
+            CREATE TABLE IF NOT EXISTS second (id INT PRIMARY KEY);
+            ",
+            Some("DROP TABLE first; DROP TABLE second;"),
+        ));
+        let before = schema(&client).await;
+        assert_eq!(migrate_db(&client, &migrations).await.unwrap(), 1);
+        let tables = schema(&client).await;
+        assert!(tables.iter().any(|(table, _)| table == "first"));
+        assert!(tables.iter().any(|(table, _)| table == "second"));
+        assert_eq!(
+            rollback(&client, &migrations, MIGRATIONS.len() + 1)
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(schema(&client).await, before);
+    }
+
+    #[test]
+    fn test_statements() {
+        assert_eq!(statements("SELECT 1;"), vec!["SELECT 1;"]);
+        assert_eq!(statements("  SELECT 1  \n"), vec!["SELECT 1"]);
+        assert_eq!(
+            statements("SELECT ';'; SELECT \"a;b\";\n"),
+            vec!["SELECT ';';", "SELECT \"a;b\";"]
+        );
+        assert_eq!(
+            statements("-- one; two\nSELECT 1; /* three; */ SELECT 2;\n-- trailing;\n"),
+            vec!["-- one; two\nSELECT 1;", "/* three; */ SELECT 2;"]
+        );
+        assert!(statements("-- nothing to run\n").is_empty());
+        assert_eq!(
+            statements(
+                "CREATE TRIGGER t AFTER DELETE ON a BEGIN DELETE FROM b; DELETE FROM c; END;\n\
+                 create temp trigger u before insert on a begin select 1; end; SELECT 2;"
+            ),
+            vec![
+                "CREATE TRIGGER t AFTER DELETE ON a BEGIN DELETE FROM b; DELETE FROM c; END;",
+                "create temp trigger u before insert on a begin select 1; end;",
+                "SELECT 2;"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_migration_files() {
+        validate(&MIGRATIONS).unwrap();
+
+        let mut swapped = MIGRATIONS.to_vec();
+        swapped.swap(3, 4);
+        assert!(matches!(
+            validate(&swapped),
+            Err(Errors::MigrationOutOfOrder { position: 4, .. })
+        ));
+        let mut gap = MIGRATIONS.to_vec();
+        gap.remove(2);
+        assert!(validate(&gap).is_err());
+
+        // every file in migrations/ is embedded, so none are forgotten
+        let mut files: Vec<String> = std::fs::read_dir("migrations")
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        let mut embedded: Vec<String> = MIGRATIONS
+            .iter()
+            .flat_map(|migration| {
+                let mut names = vec![format!("{}.up.sql", migration.name)];
+                if migration.down.is_some() {
+                    names.push(format!("{}.down.sql", migration.name));
+                }
+                names
+            })
+            .collect();
+        embedded.sort();
+        assert_eq!(files, embedded);
+    }
+
+    #[tokio::test]
+    async fn test_rollback_errors() {
+        let client = libsql_client::Client::in_memory().unwrap();
+        migrate_db(&client, &MIGRATIONS).await.unwrap();
+
+        // nothing is undone if any step in the way can't be
+        let mut migrations = MIGRATIONS.to_vec();
+        migrations.push(migration(
+            "CREATE TABLE IF NOT EXISTS forever (id INT PRIMARY KEY);",
+            None,
+        ));
+        migrations.push(migration(
+            "CREATE TABLE IF NOT EXISTS test_table (id INT PRIMARY KEY);",
+            Some("DROP TABLE test_table;"),
+        ));
+        migrate_db(&client, &migrations).await.unwrap();
+        let forever = MIGRATIONS.len() + 1;
+        assert!(matches!(
+            rollback(&client, &migrations, MIGRATIONS.len()).await,
+            Err(Errors::IrreversibleMigration { id, .. }) if id == forever
+        ));
+        assert_eq!(get_latest(&client).await.unwrap(), migrations.len());
+        assert!(matches!(
+            rollback(&client, &migrations, 0).await,
+            Err(Errors::IrreversibleMigration { .. })
+        ));
+
+        // a down step that fails leaves its migration applied and recorded
+        migrations.push(migration(
+            "CREATE TABLE IF NOT EXISTS sticky (id INT PRIMARY KEY);",
+            Some("DROP TABLE not_sticky;"),
+        ));
+        migrate_db(&client, &migrations).await.unwrap();
+        let id = migrations.len();
+        assert!(matches!(
+            rollback(&client, &migrations, forever).await,
+            Err(Errors::DbMigrationError { id: failed, .. }) if failed == id
+        ));
+        assert_eq!(get_latest(&client).await.unwrap(), id);
+    }
+
+    #[tokio::test]
+    async fn test_failed_migration_turns_foreign_keys_back_on() {
+        let client = libsql_client::Client::in_memory().unwrap();
+        let failing = async { Err::<(), _>(Errors::Default) };
+        assert!(crate::models::without_foreign_keys(&client, failing)
+            .await
+            .is_err());
+        let enabled: i64 = Query::new("PRAGMA foreign_keys;")
+            .fetch_one(&client)
+            .await
+            .unwrap()
+            .unwrap()
+            .try_column("foreign_keys")
+            .unwrap();
+        assert_eq!(enabled, 1);
+    }
+
+    /// `(table, from, to, on_delete)` for each of `table`'s foreign keys
+    async fn foreign_keys(
+        client: &libsql_client::Client,
+        table: &'static str,
+    ) -> Vec<(String, String, String, String)> {
+        Query::new(
+            "SELECT \"table\", \"from\", \"to\", on_delete FROM pragma_foreign_key_list(?)
+            ORDER BY \"from\";",
+        )
+        .bind(table)
+        .fetch(client)
+        .await
+        .unwrap()
+        .iter()
+        .map(|row| {
+            let column = |i| row.try_get::<&str>(i).unwrap().to_string();
+            (column(0), column(1), column(2), column(3))
+        })
+        .collect()
+    }
+
+    #[tokio::test]
+    async fn test_schema_matches_models() {
+        let client = libsql_client::Client::in_memory().unwrap();
+        crate::models::init_db(&client).await.unwrap();
+
+        let columns = |table: &str, tables: &[(String, String)]| -> Vec<String> {
+            tables
+                .iter()
+                .filter(|(t, _)| t == table)
+                .map(|(_, column)| column.clone())
+                .collect()
+        };
+        let tables = schema(&client).await;
+        // what models::keys reads and writes
+        assert_eq!(
+            columns("keys", &tables),
+            [
+                "createdAt",
+                "credentialId",
+                "id",
+                "lastUsedAt",
+                "nickname",
+                "pubkey",
+                "userid"
+            ]
+        );
+        // what models::proposals reads and writes
+        assert_eq!(
+            columns("proposals", &tables),
+            [
+                "authorId",
+                "closesAt",
+                "createdAt",
+                "decisionRule",
+                "description",
+                "id",
+                "quorum",
+                "status",
+                "title",
+                "updatedAt"
+            ]
+        );
+
+        let cascade = |table: &str, from: &str| {
+            (
+                table.to_string(),
+                from.to_string(),
+                "id".to_string(),
+                "CASCADE".to_string(),
+            )
+        };
+        assert_eq!(
+            foreign_keys(&client, "keys").await,
+            [cascade("users", "userid")]
+        );
+        assert_eq!(
+            foreign_keys(&client, "proposals").await,
+            [cascade("users", "authorId")]
+        );
+        assert_eq!(
+            foreign_keys(&client, "votes").await,
+            [
+                cascade("proposals", "proposalId"),
+                cascade("users", "userId")
+            ]
+        );
+        assert_eq!(
+            foreign_keys(&client, "proposal_transitions").await,
+            [cascade("proposals", "proposalId")]
+        );
+        let indexes: Vec<String> = Query::new(
+            "SELECT name FROM sqlite_master WHERE type = 'index' AND sql IS NOT NULL ORDER BY name;",
+        )
+        .fetch(&client)
+        .await
+        .unwrap()
+        .iter()
+        .map(|row| row.try_column::<&str>("name").unwrap().to_string())
+        .collect();
+        assert_eq!(
+            indexes,
+            [
+                "keys_userid",
+                "proposal_transitions_proposal_id",
+                "proposals_author_id",
+                "votes_user_id"
+            ]
+        );
+
+        // and they're enforced: a key needs a user, a vote a proposal and a member, and each
+        // goes when what it points at does. that holds even on a connection that never turned
+        // foreign keys on, as each of a remote database's is
+        Query::new("PRAGMA foreign_keys = OFF;")
+            .execute(&client)
+            .await
+            .unwrap();
+        let argon2 = argon2::Argon2::default();
+        crate::models::users::create_user_with_password(&client, &argon2, "a", "pw", None)
+            .await
+            .unwrap();
+        let userid = crate::models::users::get_user_id(&client, "a")
+            .await
+            .unwrap();
+        let add_key = |userid: String| {
+            Query::new(
+                "INSERT INTO keys (userid, pubkey, credentialId, nickname, createdAt)
+                    VALUES (?, '{}', 'cred', 'key', '');",
+            )
+            .bind(userid)
+        };
+        add_key(userid.urn().to_string())
+            .execute(&client)
+            .await
+            .unwrap();
+        assert!(add_key(uuid::Uuid::new_v4().urn().to_string())
+            .execute(&client)
+            .await
+            .is_err());
+        let proposal = crate::models::proposals::create_proposal(
+            &client,
+            userid,
+            "title",
+            "",
+            &Default::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(proposal, 1);
+        crate::models::proposals::open_proposal(&client, proposal, chrono::Utc::now())
+            .await
+            .unwrap();
+        let add_vote = |proposal: i64, userid: String| {
+            Query::new(
+                "INSERT INTO votes (proposalId, userId, choice, createdAt, updatedAt)
+                    VALUES (?, ?, 'yes', '', '');",
+            )
+            .bind(proposal)
+            .bind(userid)
+        };
+        add_vote(proposal, userid.urn().to_string())
+            .execute(&client)
+            .await
+            .unwrap();
+        assert!(add_vote(proposal + 1, userid.urn().to_string())
+            .execute(&client)
+            .await
+            .is_err());
+        assert!(add_vote(proposal, uuid::Uuid::new_v4().urn().to_string())
+            .execute(&client)
+            .await
+            .is_err());
+        assert!(Query::new(
+            "INSERT INTO proposal_transitions (proposalId, fromStatus, toStatus, at)
+                VALUES (?, 'draft', 'open', '');",
+        )
+        .bind(proposal + 1)
+        .execute(&client)
+        .await
+        .is_err());
+
+        Query::new("DELETE FROM users WHERE id = ?;")
+            .bind(userid.urn().to_string())
+            .execute(&client)
+            .await
+            .unwrap();
+        let remaining = Query::new(
+            "SELECT (SELECT COUNT(*) FROM keys) + (SELECT COUNT(*) FROM proposals)
+                + (SELECT COUNT(*) FROM votes) + (SELECT COUNT(*) FROM proposal_transitions) AS n;",
+        )
+        .fetch_one(&client)
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(remaining.try_column::<i64>("n").unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_schema_fix_keeps_data() {
+        // a database from before keys and proposals were fixed
+        let client = libsql_client::Client::in_memory().unwrap();
+        let before = MIGRATIONS
+            .iter()
+            .position(|migration| migration.name == "0026_fix_keys_and_proposals")
+            .unwrap();
+        migrate_db(&client, &MIGRATIONS[..before]).await.unwrap();
+        batch(
+            &client,
+            [
+                Query::new("INSERT INTO users (id, username) VALUES ('urn:uuid:a', 'a');"),
+                Query::new(
+                    "INSERT INTO keys (userid, pubkey, credentialId, nickname)
+                        VALUES ('urn:uuid:a', 'pk1', 'c1', 'phone'),
+                            ('urn:uuid:a', 'pk2', 'c2', 'laptop');",
+                ),
+                Query::new(
+                    "INSERT INTO proposals (id, title, authorId, status)
+                        VALUES (3, 'kept', 'urn:uuid:a', 'open'),
+                            (7, 'orphaned', 'urn:uuid:gone', 'draft');",
+                ),
+            ],
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            migrate_db(&client, &MIGRATIONS).await.unwrap(),
+            MIGRATIONS.len() - before
+        );
+
+        let keys: Vec<(i64, String)> = Query::new("SELECT id, nickname FROM keys ORDER BY id;")
+            .fetch(&client)
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| {
+                (
+                    row.try_column("id").unwrap(),
+                    row.try_column::<&str>("nickname").unwrap().to_string(),
+                )
+            })
+            .collect();
+        assert_eq!(keys, [(1, "phone".to_string()), (2, "laptop".to_string())]);
+
+        let proposals: Vec<(i64, String, String)> =
+            Query::new("SELECT id, title, status FROM proposals ORDER BY id;")
+                .fetch(&client)
+                .await
+                .unwrap()
+                .iter()
+                .map(|row| {
+                    (
+                        row.try_column("id").unwrap(),
+                        row.try_column::<&str>("title").unwrap().to_string(),
+                        row.try_column::<&str>("status").unwrap().to_string(),
+                    )
+                })
+                .collect();
+        assert_eq!(
+            proposals,
+            [
+                (3, "kept".to_string(), "open".to_string()),
+                (7, "orphaned".to_string(), "draft".to_string())
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_checksums_are_backfilled() {
+        // a database migrated before checksums were recorded
+        let client = libsql_client::Client::in_memory().unwrap();
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            client.execute(migration.up).await.unwrap();
+            // before migrations were files, they had no trailing newline
+            Query::new("INSERT INTO migrations (id, date, query) VALUES (?, ?, ?);")
+                .bind(i as i64 + 1)
+                .bind("2023-01-01T00:00:00+00:00")
+                .bind(migration.up.trim_end())
+                .execute(&client)
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(migrate_db(&client, &MIGRATIONS).await.unwrap(), 0);
+
+        let mut edited = MIGRATIONS.to_vec();
+        edited[1].up = "CREATE TABLE IF NOT EXISTS users (id TEXT PRIMARY KEY);";
+        assert!(matches!(
+            migrate_db(&client, &edited).await,
+            Err(Errors::DbMigrationDrift { changed, .. }) if changed == vec![2]
+        ));
     }
 }
@@ -2,32 +2,22 @@ pub(super) static GET_LATEST_MIGRATION: &str =
     "SELECT id FROM migrations ORDER BY id DESC LIMIT 1;";
 
 pub(super) static ADD_MIGRATION: &str =
-    "INSERT INTO migrations (id, date, query) VALUES (?, ?, ?);";
-
-pub(super) static CREATE_MIGRATIONS_TABLE: &str = "CREATE TABLE IF NOT EXISTS migrations (
-        id INT PRIMARY KEY,
-        date TEXT,
-        query TEXT
-    );";
-
-pub(super) static CREATE_USERS_TABLE: &str = "CREATE TABLE IF NOT EXISTS users (
-        id TEXT PRIMARY KEY,
-        username TEXT UNIQUE,
-        hash TEXT,
-        salt TEXT
-    );";
-
-pub(super) static CREATE_KEYS_TABLE: &str = "CREATE TABLE IF NOT EXISTS keys (
-        id INT PRIMARY KEY,
-        userid TEXT,
-        key TEXT
-    );";
-
-pub(super) static CREATE_PROPOSALS_TABLE: &str = "CREATE TABLE IF NOT EXISTS proposals (
-        id INT PRIMARY KEY,
-        title TEXT,
-        description TEXT,
-        authorId TEXT,
-        createdAt TEXT,
-        updatedAt TEXT
-    );";
+    "INSERT INTO migrations (id, date, query, checksum) VALUES (?, ?, ?, ?);";
+
+pub(super) static HAS_MIGRATION_CHECKSUMS: &str =
+    "SELECT name FROM pragma_table_info('migrations') WHERE name = 'checksum';";
+
+pub(super) static ADD_MIGRATION_CHECKSUMS: &str =
+    "ALTER TABLE migrations ADD COLUMN checksum TEXT;";
+
+pub(super) static GET_MIGRATIONS_WITHOUT_CHECKSUMS: &str =
+    "SELECT id, query FROM migrations WHERE checksum IS NULL;";
+
+pub(super) static SET_MIGRATION_CHECKSUM: &str = "UPDATE migrations SET checksum = ? WHERE id = ?;";
+
+pub(super) static REMOVE_MIGRATION: &str = "DELETE FROM migrations WHERE id = ?;";
+
+pub(super) static GET_MIGRATION_DATES: &str = "SELECT id, date FROM migrations ORDER BY id;";
+
+pub(super) static GET_MIGRATION_CHECKSUMS: &str =
+    "SELECT id, checksum FROM migrations ORDER BY id;";
@@ -1,21 +1,65 @@
+use std::future::Future;
+
 use log::info;
 
-use crate::Error;
+use crate::{errors::Errors, models::db::Query, Error};
 
 pub mod db;
-#[cfg(passkey)]
+pub mod invitations;
+#[cfg(feature = "passkey")]
 pub mod keys;
-mod migrations;
+pub mod login_attempts;
+pub mod migrations;
+pub mod outbox;
+pub mod password_resets;
 pub mod passwords;
+pub mod proposals;
+pub mod repositories;
+pub mod roles;
+pub mod sessions;
+mod tokens;
 pub mod users;
+pub mod votes;
 
-pub(crate) async fn init_db(client: &libsql_client::Client) -> Result<(), Error> {
+/// brings the schema up to date, returning how many migrations were run
+pub(crate) async fn init_db(client: &libsql_client::Client) -> Result<usize, Error> {
     info!("initializing db");
 
-    migrations::migrate_db(client, &migrations::MIGRATIONS.to_vec())
-        .await
-        .expect("error migrating database");
+    migrations::validate(&migrations::MIGRATIONS)?;
+    let ran = without_foreign_keys(
+        client,
+        migrations::migrate_db(client, &migrations::MIGRATIONS),
+    )
+    .await?;
 
     info!("done initializing db");
-    Ok(())
+    Ok(ran)
+}
+
+/// undoes every migration after `to`, returning how many were undone
+pub(crate) async fn rollback_db(client: &libsql_client::Client, to: usize) -> Result<usize, Error> {
+    migrations::validate(&migrations::MIGRATIONS)?;
+    without_foreign_keys(
+        client,
+        migrations::rollback(client, &migrations::MIGRATIONS, to),
+    )
+    .await
+}
+
+/// rebuilding a table copies rows that may not satisfy its new foreign keys, so they're only
+/// enforced once the schema is up to date. the pragma only lasts for a connection, so the
+/// references are also kept by triggers (see migration 0028) that every connection runs
+async fn without_foreign_keys<T>(
+    client: &libsql_client::Client,
+    migrating: impl Future<Output = Result<T, Errors>>,
+) -> Result<T, Error> {
+    Query::new("PRAGMA foreign_keys = OFF;")
+        .execute(client)
+        .await?;
+    // turned back on whether or not migrating worked, so a failure doesn't leave them off
+    let result = migrating.await;
+    Query::new("PRAGMA foreign_keys = ON;")
+        .execute(client)
+        .await?;
+    Ok(result?)
 }
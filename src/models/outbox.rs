@@ -0,0 +1,117 @@
+use axum_sessions::async_session::chrono::{DateTime, Utc};
+use libsql_client::Client;
+
+use crate::{
+    constants::mail::CLAIM_TIMEOUT_SECS, errors::Errors, mailer::Email, models::db::Query,
+};
+
+/// an email waiting in the outbox, with how many times sending it has failed
+#[derive(Debug, Clone)]
+pub struct Queued {
+    pub id: i64,
+    pub email: Email,
+    pub attempts: u32,
+}
+
+impl Queued {
+    fn from_db_row(row: &libsql_client::Row) -> Result<Self, Errors> {
+        let column = |name: &str| {
+            row.try_column::<&str>(name)
+                .map(str::to_string)
+                .map_err(Errors::DbFetchError)
+        };
+        Ok(Self {
+            id: row.try_column("id").map_err(Errors::DbFetchError)?,
+            email: Email {
+                to: column("recipient")?,
+                subject: column("subject")?,
+                text: column("text")?,
+                html: column("html")?,
+            },
+            attempts: row
+                .try_column::<usize>("attempts")
+                .map_err(Errors::DbFetchError)? as u32,
+        })
+    }
+}
+
+/// queues `email` to be sent by the outbox worker, which retries it until it goes through
+pub async fn enqueue(db: &Client, email: &Email, now: DateTime<Utc>) -> Result<(), Errors> {
+    Query::new(
+        "INSERT INTO outbox (recipient, subject, text, html, nextAttemptAt, createdAt)
+            VALUES (?, ?, ?, ?, ?, ?);",
+    )
+    .bind(email.to.as_str())
+    .bind(email.subject.as_str())
+    .bind(email.text.as_str())
+    .bind(email.html.as_str())
+    .bind(now.timestamp())
+    .bind(now.to_rfc3339())
+    .execute(db)
+    .await
+    .map(|_| ())
+}
+
+/// unsent emails whose next attempt is due, and that nobody else is sending
+pub async fn due(db: &Client, now: DateTime<Utc>) -> Result<Vec<Queued>, Errors> {
+    Query::new(
+        "SELECT id, recipient, subject, text, html, attempts FROM outbox
+            WHERE sentAt IS NULL AND nextAttemptAt <= ? AND (claimedAt IS NULL OR claimedAt <= ?)
+            ORDER BY id;",
+    )
+    .bind(now.timestamp())
+    .bind(now.timestamp() - CLAIM_TIMEOUT_SECS)
+    .fetch(db)
+    .await?
+    .iter()
+    .map(Queued::from_db_row)
+    .collect()
+}
+
+/// takes `id` for sending, so other workers leave it alone until it's marked sent or failed.
+/// returns `None` if it's been sent, isn't due, or someone else got to it first
+pub async fn claim(db: &Client, id: i64, now: DateTime<Utc>) -> Result<Option<Queued>, Errors> {
+    let rs = Query::new(
+        "UPDATE outbox SET claimedAt = ?
+            WHERE id = ? AND sentAt IS NULL AND nextAttemptAt <= ?
+                AND (claimedAt IS NULL OR claimedAt <= ?)
+            RETURNING id, recipient, subject, text, html, attempts;",
+    )
+    .bind(now.timestamp())
+    .bind(id)
+    .bind(now.timestamp())
+    .bind(now.timestamp() - CLAIM_TIMEOUT_SECS)
+    .execute(db)
+    .await?;
+    rs.rows.first().map(Queued::from_db_row).transpose()
+}
+
+pub async fn mark_sent(db: &Client, id: i64, now: DateTime<Utc>) -> Result<(), Errors> {
+    Query::new("UPDATE outbox SET sentAt = ?, claimedAt = NULL WHERE id = ?;")
+        .bind(now.to_rfc3339())
+        .bind(id)
+        .execute(db)
+        .await
+        .map(|_| ())
+}
+
+/// records a failed send; without a `retry_at` the email is never tried again
+pub async fn mark_failed(
+    db: &Client,
+    id: i64,
+    attempts: u32,
+    error: &str,
+    retry_at: Option<DateTime<Utc>>,
+) -> Result<(), Errors> {
+    Query::new(
+        "UPDATE outbox SET attempts = ?, lastError = ?, nextAttemptAt = ?, claimedAt = NULL
+            WHERE id = ?;",
+    )
+    .bind(attempts as i64)
+    .bind(error)
+    .bind_opt(retry_at.map(|retry_at| retry_at.timestamp()))
+    .bind(id)
+    .execute(db)
+    .await
+    .map(|_| ())
+}
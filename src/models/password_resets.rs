@@ -0,0 +1,156 @@
+use argon2::Argon2;
+use axum_sessions::async_session::chrono::{DateTime, Duration, Utc};
+use libsql_client::Client;
+use uuid::Uuid;
+
+use super::{
+    db::{batch, Query},
+    passwords,
+    tokens::{generate_token, hash_token},
+};
+use crate::errors::Errors;
+
+/// issues a new reset token for `userid`, valid until `now + ttl`, replacing any unused ones.
+/// returns the plaintext token, which is never stored.
+pub async fn create_reset_token(
+    db: &Client,
+    userid: Uuid,
+    now: DateTime<Utc>,
+    ttl: Duration,
+) -> Result<String, Errors> {
+    let token = generate_token();
+    batch(
+        db,
+        [
+            Query::new("DELETE FROM password_resets WHERE userId = ? AND usedAt IS NULL;")
+                .bind(userid.urn().to_string()),
+            Query::new(
+                "INSERT INTO password_resets (tokenHash, userId, createdAt, expiresAt)
+                    VALUES (?, ?, ?, ?);",
+            )
+            .bind(hash_token(&token))
+            .bind(userid.urn().to_string())
+            .bind(now.to_rfc3339())
+            .bind((now + ttl).timestamp()),
+        ],
+    )
+    .await?;
+    Ok(token)
+}
+
+/// sets a new password with `token` and logs its owner out everywhere, returning whose it was.
+/// the token has to exist, be unexpired and unused; it's used up in the same transaction the
+/// password is changed in, so neither can happen without the other
+pub async fn reset_password(
+    db: &Client,
+    argon2: &Argon2<'_>,
+    token: &str,
+    password: &str,
+    now: DateTime<Utc>,
+) -> Result<Uuid, Errors> {
+    let salt = passwords::generate_salt();
+    let hash = passwords::get_hash(argon2, password, &salt)?;
+    let token_hash = hash_token(token);
+
+    // the later statements only run if the token was used up just now, which changes() still
+    // counts; each one leaves it counting one row for the next
+    let consume = Query::new(
+        "UPDATE password_resets SET usedAt = ?
+            WHERE tokenHash = ? AND usedAt IS NULL AND expiresAt > ?
+            RETURNING userId;",
+    )
+    .bind(now.to_rfc3339())
+    .bind(token_hash.as_str())
+    .bind(now.timestamp());
+    let set_password = Query::new(
+        "UPDATE users SET hash = ?, sessionsRevokedAt = ?
+            WHERE id = (SELECT userId FROM password_resets WHERE tokenHash = ?)
+            AND changes() > 0;",
+    )
+    .bind(hash.to_string())
+    .bind(now.timestamp_millis())
+    .bind(token_hash.as_str());
+    let revoke_sessions = Query::new(
+        "DELETE FROM sessions
+            WHERE userId = (SELECT userId FROM password_resets WHERE tokenHash = ?)
+            AND changes() > 0;",
+    )
+    .bind(token_hash.as_str());
+
+    let results = batch(db, [consume, set_password, revoke_sessions]).await?;
+    let userid: &str = results
+        .first()
+        .and_then(|rs| rs.rows.first())
+        .ok_or(Errors::InvalidResetToken)?
+        .try_column("userId")
+        .map_err(Errors::DbStoredUuidParsingError)?;
+    Uuid::parse_str(userid).map_err(Errors::UuidParsingError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::users;
+
+    #[tokio::test]
+    async fn test_reset_tokens() {
+        let client = libsql_client::Client::in_memory().unwrap();
+        crate::models::init_db(&client).await.unwrap();
+        let argon2 = passwords::hasher(argon2::Params::new(1024, 1, 1, None).unwrap());
+        users::create_user_with_password(&client, &argon2, "test", "old", None)
+            .await
+            .unwrap();
+        let userid = users::get_user_id(&client, "test").await.unwrap();
+        let now = Utc::now();
+        let ttl = Duration::minutes(30);
+        let reset = |token: String, password: &'static str, at: DateTime<Utc>| {
+            let (client, argon2) = (&client, &argon2);
+            async move { reset_password(client, argon2, &token, password, at).await }
+        };
+
+        // tokens are single use, and change the password and revoke sessions as they're used
+        let token = create_reset_token(&client, userid, now, ttl).await.unwrap();
+        assert_eq!(reset(token.clone(), "new", now).await.unwrap(), userid);
+        assert!(
+            passwords::validate_password(&client, &argon2, "test", "new")
+                .await
+                .is_ok()
+        );
+        assert_eq!(
+            users::sessions_revoked_at(&client, userid).await.unwrap(),
+            now.timestamp_millis()
+        );
+        assert!(matches!(
+            reset(token, "newer", now).await,
+            Err(Errors::InvalidResetToken)
+        ));
+
+        // and expire, leaving the password as it was
+        let token = create_reset_token(&client, userid, now, ttl).await.unwrap();
+        assert!(matches!(
+            reset(token, "expired", now + ttl).await,
+            Err(Errors::InvalidResetToken)
+        ));
+        assert!(
+            passwords::validate_password(&client, &argon2, "test", "new")
+                .await
+                .is_ok()
+        );
+
+        // issuing a new token replaces the old one
+        let old = create_reset_token(&client, userid, now, ttl).await.unwrap();
+        let new = create_reset_token(&client, userid, now, ttl).await.unwrap();
+        assert!(reset(old, "old token", now).await.is_err());
+        assert!(reset(new.clone(), "new token", now).await.is_ok());
+
+        // only the hash is stored
+        let rs = client
+            .execute("SELECT tokenHash FROM password_resets;")
+            .await
+            .unwrap();
+        assert!(rs
+            .rows
+            .iter()
+            .all(|row| row.try_column::<&str>("tokenHash").unwrap() != new));
+    }
+}
@@ -1,77 +1,178 @@
-use argon2::{password_hash::SaltString, PasswordHasher};
-use libsql_client::{args, Client, Statement};
-use log::debug;
-use rand::rngs::OsRng;
+use argon2::{
+    password_hash::{self, SaltString},
+    Algorithm, Argon2, Params, PasswordHash, PasswordHasher, PasswordVerifier, Version,
+};
+use libsql_client::Client;
+use log::{debug, error};
+use rand::{distributions::Alphanumeric, rngs::OsRng, Rng};
 use uuid::Uuid;
 
-use crate::errors::Errors;
+use crate::{errors::Errors, models::db::Query};
 
-// returns UUID if valid, error otherwise
-// TODO: clean this up with map/map_err etc
+/// the hasher every new or upgraded password hash is made with
+pub fn hasher(params: Params) -> Argon2<'static> {
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+}
+
+// returns UUID if valid, error otherwise.
+// hashes made with older parameters are upgraded to `argon2`'s after a successful login
 pub(crate) async fn validate_password(
     db: &Client,
+    argon2: &Argon2<'_>,
     username: &str,
     password: &str,
 ) -> Result<Uuid, Errors> {
-    let stmt = Statement::with_args(
-        "SELECT salt FROM users WHERE username = ?;",
-        args!(username),
-    );
-    debug!("stmt: {}", stmt);
-    let rows = match db.execute(stmt).await {
-        Ok(rs) => rs.rows,
-        Err(e) => {
-            return Err(Errors::DbFetchError(e));
-        }
-    };
-    let salt: &str = match rows.first() {
-        Some(row) => row.try_column("salt").unwrap(),
-        None => return Err(Errors::DbUserNotFound(username.to_string())),
-    };
-    let salt = match SaltString::from_b64(salt) {
-        Ok(s) => s,
-        Err(e) => return Err(Errors::DbStoredSaltParsingError(e)),
-    };
-    debug!("got salt({}) for username: {}", salt, username);
-    let hash = match get_hash(password, &salt) {
-        Ok(h) => h,
-        Err(e) => return Err(e),
+    let row = Query::new("SELECT id, hash, disabledAt FROM users WHERE username = ?;")
+        .bind(username)
+        .fetch_one(db)
+        .await?;
+    let Some(row) = row else {
+        hash_anyway(argon2, password);
+        return Err(Errors::DbUserNotFound(username.to_string()));
     };
 
-    let stmt = Statement::with_args(
-        "SELECT id FROM users WHERE username=? AND hash=?;",
-        args!(username, hash.to_string()),
-    );
-    debug!("stmt: {}", stmt);
-    let rows = match db.execute(stmt).await {
-        Ok(rs) => rs.rows,
-        Err(e) => return Err(Errors::DbFetchError(e)),
-    };
-    if rows.len() != 1 {
+    // users who only registered a passkey have no hash
+    let Ok(hash) = row.try_column::<&str>("hash") else {
+        hash_anyway(argon2, password);
         return Err(Errors::DbNoHashMatch(username.to_string()));
+    };
+    let hash = PasswordHash::new(hash).map_err(Errors::DbStoredHashParsingError)?;
+    match argon2.verify_password(password.as_bytes(), &hash) {
+        Ok(()) => (),
+        Err(password_hash::Error::Password) => {
+            return Err(Errors::DbNoHashMatch(username.to_string()))
+        }
+        Err(e) => return Err(Errors::DbStoredHashParsingError(e)),
     }
 
-    let id: &str = rows
-        .first()
-        .ok_or(Errors::DbNoHashMatch(username.to_string()))?
+    // only after the password matched, so this can't be used to find out which accounts exist
+    if row.try_column::<&str>("disabledAt").is_ok() {
+        return Err(Errors::UserDisabled(username.to_string()));
+    }
+
+    let id: &str = row
         .try_column("id")
         .map_err(Errors::DbStoredUuidParsingError)?;
+    let id = Uuid::parse_str(id)?;
 
-    let id = Uuid::parse_str(id).map_err(Errors::UuidParsingError)?;
+    if needs_rehash(argon2, &hash) {
+        debug!("upgrading password hash for {}", username);
+        // the password was already accepted, so a failed upgrade shouldn't fail the login
+        if let Err(e) = set_password(db, argon2, id, password).await {
+            error!("error upgrading password hash for {}: {:?}", username, e);
+        }
+    }
 
     Ok(id)
 }
 
+/// spends as long as checking a password against a current hash would, for logins that have
+/// no hash to check, so how long a login takes doesn't give away which accounts exist
+fn hash_anyway(argon2: &Argon2<'_>, password: &str) {
+    let salt = SaltString::from_b64(DUMMY_SALT).expect("DUMMY_SALT is valid base64");
+    let _ = argon2.hash_password(password.as_bytes(), &salt);
+}
+
+/// hashing with `argon2` costs what verifying a hash made with it does, so the salt can be fixed
+const DUMMY_SALT: &str = "bG9jaHN0ZXBkdW1teXNhbHQ";
+
+/// whether `hash` was made with a different algorithm, version or cost than `argon2` uses now
+fn needs_rehash(argon2: &Argon2<'_>, hash: &PasswordHash<'_>) -> bool {
+    hash.algorithm != Algorithm::Argon2id.ident()
+        || hash.version != Some(Version::V0x13.into())
+        || Params::try_from(hash).map_or(true, |params| {
+            let current = argon2.params();
+            (params.m_cost(), params.t_cost(), params.p_cost())
+                != (current.m_cost(), current.t_cost(), current.p_cost())
+        })
+}
+
+/// replaces `userid`'s password hash
+pub(crate) async fn set_password(
+    db: &Client,
+    argon2: &Argon2<'_>,
+    userid: Uuid,
+    password: &str,
+) -> Result<(), Errors> {
+    let salt = generate_salt();
+    let hash = get_hash(argon2, password, &salt)?;
+    Query::new("UPDATE users SET hash = ? WHERE id = ?;")
+        .bind(hash.to_string())
+        .bind(userid.urn().to_string())
+        .execute(db)
+        .await
+        .map(|_| ())
+}
+
 pub(super) fn get_hash<'a>(
+    argon2: &Argon2<'_>,
     pw: &str,
     salt: &'a SaltString,
-) -> Result<argon2::PasswordHash<'a>, Errors> {
-    match argon2::Argon2::default().hash_password(pw.as_bytes(), salt) {
-        Ok(h) => Ok(h),
-        Err(e) => Err(Errors::GetHashError(e)),
-    }
+) -> Result<PasswordHash<'a>, Errors> {
+    argon2
+        .hash_password(pw.as_bytes(), salt)
+        .map_err(Errors::GetHashError)
 }
 
 pub(super) fn generate_salt() -> SaltString {
     SaltString::generate(&mut OsRng)
 }
+
+/// a random password for an operator to hand over, and for its owner to change
+pub fn generate_password() -> String {
+    OsRng
+        .sample_iter(&Alphanumeric)
+        .take(20)
+        .map(char::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::users;
+
+    fn stored_hash(rs: &libsql_client::ResultSet) -> String {
+        rs.rows[0].try_column::<&str>("hash").unwrap().to_string()
+    }
+
+    #[tokio::test]
+    async fn test_rehash_on_param_change() {
+        let client = libsql_client::Client::in_memory().unwrap();
+        crate::models::init_db(&client).await.unwrap();
+        let cheap = hasher(Params::new(8 * 1024, 1, 1, None).unwrap());
+        let costlier = hasher(Params::new(16 * 1024, 2, 1, None).unwrap());
+
+        users::create_user_with_password(&client, &cheap, "test", "test", None)
+            .await
+            .unwrap();
+        let before = stored_hash(&client.execute("SELECT hash FROM users;").await.unwrap());
+
+        assert!(matches!(
+            validate_password(&client, &costlier, "test", "wrong").await,
+            Err(Errors::DbNoHashMatch(_))
+        ));
+        let id = validate_password(&client, &cheap, "test", "test")
+            .await
+            .unwrap();
+        let unchanged = stored_hash(&client.execute("SELECT hash FROM users;").await.unwrap());
+        assert_eq!(before, unchanged);
+
+        // logging in under new parameters upgrades the stored hash, which still verifies
+        assert_eq!(
+            validate_password(&client, &costlier, "test", "test")
+                .await
+                .unwrap(),
+            id
+        );
+        let upgraded = stored_hash(&client.execute("SELECT hash FROM users;").await.unwrap());
+        assert_ne!(before, upgraded);
+        assert!(!needs_rehash(
+            &costlier,
+            &PasswordHash::new(&upgraded).unwrap()
+        ));
+        assert!(validate_password(&client, &costlier, "test", "test")
+            .await
+            .is_ok());
+    }
+}
@@ -0,0 +1,481 @@
+use axum_sessions::async_session::chrono;
+use libsql_client::Client;
+use log::info;
+use serde::Serialize;
+use uuid::Uuid;
+
+use crate::{
+    constants::proposals::CLOSE_ATTEMPTS,
+    errors::Errors,
+    models::{
+        db::{batch, Query},
+        votes::{self, DecisionRule},
+    },
+};
+
+mod lifecycle;
+
+pub use lifecycle::ProposalStatus;
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Proposal {
+    pub id: i64,
+    pub title: String,
+    pub description: String,
+    pub author_id: Uuid,
+    pub author_name: Option<String>,
+    pub created_at: String,
+    pub updated_at: String,
+    pub decision_rule: DecisionRule,
+    pub status: ProposalStatus,
+    pub closes_at: Option<String>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Transition {
+    pub from: ProposalStatus,
+    pub to: ProposalStatus,
+    pub at: String,
+}
+
+impl Proposal {
+    fn from_db_row(row: &libsql_client::Row) -> Result<Self, Errors> {
+        let author_id: &str = row
+            .try_column("authorId")
+            .map_err(Errors::DbStoredProposalParsingError)?;
+
+        Ok(Self {
+            id: row
+                .try_column("id")
+                .map_err(Errors::DbStoredProposalParsingError)?,
+            title: row
+                .try_column::<&str>("title")
+                .map_err(Errors::DbStoredProposalParsingError)?
+                .to_string(),
+            description: row
+                .try_column::<&str>("description")
+                .map_err(Errors::DbStoredProposalParsingError)?
+                .to_string(),
+            author_id: Uuid::parse_str(author_id)?,
+            author_name: row.try_column::<&str>("username").ok().map(str::to_string),
+            created_at: row
+                .try_column::<&str>("createdAt")
+                .map_err(Errors::DbStoredProposalParsingError)?
+                .to_string(),
+            updated_at: row
+                .try_column::<&str>("updatedAt")
+                .map_err(Errors::DbStoredProposalParsingError)?
+                .to_string(),
+            decision_rule: DecisionRule {
+                threshold: row
+                    .try_column::<&str>("decisionRule")
+                    .map_err(Errors::DbStoredProposalParsingError)?
+                    .parse()?,
+                quorum: row
+                    .try_column::<usize>("quorum")
+                    .map_err(Errors::DbStoredProposalParsingError)? as u32,
+            },
+            status: row
+                .try_column::<&str>("status")
+                .map_err(Errors::DbStoredProposalParsingError)?
+                .parse()?,
+            closes_at: row.try_column::<&str>("closesAt").ok().map(str::to_string),
+        })
+    }
+
+    /// whether votes can be cast on this proposal at `now`
+    pub fn is_open(&self, now: chrono::DateTime<chrono::Utc>) -> bool {
+        self.status == ProposalStatus::Open && !self.is_past_deadline(now)
+    }
+
+    fn is_past_deadline(&self, now: chrono::DateTime<chrono::Utc>) -> bool {
+        self.closes_at
+            .as_deref()
+            .and_then(|closes_at| chrono::DateTime::parse_from_rfc3339(closes_at).ok())
+            .map(|closes_at| closes_at <= now)
+            .unwrap_or(false)
+    }
+}
+
+/// every proposal column plus its author's name, followed by `$rest`.
+/// a macro so the result is still a literal, and can go straight into a `Query`
+macro_rules! select_proposals {
+    ($rest:literal) => {
+        concat!(
+            "SELECT p.id, p.title, p.description, p.authorId, p.createdAt, p.updatedAt,
+                p.decisionRule, p.quorum, p.status, p.closesAt, u.username
+            FROM proposals p LEFT JOIN users u ON u.id = p.authorId ",
+            $rest
+        )
+    };
+}
+
+pub async fn create_proposal(
+    db: &Client,
+    author_id: Uuid,
+    title: &str,
+    description: &str,
+    decision_rule: &DecisionRule,
+) -> Result<i64, Errors> {
+    let now = chrono::offset::Utc::now().to_rfc3339();
+    let rs = Query::new(
+        "INSERT INTO proposals
+                (title, description, authorId, createdAt, updatedAt, decisionRule, quorum)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            RETURNING id;",
+    )
+    .bind(title)
+    .bind(description)
+    .bind(author_id.urn().to_string())
+    .bind(now.as_str())
+    .bind(now.as_str())
+    .bind(decision_rule.threshold.to_string())
+    .bind(i64::from(decision_rule.quorum))
+    .execute(db)
+    .await?;
+    rs.rows
+        .first()
+        .ok_or(Errors::Default)?
+        .try_column("id")
+        .map_err(Errors::DbStoredProposalParsingError)
+}
+
+pub async fn update_proposal(
+    db: &Client,
+    id: i64,
+    title: &str,
+    description: &str,
+    decision_rule: &DecisionRule,
+) -> Result<(), Errors> {
+    let now = chrono::offset::Utc::now().to_rfc3339();
+    let rs = Query::new(
+        "UPDATE proposals SET title = ?, description = ?, decisionRule = ?, quorum = ?, updatedAt = ? WHERE id = ?;",
+    )
+    .bind(title)
+    .bind(description)
+    .bind(decision_rule.threshold.to_string())
+    .bind(i64::from(decision_rule.quorum))
+    .bind(now)
+    .bind(id)
+    .execute(db)
+    .await?;
+    if rs.rows_affected == 0 {
+        return Err(Errors::DbProposalNotFound(id));
+    }
+    Ok(())
+}
+
+pub async fn get_proposal(db: &Client, id: i64) -> Result<Proposal, Errors> {
+    let row = Query::new(select_proposals!("WHERE p.id = ?;"))
+        .bind(id)
+        .fetch_one(db)
+        .await?;
+    match row {
+        Some(row) => Proposal::from_db_row(&row),
+        None => Err(Errors::DbProposalNotFound(id)),
+    }
+}
+
+pub async fn all_proposals(db: &Client) -> Result<Vec<Proposal>, Errors> {
+    Query::new(select_proposals!("ORDER BY p.id DESC;"))
+        .fetch(db)
+        .await?
+        .iter()
+        .map(Proposal::from_db_row)
+        .collect()
+}
+
+pub async fn get_transitions(db: &Client, id: i64) -> Result<Vec<Transition>, Errors> {
+    Query::new(
+        "SELECT fromStatus, toStatus, at FROM proposal_transitions
+            WHERE proposalId = ? ORDER BY rowid ASC;",
+    )
+    .bind(id)
+    .fetch(db)
+    .await?
+    .iter()
+    .map(|row| {
+        let column = |name| {
+            row.try_column::<&str>(name)
+                .map_err(Errors::DbStoredProposalParsingError)
+        };
+        Ok(Transition {
+            from: column("fromStatus")?.parse()?,
+            to: column("toStatus")?.parse()?,
+            at: column("at")?.to_string(),
+        })
+    })
+    .collect()
+}
+
+/// moves a proposal to `to`, if the lifecycle allows it from its current status,
+/// and records when it happened.
+async fn transition_proposal(
+    db: &Client,
+    proposal: &Proposal,
+    to: ProposalStatus,
+    closes_at: Option<String>,
+) -> Result<(), Errors> {
+    proposal.status.transition(to)?;
+
+    let now = chrono::offset::Utc::now().to_rfc3339();
+    let closes_at = closes_at.or_else(|| proposal.closes_at.clone());
+    let results = batch(
+        db,
+        transition_queries(proposal.id, proposal.status, to, closes_at, &now, false),
+    )
+    .await?;
+    if results.first().map(|rs| rs.rows_affected) == Some(0) {
+        return Err(Errors::IllegalProposalTransition {
+            from: proposal.status,
+            to,
+        });
+    }
+    Ok(())
+}
+
+/// the update moving a proposal from `from` to `to`, and the record of it. `chained` only
+/// lets the update happen if the statement before it in the batch changed something
+fn transition_queries(
+    id: i64,
+    from: ProposalStatus,
+    to: ProposalStatus,
+    closes_at: Option<String>,
+    now: &str,
+    chained: bool,
+) -> [Query; 2] {
+    let update = if chained {
+        "UPDATE proposals SET status = ?, closesAt = ?, updatedAt = ?
+            WHERE id = ? AND status = ? AND changes() > 0;"
+    } else {
+        "UPDATE proposals SET status = ?, closesAt = ?, updatedAt = ? WHERE id = ? AND status = ?;"
+    };
+    let update = Query::new(update)
+        .bind(to.to_string())
+        .bind_opt(closes_at)
+        .bind(now)
+        .bind(id)
+        .bind(from.to_string());
+    // only record the transition if the update above actually happened,
+    // i.e. nobody else moved the proposal on in the meantime
+    let record = Query::new(
+        "INSERT INTO proposal_transitions (proposalId, fromStatus, toStatus, at)
+            SELECT ?, ?, ?, ? WHERE changes() > 0;",
+    )
+    .bind(id)
+    .bind(from.to_string())
+    .bind(to.to_string())
+    .bind(now);
+    [update, record]
+}
+
+pub async fn open_proposal(
+    db: &Client,
+    id: i64,
+    closes_at: chrono::DateTime<chrono::Utc>,
+) -> Result<(), Errors> {
+    let proposal = get_proposal(db, id).await?;
+    transition_proposal(
+        db,
+        &proposal,
+        ProposalStatus::Open,
+        Some(closes_at.to_rfc3339()),
+    )
+    .await
+}
+
+pub async fn withdraw_proposal(db: &Client, id: i64) -> Result<(), Errors> {
+    let proposal = get_proposal(db, id).await?;
+    transition_proposal(db, &proposal, ProposalStatus::Withdrawn, None).await
+}
+
+/// closes voting on an open proposal and resolves it to passed or failed
+/// according to its decision rule. both happen in one transaction, so a proposal
+/// is never left closed but unresolved, and only if the votes are still the ones
+/// that were tallied; if one came in meanwhile, it's tallied again.
+pub async fn close_proposal(db: &Client, id: i64) -> Result<ProposalStatus, Errors> {
+    for _ in 0..CLOSE_ATTEMPTS {
+        let proposal = get_proposal(db, id).await?;
+        proposal.status.transition(ProposalStatus::Closed)?;
+        let tally = votes::tally_proposal(db, id, &proposal.decision_rule).await?;
+        let resolution = ProposalStatus::resolve(&tally);
+        ProposalStatus::Closed.transition(resolution)?;
+
+        let now = chrono::offset::Utc::now().to_rfc3339();
+        let unchanged = votes::unchanged_since(id, &tally);
+        let close = transition_queries(
+            id,
+            proposal.status,
+            ProposalStatus::Closed,
+            proposal.closes_at.clone(),
+            &now,
+            true,
+        );
+        // changes() still counts the record of closing, so resolving only follows it
+        let resolve = transition_queries(
+            id,
+            ProposalStatus::Closed,
+            resolution,
+            proposal.closes_at,
+            &now,
+            true,
+        );
+        let queries = std::iter::once(unchanged).chain(close).chain(resolve);
+        let results = batch(db, queries).await?;
+        if results.first().map(|rs| rs.rows_affected) == Some(0) {
+            continue;
+        }
+        if results.get(1).map(|rs| rs.rows_affected) == Some(0) {
+            return Err(Errors::IllegalProposalTransition {
+                from: proposal.status,
+                to: ProposalStatus::Closed,
+            });
+        }
+        return Ok(resolution);
+    }
+    Err(Errors::VotesChanged(id))
+}
+
+/// closes every open proposal whose voting deadline has passed by `now`.
+pub async fn close_expired_proposals(
+    db: &Client,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Result<usize, Errors> {
+    let rows = Query::new(select_proposals!(
+        "WHERE p.status = 'open' AND p.closesAt IS NOT NULL;"
+    ))
+    .fetch(db)
+    .await?;
+    let mut closed = 0;
+    for row in rows.iter() {
+        let proposal = Proposal::from_db_row(row)?;
+        if proposal.is_past_deadline(now) {
+            let resolution = close_proposal(db, proposal.id).await?;
+            info!(
+                "voting closed on proposal {}, which {}",
+                proposal.id, resolution
+            );
+            closed += 1;
+        }
+    }
+    Ok(closed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{Clock, FakeClock};
+
+    /// proposals need an author that exists
+    async fn create_author(client: &Client) -> Uuid {
+        let argon2 = argon2::Argon2::default();
+        crate::models::users::create_user_with_password(client, &argon2, "author", "pw", None)
+            .await
+            .unwrap();
+        crate::models::users::get_user_id(client, "author")
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_proposal_crud() {
+        let client = libsql_client::Client::in_memory().unwrap();
+        crate::models::init_db(&client).await.unwrap();
+        let author = create_author(&client).await;
+        let rule = DecisionRule::default();
+
+        let id = create_proposal(&client, author, "title", "description", &rule)
+            .await
+            .unwrap();
+        assert_eq!(id, 1);
+        assert_eq!(
+            create_proposal(&client, author, "second", "", &rule)
+                .await
+                .unwrap(),
+            2
+        );
+
+        let edited = DecisionRule {
+            threshold: votes::Threshold::Consensus,
+            quorum: 50,
+        };
+        update_proposal(&client, id, "new title", "new description", &edited)
+            .await
+            .unwrap();
+        let proposal = get_proposal(&client, id).await.unwrap();
+        assert_eq!(proposal.title, "new title");
+        assert_eq!(proposal.description, "new description");
+        assert_eq!(proposal.author_id, author);
+        assert_eq!(proposal.decision_rule, edited);
+        assert_eq!(proposal.status, ProposalStatus::Draft);
+
+        assert_eq!(all_proposals(&client).await.unwrap().len(), 2);
+        assert!(matches!(
+            get_proposal(&client, 3).await,
+            Err(Errors::DbProposalNotFound(3))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_proposal_lifecycle() {
+        let client = libsql_client::Client::in_memory().unwrap();
+        crate::models::init_db(&client).await.unwrap();
+        let author = create_author(&client).await;
+        let rule = DecisionRule::default();
+        let id = create_proposal(&client, author, "title", "", &rule)
+            .await
+            .unwrap();
+
+        assert!(matches!(
+            close_proposal(&client, id).await,
+            Err(Errors::IllegalProposalTransition {
+                from: ProposalStatus::Draft,
+                to: ProposalStatus::Closed
+            })
+        ));
+
+        let clock = FakeClock::new(chrono::offset::Utc::now());
+        open_proposal(&client, id, clock.now() + chrono::Duration::days(1))
+            .await
+            .unwrap();
+        votes::cast_vote(&client, id, author, votes::VoteChoice::Yes, clock.now())
+            .await
+            .unwrap();
+        assert_eq!(
+            close_expired_proposals(&client, clock.now()).await.unwrap(),
+            0
+        );
+
+        clock.advance(chrono::Duration::days(1));
+        assert!(!get_proposal(&client, id)
+            .await
+            .unwrap()
+            .is_open(clock.now()));
+        assert!(matches!(
+            votes::cast_vote(&client, id, author, votes::VoteChoice::No, clock.now()).await,
+            Err(Errors::ProposalNotInStatus(_, ProposalStatus::Open))
+        ));
+
+        assert_eq!(
+            close_expired_proposals(&client, clock.now()).await.unwrap(),
+            1
+        );
+        let proposal = get_proposal(&client, id).await.unwrap();
+        assert_eq!(proposal.status, ProposalStatus::Passed);
+
+        let transitions = get_transitions(&client, id).await.unwrap();
+        let transitions: Vec<_> = transitions.iter().map(|t| (t.from, t.to)).collect();
+        assert_eq!(
+            transitions,
+            vec![
+                (ProposalStatus::Draft, ProposalStatus::Open),
+                (ProposalStatus::Open, ProposalStatus::Closed),
+                (ProposalStatus::Closed, ProposalStatus::Passed),
+            ]
+        );
+        assert_eq!(
+            close_expired_proposals(&client, clock.now()).await.unwrap(),
+            0
+        );
+    }
+}
@@ -0,0 +1,112 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    errors::Errors,
+    models::votes::{Outcome, Tally},
+};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ProposalStatus {
+    Draft,
+    Open,
+    Closed,
+    Passed,
+    Failed,
+    Withdrawn,
+}
+
+impl ProposalStatus {
+    /// every legal move through the lifecycle:
+    /// draft -> open -> closed -> passed/failed, and draft/open -> withdrawn
+    pub fn can_transition_to(self, to: ProposalStatus) -> bool {
+        use ProposalStatus::*;
+        matches!(
+            (self, to),
+            (Draft, Open)
+                | (Draft, Withdrawn)
+                | (Open, Closed)
+                | (Open, Withdrawn)
+                | (Closed, Passed)
+                | (Closed, Failed)
+        )
+    }
+
+    pub fn transition(self, to: ProposalStatus) -> Result<ProposalStatus, Errors> {
+        if self.can_transition_to(to) {
+            Ok(to)
+        } else {
+            Err(Errors::IllegalProposalTransition { from: self, to })
+        }
+    }
+
+    /// the status a closed proposal resolves to, given its final tally
+    pub fn resolve(tally: &Tally) -> ProposalStatus {
+        match tally.outcome {
+            Outcome::Passed => ProposalStatus::Passed,
+            Outcome::Failed | Outcome::Blocked | Outcome::NoQuorum => ProposalStatus::Failed,
+        }
+    }
+}
+
+impl FromStr for ProposalStatus {
+    type Err = Errors;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "draft" => Ok(ProposalStatus::Draft),
+            "open" => Ok(ProposalStatus::Open),
+            "closed" => Ok(ProposalStatus::Closed),
+            "passed" => Ok(ProposalStatus::Passed),
+            "failed" => Ok(ProposalStatus::Failed),
+            "withdrawn" => Ok(ProposalStatus::Withdrawn),
+            _ => Err(Errors::ProposalStatusParseError(s.to_string())),
+        }
+    }
+}
+
+impl Display for ProposalStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ProposalStatus::Draft => "draft",
+            ProposalStatus::Open => "open",
+            ProposalStatus::Closed => "closed",
+            ProposalStatus::Passed => "passed",
+            ProposalStatus::Failed => "failed",
+            ProposalStatus::Withdrawn => "withdrawn",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ProposalStatus::*;
+
+    #[test]
+    fn test_transitions() {
+        assert_eq!(Draft.transition(Open).unwrap(), Open);
+        assert_eq!(Open.transition(Closed).unwrap(), Closed);
+        assert_eq!(Closed.transition(Passed).unwrap(), Passed);
+        assert_eq!(Closed.transition(Failed).unwrap(), Failed);
+        assert_eq!(Open.transition(Withdrawn).unwrap(), Withdrawn);
+
+        for (from, to) in [
+            (Draft, Closed),
+            (Draft, Passed),
+            (Open, Passed),
+            (Open, Draft),
+            (Closed, Open),
+            (Closed, Withdrawn),
+            (Passed, Failed),
+            (Withdrawn, Open),
+        ] {
+            assert!(matches!(
+                from.transition(to),
+                Err(Errors::IllegalProposalTransition { .. })
+            ));
+        }
+    }
+}
@@ -0,0 +1,275 @@
+use argon2::Argon2;
+use axum_sessions::async_session::{
+    async_trait,
+    chrono::{DateTime, Duration, Utc},
+};
+use uuid::Uuid;
+#[cfg(feature = "passkey")]
+use webauthn_rs::prelude::{AuthenticationResult, Passkey};
+
+#[cfg(feature = "passkey")]
+use crate::models::keys::Key;
+use crate::{
+    errors::Errors,
+    mailer::Email,
+    models::{
+        login_attempts::{Lockout, Subject},
+        outbox::Queued,
+        proposals::{Proposal, ProposalStatus, Transition},
+        roles::Role,
+        users::User,
+        votes::{DecisionRule, Tally, VoteChoice},
+    },
+};
+
+mod libsql;
+#[cfg(test)]
+mod memory;
+
+pub use libsql::LibsqlRepository;
+#[cfg(test)]
+pub use memory::MemoryRepository;
+
+/// accounts, their passwords and roles, and when their sessions were last revoked
+#[async_trait]
+pub trait UserRepository: Send + Sync {
+    /// creates a passkey-only account, returning its id
+    #[cfg(feature = "passkey")]
+    async fn create_user(&self, username: &str) -> Result<Uuid, Errors>;
+
+    async fn create_user_with_password(
+        &self,
+        argon2: &Argon2<'_>,
+        username: &str,
+        password: &str,
+        email: Option<&str>,
+    ) -> Result<(), Errors>;
+
+    /// the id of the user `password` belongs to, if it's theirs
+    async fn validate_password(
+        &self,
+        argon2: &Argon2<'_>,
+        username: &str,
+        password: &str,
+    ) -> Result<Uuid, Errors>;
+
+    async fn set_password(
+        &self,
+        argon2: &Argon2<'_>,
+        userid: Uuid,
+        password: &str,
+    ) -> Result<(), Errors>;
+
+    async fn get_user(&self, userid: Uuid) -> Result<User, Errors>;
+
+    async fn get_user_id(&self, username: &str) -> Result<Uuid, Errors>;
+
+    /// the address notifications are emailed to, if the user gave one
+    async fn get_email(&self, username: &str) -> Result<Option<String>, Errors>;
+
+    async fn all_users(&self) -> Result<Vec<User>, Errors>;
+
+    /// changes `username`'s role, refusing to demote the only remaining admin
+    async fn set_role(&self, username: &str, role: Role) -> Result<(), Errors>;
+
+    /// logs `userid` out everywhere
+    async fn revoke_sessions(&self, userid: Uuid) -> Result<(), Errors>;
+
+    /// unix milliseconds, or 0 if their sessions have never been revoked
+    async fn sessions_revoked_at(&self, userid: Uuid) -> Result<i64, Errors>;
+}
+
+/// the passkeys each user can log in with
+#[cfg(feature = "passkey")]
+#[async_trait]
+pub trait KeyRepository: Send + Sync {
+    async fn add_key(&self, userid: Uuid, key: Passkey, nickname: &str) -> Result<(), Errors>;
+
+    async fn get_keys(&self, userid: Uuid) -> Result<Vec<Passkey>, Errors>;
+
+    async fn list_keys(&self, userid: Uuid) -> Result<Vec<Key>, Errors>;
+
+    async fn rename_key(
+        &self,
+        userid: Uuid,
+        credential_id: &str,
+        nickname: &str,
+    ) -> Result<(), Errors>;
+
+    /// deletes one of `userid`'s passkeys, unless it's the only way they have left to log in
+    async fn remove_key(&self, userid: Uuid, credential_id: &str) -> Result<(), Errors>;
+
+    async fn record_key_use(
+        &self,
+        userid: Uuid,
+        auth_result: &AuthenticationResult,
+    ) -> Result<(), Errors>;
+}
+
+/// proposals, their lifecycle, and the votes cast on them
+#[async_trait]
+pub trait ProposalRepository: Send + Sync {
+    async fn create_proposal(
+        &self,
+        author_id: Uuid,
+        title: &str,
+        description: &str,
+        decision_rule: &DecisionRule,
+    ) -> Result<i64, Errors>;
+
+    async fn update_proposal(
+        &self,
+        id: i64,
+        title: &str,
+        description: &str,
+        decision_rule: &DecisionRule,
+    ) -> Result<(), Errors>;
+
+    async fn get_proposal(&self, id: i64) -> Result<Proposal, Errors>;
+
+    /// newest first
+    async fn all_proposals(&self) -> Result<Vec<Proposal>, Errors>;
+
+    /// oldest first
+    async fn get_transitions(&self, id: i64) -> Result<Vec<Transition>, Errors>;
+
+    async fn open_proposal(&self, id: i64, closes_at: DateTime<Utc>) -> Result<(), Errors>;
+
+    async fn withdraw_proposal(&self, id: i64) -> Result<(), Errors>;
+
+    /// closes voting and resolves the proposal to passed or failed
+    async fn close_proposal(&self, id: i64) -> Result<ProposalStatus, Errors>;
+
+    /// closes every open proposal whose deadline has passed, returning how many were closed
+    async fn close_expired_proposals(&self, now: DateTime<Utc>) -> Result<usize, Errors>;
+
+    /// records `userid`'s vote, replacing any earlier one on the same proposal,
+    /// as long as it's still open at `now`
+    async fn cast_vote(
+        &self,
+        id: i64,
+        userid: Uuid,
+        choice: VoteChoice,
+        now: DateTime<Utc>,
+    ) -> Result<(), Errors>;
+
+    async fn get_user_vote(&self, id: i64, userid: Uuid) -> Result<Option<VoteChoice>, Errors>;
+
+    async fn tally_proposal(&self, id: i64, rule: &DecisionRule) -> Result<Tally, Errors>;
+}
+
+/// invitations to register, from being sent until someone registers with one
+#[async_trait]
+pub trait InvitationRepository: Send + Sync {
+    /// returns the token for the invitation link; only its hash is stored
+    async fn create_invitation(
+        &self,
+        invited_by: Uuid,
+        email: &str,
+        now: DateTime<Utc>,
+        ttl: Duration,
+    ) -> Result<String, Errors>;
+
+    /// the address an unused, unexpired invitation was sent to
+    async fn get_invitation_email(&self, token: &str, now: DateTime<Utc>)
+        -> Result<String, Errors>;
+
+    /// uses up the invitation, returning the address it was sent to
+    async fn consume_invitation(&self, token: &str, now: DateTime<Utc>) -> Result<String, Errors>;
+
+    /// makes a consumed invitation usable again, when registering with it failed
+    async fn release_invitation(&self, token: &str) -> Result<(), Errors>;
+}
+
+/// failed logins and password reset requests, and the lockouts they lead to
+#[async_trait]
+pub trait LoginAttemptRepository: Send + Sync {
+    /// when the latest lockout among `subjects` ends, if any of them is locked out
+    async fn retry_at(
+        &self,
+        subjects: &[Subject<'_>],
+        now: DateTime<Utc>,
+    ) -> Result<Option<DateTime<Utc>>, Errors>;
+
+    async fn record_failure(
+        &self,
+        subjects: &[Subject<'_>],
+        now: DateTime<Utc>,
+    ) -> Result<(), Errors>;
+
+    /// forgets `subject`'s failures, after it gets something right
+    async fn clear(&self, subject: Subject<'_>) -> Result<(), Errors>;
+
+    /// newest first
+    async fn lockouts(&self) -> Result<Vec<Lockout>, Errors>;
+}
+
+/// tokens for resetting a forgotten password
+#[async_trait]
+pub trait PasswordResetRepository: Send + Sync {
+    /// returns the token for the reset link; only its hash is stored
+    async fn create_reset_token(
+        &self,
+        userid: Uuid,
+        now: DateTime<Utc>,
+        ttl: Duration,
+    ) -> Result<String, Errors>;
+
+    /// uses up `token` to set its user's password, logging them out everywhere, and
+    /// returns whose password it was
+    async fn reset_password(
+        &self,
+        argon2: &Argon2<'_>,
+        token: &str,
+        password: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Uuid, Errors>;
+}
+
+/// emails waiting to be sent, and what happened when they were
+#[async_trait]
+pub trait OutboxRepository: Send + Sync {
+    async fn enqueue(&self, email: &Email, now: DateTime<Utc>) -> Result<(), Errors>;
+
+    /// unsent emails whose next attempt is due and that nobody is sending already
+    async fn due(&self, now: DateTime<Utc>) -> Result<Vec<Queued>, Errors>;
+
+    /// takes the email for sending, or `None` if someone else already has
+    async fn claim(&self, id: i64, now: DateTime<Utc>) -> Result<Option<Queued>, Errors>;
+
+    async fn mark_sent(&self, id: i64, now: DateTime<Utc>) -> Result<(), Errors>;
+
+    /// records a failed attempt; without a `retry_at` the email is given up on
+    async fn mark_failed(
+        &self,
+        id: i64,
+        attempts: u32,
+        error: &str,
+        retry_at: Option<DateTime<Utc>>,
+    ) -> Result<(), Errors>;
+}
+
+/// every repository at once, for a backend that keeps all of the app's data
+#[cfg(feature = "passkey")]
+pub trait Repository:
+    UserRepository
+    + KeyRepository
+    + ProposalRepository
+    + InvitationRepository
+    + LoginAttemptRepository
+    + PasswordResetRepository
+    + OutboxRepository
+{
+}
+
+/// every repository at once, for a backend that keeps all of the app's data
+#[cfg(not(feature = "passkey"))]
+pub trait Repository:
+    UserRepository
+    + ProposalRepository
+    + InvitationRepository
+    + LoginAttemptRepository
+    + PasswordResetRepository
+    + OutboxRepository
+{
+}
@@ -0,0 +1,325 @@
+use std::sync::Arc;
+
+use argon2::Argon2;
+use axum_sessions::async_session::{
+    async_trait,
+    chrono::{DateTime, Duration, Utc},
+};
+use libsql_client::Client;
+use uuid::Uuid;
+#[cfg(feature = "passkey")]
+use webauthn_rs::prelude::{AuthenticationResult, Passkey};
+
+#[cfg(feature = "passkey")]
+use super::KeyRepository;
+use super::{
+    InvitationRepository, LoginAttemptRepository, OutboxRepository, PasswordResetRepository,
+    ProposalRepository, Repository, UserRepository,
+};
+#[cfg(feature = "passkey")]
+use crate::models::keys::{self, Key};
+use crate::{
+    errors::Errors,
+    mailer::Email,
+    models::{
+        invitations,
+        login_attempts::{self, Lockout, Subject},
+        outbox::{self, Queued},
+        password_resets, passwords,
+        proposals::{self, Proposal, ProposalStatus, Transition},
+        roles::Role,
+        users::{self, User},
+        votes::{self, DecisionRule, Tally, VoteChoice},
+    },
+};
+
+/// every repository, backed by the models' queries against one libsql database
+#[derive(Clone)]
+pub struct LibsqlRepository {
+    db: Arc<Client>,
+}
+
+impl LibsqlRepository {
+    pub fn new(db: Arc<Client>) -> Self {
+        Self { db }
+    }
+}
+
+impl Repository for LibsqlRepository {}
+
+#[async_trait]
+impl UserRepository for LibsqlRepository {
+    #[cfg(feature = "passkey")]
+    async fn create_user(&self, username: &str) -> Result<Uuid, Errors> {
+        users::create_user(&self.db, username).await
+    }
+
+    async fn create_user_with_password(
+        &self,
+        argon2: &Argon2<'_>,
+        username: &str,
+        password: &str,
+        email: Option<&str>,
+    ) -> Result<(), Errors> {
+        users::create_user_with_password(&self.db, argon2, username, password, email).await
+    }
+
+    async fn validate_password(
+        &self,
+        argon2: &Argon2<'_>,
+        username: &str,
+        password: &str,
+    ) -> Result<Uuid, Errors> {
+        passwords::validate_password(&self.db, argon2, username, password).await
+    }
+
+    async fn set_password(
+        &self,
+        argon2: &Argon2<'_>,
+        userid: Uuid,
+        password: &str,
+    ) -> Result<(), Errors> {
+        passwords::set_password(&self.db, argon2, userid, password).await
+    }
+
+    async fn get_user(&self, userid: Uuid) -> Result<User, Errors> {
+        users::get_user(&self.db, userid).await
+    }
+
+    async fn get_user_id(&self, username: &str) -> Result<Uuid, Errors> {
+        users::get_user_id(&self.db, username).await
+    }
+
+    async fn get_email(&self, username: &str) -> Result<Option<String>, Errors> {
+        users::get_email(&self.db, username).await
+    }
+
+    async fn all_users(&self) -> Result<Vec<User>, Errors> {
+        users::all_users(&self.db).await
+    }
+
+    async fn set_role(&self, username: &str, role: Role) -> Result<(), Errors> {
+        users::set_role(&self.db, username, role).await
+    }
+
+    async fn revoke_sessions(&self, userid: Uuid) -> Result<(), Errors> {
+        users::revoke_sessions(&self.db, userid).await
+    }
+
+    async fn sessions_revoked_at(&self, userid: Uuid) -> Result<i64, Errors> {
+        users::sessions_revoked_at(&self.db, userid).await
+    }
+}
+
+#[cfg(feature = "passkey")]
+#[async_trait]
+impl KeyRepository for LibsqlRepository {
+    async fn add_key(&self, userid: Uuid, key: Passkey, nickname: &str) -> Result<(), Errors> {
+        keys::add_key(&self.db, userid, key, nickname).await
+    }
+
+    async fn get_keys(&self, userid: Uuid) -> Result<Vec<Passkey>, Errors> {
+        keys::get_keys(&self.db, userid).await
+    }
+
+    async fn list_keys(&self, userid: Uuid) -> Result<Vec<Key>, Errors> {
+        keys::list_keys(&self.db, userid).await
+    }
+
+    async fn rename_key(
+        &self,
+        userid: Uuid,
+        credential_id: &str,
+        nickname: &str,
+    ) -> Result<(), Errors> {
+        keys::rename_key(&self.db, userid, credential_id, nickname).await
+    }
+
+    async fn remove_key(&self, userid: Uuid, credential_id: &str) -> Result<(), Errors> {
+        keys::remove_key(&self.db, userid, credential_id).await
+    }
+
+    async fn record_key_use(
+        &self,
+        userid: Uuid,
+        auth_result: &AuthenticationResult,
+    ) -> Result<(), Errors> {
+        keys::record_key_use(&self.db, userid, auth_result).await
+    }
+}
+
+#[async_trait]
+impl ProposalRepository for LibsqlRepository {
+    async fn create_proposal(
+        &self,
+        author_id: Uuid,
+        title: &str,
+        description: &str,
+        decision_rule: &DecisionRule,
+    ) -> Result<i64, Errors> {
+        proposals::create_proposal(&self.db, author_id, title, description, decision_rule).await
+    }
+
+    async fn update_proposal(
+        &self,
+        id: i64,
+        title: &str,
+        description: &str,
+        decision_rule: &DecisionRule,
+    ) -> Result<(), Errors> {
+        proposals::update_proposal(&self.db, id, title, description, decision_rule).await
+    }
+
+    async fn get_proposal(&self, id: i64) -> Result<Proposal, Errors> {
+        proposals::get_proposal(&self.db, id).await
+    }
+
+    async fn all_proposals(&self) -> Result<Vec<Proposal>, Errors> {
+        proposals::all_proposals(&self.db).await
+    }
+
+    async fn get_transitions(&self, id: i64) -> Result<Vec<Transition>, Errors> {
+        proposals::get_transitions(&self.db, id).await
+    }
+
+    async fn open_proposal(&self, id: i64, closes_at: DateTime<Utc>) -> Result<(), Errors> {
+        proposals::open_proposal(&self.db, id, closes_at).await
+    }
+
+    async fn withdraw_proposal(&self, id: i64) -> Result<(), Errors> {
+        proposals::withdraw_proposal(&self.db, id).await
+    }
+
+    async fn close_proposal(&self, id: i64) -> Result<ProposalStatus, Errors> {
+        proposals::close_proposal(&self.db, id).await
+    }
+
+    async fn close_expired_proposals(&self, now: DateTime<Utc>) -> Result<usize, Errors> {
+        proposals::close_expired_proposals(&self.db, now).await
+    }
+
+    async fn cast_vote(
+        &self,
+        id: i64,
+        userid: Uuid,
+        choice: VoteChoice,
+        now: DateTime<Utc>,
+    ) -> Result<(), Errors> {
+        votes::cast_vote(&self.db, id, userid, choice, now).await
+    }
+
+    async fn get_user_vote(&self, id: i64, userid: Uuid) -> Result<Option<VoteChoice>, Errors> {
+        votes::get_user_vote(&self.db, id, userid).await
+    }
+
+    async fn tally_proposal(&self, id: i64, rule: &DecisionRule) -> Result<Tally, Errors> {
+        votes::tally_proposal(&self.db, id, rule).await
+    }
+}
+
+#[async_trait]
+impl InvitationRepository for LibsqlRepository {
+    async fn create_invitation(
+        &self,
+        invited_by: Uuid,
+        email: &str,
+        now: DateTime<Utc>,
+        ttl: Duration,
+    ) -> Result<String, Errors> {
+        invitations::create_invitation(&self.db, invited_by, email, now, ttl).await
+    }
+
+    async fn get_invitation_email(
+        &self,
+        token: &str,
+        now: DateTime<Utc>,
+    ) -> Result<String, Errors> {
+        invitations::get_invitation_email(&self.db, token, now).await
+    }
+
+    async fn consume_invitation(&self, token: &str, now: DateTime<Utc>) -> Result<String, Errors> {
+        invitations::consume_invitation(&self.db, token, now).await
+    }
+
+    async fn release_invitation(&self, token: &str) -> Result<(), Errors> {
+        invitations::release_invitation(&self.db, token).await
+    }
+}
+
+#[async_trait]
+impl LoginAttemptRepository for LibsqlRepository {
+    async fn retry_at(
+        &self,
+        subjects: &[Subject<'_>],
+        now: DateTime<Utc>,
+    ) -> Result<Option<DateTime<Utc>>, Errors> {
+        login_attempts::retry_at(&self.db, subjects, now).await
+    }
+
+    async fn record_failure(
+        &self,
+        subjects: &[Subject<'_>],
+        now: DateTime<Utc>,
+    ) -> Result<(), Errors> {
+        login_attempts::record_failure(&self.db, subjects, now).await
+    }
+
+    async fn clear(&self, subject: Subject<'_>) -> Result<(), Errors> {
+        login_attempts::clear(&self.db, subject).await
+    }
+
+    async fn lockouts(&self) -> Result<Vec<Lockout>, Errors> {
+        login_attempts::lockouts(&self.db).await
+    }
+}
+
+#[async_trait]
+impl PasswordResetRepository for LibsqlRepository {
+    async fn create_reset_token(
+        &self,
+        userid: Uuid,
+        now: DateTime<Utc>,
+        ttl: Duration,
+    ) -> Result<String, Errors> {
+        password_resets::create_reset_token(&self.db, userid, now, ttl).await
+    }
+
+    async fn reset_password(
+        &self,
+        argon2: &Argon2<'_>,
+        token: &str,
+        password: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Uuid, Errors> {
+        password_resets::reset_password(&self.db, argon2, token, password, now).await
+    }
+}
+
+#[async_trait]
+impl OutboxRepository for LibsqlRepository {
+    async fn enqueue(&self, email: &Email, now: DateTime<Utc>) -> Result<(), Errors> {
+        outbox::enqueue(&self.db, email, now).await
+    }
+
+    async fn due(&self, now: DateTime<Utc>) -> Result<Vec<Queued>, Errors> {
+        outbox::due(&self.db, now).await
+    }
+
+    async fn claim(&self, id: i64, now: DateTime<Utc>) -> Result<Option<Queued>, Errors> {
+        outbox::claim(&self.db, id, now).await
+    }
+
+    async fn mark_sent(&self, id: i64, now: DateTime<Utc>) -> Result<(), Errors> {
+        outbox::mark_sent(&self.db, id, now).await
+    }
+
+    async fn mark_failed(
+        &self,
+        id: i64,
+        attempts: u32,
+        error: &str,
+        retry_at: Option<DateTime<Utc>>,
+    ) -> Result<(), Errors> {
+        outbox::mark_failed(&self.db, id, attempts, error, retry_at).await
+    }
+}
@@ -0,0 +1,747 @@
+use std::{
+    collections::HashMap,
+    sync::{Mutex, MutexGuard},
+};
+
+use argon2::{password_hash, Argon2, PasswordHash, PasswordVerifier};
+use axum_sessions::async_session::{
+    async_trait,
+    chrono::{self, DateTime, Duration, TimeZone, Utc},
+};
+use uuid::Uuid;
+#[cfg(feature = "passkey")]
+use webauthn_rs::prelude::{AuthenticationResult, Passkey};
+
+#[cfg(feature = "passkey")]
+use super::KeyRepository;
+use super::{
+    InvitationRepository, LoginAttemptRepository, OutboxRepository, PasswordResetRepository,
+    ProposalRepository, Repository, UserRepository,
+};
+#[cfg(feature = "passkey")]
+use crate::models::keys::Key;
+use crate::{
+    constants::{
+        login::{LOCKOUT_SECS, LOCKOUT_THRESHOLD},
+        mail::CLAIM_TIMEOUT_SECS,
+    },
+    errors::Errors,
+    mailer::Email,
+    models::{
+        login_attempts::{Failures, Lockout, Subject},
+        outbox::Queued,
+        passwords,
+        proposals::{Proposal, ProposalStatus, Transition},
+        roles::Role,
+        tokens::{generate_token, hash_token},
+        users::User,
+        votes::{self, DecisionRule, Tally, VoteChoice},
+    },
+};
+
+struct StoredUser {
+    user: User,
+    hash: Option<String>,
+    email: Option<String>,
+    sessions_revoked_at: i64,
+}
+
+#[cfg(feature = "passkey")]
+struct StoredKey {
+    userid: Uuid,
+    key: Passkey,
+    nickname: String,
+    created_at: String,
+    last_used_at: Option<String>,
+}
+
+/// an invitation or password reset; like the database, only the token's hash is kept
+struct StoredToken<T> {
+    token_hash: String,
+    owner: T,
+    expires_at: i64,
+    used: bool,
+}
+
+struct StoredEmail {
+    queued: Queued,
+    /// `None` once it's been given up on
+    next_attempt_at: Option<i64>,
+    claimed_at: Option<i64>,
+    sent: bool,
+}
+
+#[derive(Default)]
+struct Data {
+    users: Vec<StoredUser>,
+    #[cfg(feature = "passkey")]
+    keys: Vec<StoredKey>,
+    proposals: Vec<Proposal>,
+    transitions: Vec<(i64, Transition)>,
+    votes: Vec<(i64, Uuid, VoteChoice)>,
+    /// tokens for invitations, owned by the address they were sent to
+    invitations: Vec<StoredToken<String>>,
+    password_resets: Vec<StoredToken<Uuid>>,
+    failures: HashMap<(&'static str, String), Failures>,
+    lockouts: Vec<Lockout>,
+    outbox: Vec<StoredEmail>,
+}
+
+impl Data {
+    fn user(&mut self, userid: Uuid) -> Result<&mut StoredUser, Errors> {
+        self.users
+            .iter_mut()
+            .find(|stored| stored.user.id == userid)
+            .ok_or(Errors::DbUserNotFound(userid.to_string()))
+    }
+
+    fn user_by_name(&mut self, username: &str) -> Result<&mut StoredUser, Errors> {
+        self.users
+            .iter_mut()
+            .find(|stored| stored.user.username == username)
+            .ok_or(Errors::DbUserNotFound(username.to_string()))
+    }
+
+    fn add_user(&mut self, username: &str, hash: Option<String>, email: Option<&str>) -> Uuid {
+        let id = Uuid::new_v4();
+        // the first account is made an admin, as it is in the database
+        let role = match self.users.len() {
+            0 => Role::Admin,
+            _ => Role::Member,
+        };
+        self.users.push(StoredUser {
+            user: User {
+                id,
+                username: username.to_string(),
+                role,
+                disabled: false,
+            },
+            hash,
+            email: email.map(str::to_string),
+            sessions_revoked_at: 0,
+        });
+        id
+    }
+
+    fn proposal(&mut self, id: i64) -> Result<&mut Proposal, Errors> {
+        self.proposals
+            .iter_mut()
+            .find(|proposal| proposal.id == id)
+            .ok_or(Errors::DbProposalNotFound(id))
+    }
+
+    fn transition(
+        &mut self,
+        id: i64,
+        to: ProposalStatus,
+        closes_at: Option<String>,
+    ) -> Result<(), Errors> {
+        let now = chrono::offset::Utc::now().to_rfc3339();
+        let proposal = self.proposal(id)?;
+        let from = proposal.status;
+        proposal.status = from.transition(to)?;
+        proposal.closes_at = closes_at.or(proposal.closes_at.take());
+        proposal.updated_at = now.clone();
+        self.transitions
+            .push((id, Transition { from, to, at: now }));
+        Ok(())
+    }
+
+    fn tally(&self, id: i64, rule: &DecisionRule) -> Tally {
+        let votes: Vec<VoteChoice> = self
+            .votes
+            .iter()
+            .filter(|(proposal_id, _, _)| *proposal_id == id)
+            .map(|(_, _, choice)| *choice)
+            .collect();
+        let eligible = self.users.iter().filter(|u| !u.user.disabled).count();
+        votes::tally::tally(rule, &votes, eligible as u32)
+    }
+
+    fn close(&mut self, id: i64) -> Result<ProposalStatus, Errors> {
+        self.transition(id, ProposalStatus::Closed, None)?;
+        let rule = self.proposal(id)?.decision_rule;
+        let resolution = ProposalStatus::resolve(&self.tally(id, &rule));
+        self.transition(id, resolution, None)?;
+        Ok(resolution)
+    }
+
+    /// the stored email `id`, if it's due and nobody is sending it
+    fn due_email(&mut self, id: i64, now: DateTime<Utc>) -> Option<&mut StoredEmail> {
+        let now = now.timestamp();
+        self.outbox.iter_mut().find(|stored| {
+            stored.queued.id == id
+                && !stored.sent
+                && stored.next_attempt_at.is_some_and(|at| at <= now)
+                && stored
+                    .claimed_at
+                    .map_or(true, |at| at <= now - CLAIM_TIMEOUT_SECS)
+        })
+    }
+}
+
+/// the unused, unexpired token matching `token`
+fn find_token<'a, T>(
+    tokens: &'a mut [StoredToken<T>],
+    token: &str,
+    now: DateTime<Utc>,
+) -> Option<&'a mut StoredToken<T>> {
+    let token_hash = hash_token(token);
+    tokens.iter_mut().find(|stored| {
+        stored.token_hash == token_hash && !stored.used && stored.expires_at > now.timestamp()
+    })
+}
+
+/// every repository, kept in memory, for testing controllers without a database
+#[derive(Default)]
+pub struct MemoryRepository {
+    data: Mutex<Data>,
+}
+
+impl MemoryRepository {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Data> {
+        self.data.lock().unwrap()
+    }
+}
+
+impl Repository for MemoryRepository {}
+
+#[async_trait]
+impl UserRepository for MemoryRepository {
+    #[cfg(feature = "passkey")]
+    async fn create_user(&self, username: &str) -> Result<Uuid, Errors> {
+        Ok(self.lock().add_user(username, None, None))
+    }
+
+    async fn create_user_with_password(
+        &self,
+        argon2: &Argon2<'_>,
+        username: &str,
+        password: &str,
+        email: Option<&str>,
+    ) -> Result<(), Errors> {
+        let salt = passwords::generate_salt();
+        let hash = passwords::get_hash(argon2, password, &salt)?.to_string();
+
+        let mut data = self.lock();
+        if data.user_by_name(username).is_ok() {
+            return Err(Errors::UserAlreadyExists(username.to_string()));
+        }
+        data.add_user(username, Some(hash), email);
+        Ok(())
+    }
+
+    async fn validate_password(
+        &self,
+        argon2: &Argon2<'_>,
+        username: &str,
+        password: &str,
+    ) -> Result<Uuid, Errors> {
+        let (id, hash, disabled) = {
+            let mut data = self.lock();
+            let stored = data.user_by_name(username)?;
+            (stored.user.id, stored.hash.clone(), stored.user.disabled)
+        };
+        let hash = hash.ok_or(Errors::DbNoHashMatch(username.to_string()))?;
+        let hash = PasswordHash::new(&hash).map_err(Errors::DbStoredHashParsingError)?;
+        match argon2.verify_password(password.as_bytes(), &hash) {
+            Ok(()) if disabled => Err(Errors::UserDisabled(username.to_string())),
+            Ok(()) => Ok(id),
+            Err(password_hash::Error::Password) => Err(Errors::DbNoHashMatch(username.to_string())),
+            Err(e) => Err(Errors::DbStoredHashParsingError(e)),
+        }
+    }
+
+    async fn set_password(
+        &self,
+        argon2: &Argon2<'_>,
+        userid: Uuid,
+        password: &str,
+    ) -> Result<(), Errors> {
+        let salt = passwords::generate_salt();
+        let hash = passwords::get_hash(argon2, password, &salt)?.to_string();
+        self.lock().user(userid)?.hash = Some(hash);
+        Ok(())
+    }
+
+    async fn get_user(&self, userid: Uuid) -> Result<User, Errors> {
+        Ok(self.lock().user(userid)?.user.clone())
+    }
+
+    async fn get_user_id(&self, username: &str) -> Result<Uuid, Errors> {
+        Ok(self.lock().user_by_name(username)?.user.id)
+    }
+
+    async fn get_email(&self, username: &str) -> Result<Option<String>, Errors> {
+        Ok(self.lock().user_by_name(username)?.email.clone())
+    }
+
+    async fn all_users(&self) -> Result<Vec<User>, Errors> {
+        Ok(self
+            .lock()
+            .users
+            .iter()
+            .map(|stored| stored.user.clone())
+            .collect())
+    }
+
+    async fn set_role(&self, username: &str, role: Role) -> Result<(), Errors> {
+        let mut data = self.lock();
+        let admins = data
+            .users
+            .iter()
+            .filter(|stored| stored.user.role == Role::Admin)
+            .count();
+        let user = &mut data.user_by_name(username)?.user;
+        if user.role == Role::Admin && role != Role::Admin && admins <= 1 {
+            return Err(Errors::CannotRemoveLastAdmin);
+        }
+        user.role = role;
+        Ok(())
+    }
+
+    async fn revoke_sessions(&self, userid: Uuid) -> Result<(), Errors> {
+        self.lock().user(userid)?.sessions_revoked_at =
+            chrono::offset::Utc::now().timestamp_millis();
+        Ok(())
+    }
+
+    async fn sessions_revoked_at(&self, userid: Uuid) -> Result<i64, Errors> {
+        Ok(self
+            .lock()
+            .user(userid)
+            .map_or(0, |stored| stored.sessions_revoked_at))
+    }
+}
+
+#[cfg(feature = "passkey")]
+#[async_trait]
+impl KeyRepository for MemoryRepository {
+    async fn add_key(&self, userid: Uuid, key: Passkey, nickname: &str) -> Result<(), Errors> {
+        self.lock().keys.push(StoredKey {
+            userid,
+            key,
+            nickname: nickname.to_string(),
+            created_at: chrono::offset::Utc::now().to_rfc3339(),
+            last_used_at: None,
+        });
+        Ok(())
+    }
+
+    async fn get_keys(&self, userid: Uuid) -> Result<Vec<Passkey>, Errors> {
+        Ok(self
+            .lock()
+            .keys
+            .iter()
+            .filter(|stored| stored.userid == userid)
+            .map(|stored| stored.key.clone())
+            .collect())
+    }
+
+    async fn list_keys(&self, userid: Uuid) -> Result<Vec<Key>, Errors> {
+        Ok(self
+            .lock()
+            .keys
+            .iter()
+            .filter(|stored| stored.userid == userid)
+            .map(|stored| Key {
+                credential_id: stored.key.cred_id().to_string(),
+                nickname: stored.nickname.clone(),
+                created_at: stored.created_at.clone(),
+                last_used_at: stored.last_used_at.clone(),
+            })
+            .collect())
+    }
+
+    async fn rename_key(
+        &self,
+        userid: Uuid,
+        credential_id: &str,
+        nickname: &str,
+    ) -> Result<(), Errors> {
+        let mut data = self.lock();
+        let stored = data
+            .keys
+            .iter_mut()
+            .find(|stored| {
+                stored.userid == userid && stored.key.cred_id().to_string() == credential_id
+            })
+            .ok_or(Errors::DbKeyNotFound(credential_id.to_string()))?;
+        stored.nickname = nickname.to_string();
+        Ok(())
+    }
+
+    async fn remove_key(&self, userid: Uuid, credential_id: &str) -> Result<(), Errors> {
+        let mut data = self.lock();
+        let owned = |stored: &StoredKey| stored.userid == userid;
+        let matches = |stored: &StoredKey| stored.key.cred_id().to_string() == credential_id;
+        if !data
+            .keys
+            .iter()
+            .any(|stored| owned(stored) && matches(stored))
+        {
+            return Err(Errors::DbKeyNotFound(credential_id.to_string()));
+        }
+        let has_password = data.user(userid)?.hash.is_some();
+        if data.keys.iter().filter(|stored| owned(stored)).count() == 1 && !has_password {
+            return Err(Errors::CannotRemoveLastCredential);
+        }
+        data.keys
+            .retain(|stored| !(owned(stored) && matches(stored)));
+        Ok(())
+    }
+
+    async fn record_key_use(
+        &self,
+        userid: Uuid,
+        auth_result: &AuthenticationResult,
+    ) -> Result<(), Errors> {
+        let now = chrono::offset::Utc::now().to_rfc3339();
+        for stored in self.lock().keys.iter_mut() {
+            if stored.userid == userid && stored.key.cred_id() == auth_result.cred_id() {
+                stored.key.update_credential(auth_result);
+                stored.last_used_at = Some(now.clone());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl ProposalRepository for MemoryRepository {
+    async fn create_proposal(
+        &self,
+        author_id: Uuid,
+        title: &str,
+        description: &str,
+        decision_rule: &DecisionRule,
+    ) -> Result<i64, Errors> {
+        let now = chrono::offset::Utc::now().to_rfc3339();
+        let mut data = self.lock();
+        let id = data.proposals.iter().map(|p| p.id).max().unwrap_or(0) + 1;
+        let author_name = data
+            .user(author_id)
+            .ok()
+            .map(|stored| stored.user.username.clone());
+        data.proposals.push(Proposal {
+            id,
+            title: title.to_string(),
+            description: description.to_string(),
+            author_id,
+            author_name,
+            created_at: now.clone(),
+            updated_at: now,
+            decision_rule: *decision_rule,
+            status: ProposalStatus::Draft,
+            closes_at: None,
+        });
+        Ok(id)
+    }
+
+    async fn update_proposal(
+        &self,
+        id: i64,
+        title: &str,
+        description: &str,
+        decision_rule: &DecisionRule,
+    ) -> Result<(), Errors> {
+        let mut data = self.lock();
+        let proposal = data.proposal(id)?;
+        proposal.title = title.to_string();
+        proposal.description = description.to_string();
+        proposal.decision_rule = *decision_rule;
+        proposal.updated_at = chrono::offset::Utc::now().to_rfc3339();
+        Ok(())
+    }
+
+    async fn get_proposal(&self, id: i64) -> Result<Proposal, Errors> {
+        Ok(self.lock().proposal(id)?.clone())
+    }
+
+    async fn all_proposals(&self) -> Result<Vec<Proposal>, Errors> {
+        Ok(self.lock().proposals.iter().rev().cloned().collect())
+    }
+
+    async fn get_transitions(&self, id: i64) -> Result<Vec<Transition>, Errors> {
+        Ok(self
+            .lock()
+            .transitions
+            .iter()
+            .filter(|(proposal_id, _)| *proposal_id == id)
+            .map(|(_, transition)| transition.clone())
+            .collect())
+    }
+
+    async fn open_proposal(&self, id: i64, closes_at: DateTime<Utc>) -> Result<(), Errors> {
+        self.lock()
+            .transition(id, ProposalStatus::Open, Some(closes_at.to_rfc3339()))
+    }
+
+    async fn withdraw_proposal(&self, id: i64) -> Result<(), Errors> {
+        self.lock().transition(id, ProposalStatus::Withdrawn, None)
+    }
+
+    async fn close_proposal(&self, id: i64) -> Result<ProposalStatus, Errors> {
+        self.lock().close(id)
+    }
+
+    async fn close_expired_proposals(&self, now: DateTime<Utc>) -> Result<usize, Errors> {
+        let mut data = self.lock();
+        let expired: Vec<i64> = data
+            .proposals
+            .iter()
+            .filter(|p| p.status == ProposalStatus::Open && !p.is_open(now))
+            .map(|p| p.id)
+            .collect();
+        for id in &expired {
+            data.close(*id)?;
+        }
+        Ok(expired.len())
+    }
+
+    async fn cast_vote(
+        &self,
+        id: i64,
+        userid: Uuid,
+        choice: VoteChoice,
+        now: DateTime<Utc>,
+    ) -> Result<(), Errors> {
+        let mut data = self.lock();
+        if !data.proposal(id).map(|p| p.is_open(now)).unwrap_or(false) {
+            return Err(Errors::ProposalNotInStatus(id, ProposalStatus::Open));
+        }
+        data.votes
+            .retain(|(proposal_id, voter, _)| !(*proposal_id == id && *voter == userid));
+        data.votes.push((id, userid, choice));
+        Ok(())
+    }
+
+    async fn get_user_vote(&self, id: i64, userid: Uuid) -> Result<Option<VoteChoice>, Errors> {
+        Ok(self
+            .lock()
+            .votes
+            .iter()
+            .find(|(proposal_id, voter, _)| *proposal_id == id && *voter == userid)
+            .map(|(_, _, choice)| *choice))
+    }
+
+    async fn tally_proposal(&self, id: i64, rule: &DecisionRule) -> Result<Tally, Errors> {
+        Ok(self.lock().tally(id, rule))
+    }
+}
+
+#[async_trait]
+impl InvitationRepository for MemoryRepository {
+    async fn create_invitation(
+        &self,
+        _invited_by: Uuid,
+        email: &str,
+        now: DateTime<Utc>,
+        ttl: Duration,
+    ) -> Result<String, Errors> {
+        let token = generate_token();
+        let mut data = self.lock();
+        data.invitations
+            .retain(|stored| stored.owner != email || stored.used);
+        data.invitations.push(StoredToken {
+            token_hash: hash_token(&token),
+            owner: email.to_string(),
+            expires_at: (now + ttl).timestamp(),
+            used: false,
+        });
+        Ok(token)
+    }
+
+    async fn get_invitation_email(
+        &self,
+        token: &str,
+        now: DateTime<Utc>,
+    ) -> Result<String, Errors> {
+        let mut data = self.lock();
+        let stored =
+            find_token(&mut data.invitations, token, now).ok_or(Errors::InvalidInvitation)?;
+        Ok(stored.owner.clone())
+    }
+
+    async fn consume_invitation(&self, token: &str, now: DateTime<Utc>) -> Result<String, Errors> {
+        let mut data = self.lock();
+        let stored =
+            find_token(&mut data.invitations, token, now).ok_or(Errors::InvalidInvitation)?;
+        stored.used = true;
+        Ok(stored.owner.clone())
+    }
+
+    async fn release_invitation(&self, token: &str) -> Result<(), Errors> {
+        let token_hash = hash_token(token);
+        for stored in self.lock().invitations.iter_mut() {
+            if stored.token_hash == token_hash {
+                stored.used = false;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl LoginAttemptRepository for MemoryRepository {
+    async fn retry_at(
+        &self,
+        subjects: &[Subject<'_>],
+        now: DateTime<Utc>,
+    ) -> Result<Option<DateTime<Utc>>, Errors> {
+        let data = self.lock();
+        let latest = subjects
+            .iter()
+            .filter_map(|subject| {
+                data.failures
+                    .get(&(subject.scope(), subject.value().to_string()))
+                    .and_then(|failures| failures.retry_at(now.timestamp()))
+            })
+            .max();
+        Ok(latest.map(|secs| Utc.timestamp_opt(secs, 0).unwrap()))
+    }
+
+    async fn record_failure(
+        &self,
+        subjects: &[Subject<'_>],
+        now: DateTime<Utc>,
+    ) -> Result<(), Errors> {
+        let mut data = self.lock();
+        for subject in subjects {
+            let key = (subject.scope(), subject.value().to_string());
+            let failures = data.failures.entry(key).or_default();
+            *failures = failures.fail(now.timestamp());
+            if failures.failures == LOCKOUT_THRESHOLD {
+                let lockout = Lockout {
+                    scope: subject.scope().to_string(),
+                    subject: subject.value().to_string(),
+                    failures: LOCKOUT_THRESHOLD,
+                    locked_at: now.to_rfc3339(),
+                    locked_until: (now + Duration::seconds(LOCKOUT_SECS)).to_rfc3339(),
+                };
+                data.lockouts.push(lockout);
+            }
+        }
+        Ok(())
+    }
+
+    async fn clear(&self, subject: Subject<'_>) -> Result<(), Errors> {
+        self.lock()
+            .failures
+            .remove(&(subject.scope(), subject.value().to_string()));
+        Ok(())
+    }
+
+    async fn lockouts(&self) -> Result<Vec<Lockout>, Errors> {
+        Ok(self.lock().lockouts.iter().rev().cloned().collect())
+    }
+}
+
+#[async_trait]
+impl PasswordResetRepository for MemoryRepository {
+    async fn create_reset_token(
+        &self,
+        userid: Uuid,
+        now: DateTime<Utc>,
+        ttl: Duration,
+    ) -> Result<String, Errors> {
+        let token = generate_token();
+        let mut data = self.lock();
+        data.password_resets
+            .retain(|stored| stored.owner != userid || stored.used);
+        data.password_resets.push(StoredToken {
+            token_hash: hash_token(&token),
+            owner: userid,
+            expires_at: (now + ttl).timestamp(),
+            used: false,
+        });
+        Ok(token)
+    }
+
+    async fn reset_password(
+        &self,
+        argon2: &Argon2<'_>,
+        token: &str,
+        password: &str,
+        now: DateTime<Utc>,
+    ) -> Result<Uuid, Errors> {
+        let salt = passwords::generate_salt();
+        let hash = passwords::get_hash(argon2, password, &salt)?.to_string();
+
+        let mut data = self.lock();
+        let stored =
+            find_token(&mut data.password_resets, token, now).ok_or(Errors::InvalidResetToken)?;
+        stored.used = true;
+        let userid = stored.owner;
+        let user = data.user(userid)?;
+        user.hash = Some(hash);
+        user.sessions_revoked_at = now.timestamp_millis();
+        Ok(userid)
+    }
+}
+
+#[async_trait]
+impl OutboxRepository for MemoryRepository {
+    async fn enqueue(&self, email: &Email, now: DateTime<Utc>) -> Result<(), Errors> {
+        let mut data = self.lock();
+        let id = data.outbox.len() as i64 + 1;
+        data.outbox.push(StoredEmail {
+            queued: Queued {
+                id,
+                email: email.clone(),
+                attempts: 0,
+            },
+            next_attempt_at: Some(now.timestamp()),
+            claimed_at: None,
+            sent: false,
+        });
+        Ok(())
+    }
+
+    async fn due(&self, now: DateTime<Utc>) -> Result<Vec<Queued>, Errors> {
+        let mut data = self.lock();
+        let ids: Vec<i64> = data.outbox.iter().map(|stored| stored.queued.id).collect();
+        Ok(ids
+            .into_iter()
+            .filter_map(|id| data.due_email(id, now).map(|stored| stored.queued.clone()))
+            .collect())
+    }
+
+    async fn claim(&self, id: i64, now: DateTime<Utc>) -> Result<Option<Queued>, Errors> {
+        Ok(self.lock().due_email(id, now).map(|stored| {
+            stored.claimed_at = Some(now.timestamp());
+            stored.queued.clone()
+        }))
+    }
+
+    async fn mark_sent(&self, id: i64, _now: DateTime<Utc>) -> Result<(), Errors> {
+        for stored in self.lock().outbox.iter_mut() {
+            if stored.queued.id == id {
+                stored.sent = true;
+                stored.claimed_at = None;
+            }
+        }
+        Ok(())
+    }
+
+    async fn mark_failed(
+        &self,
+        id: i64,
+        attempts: u32,
+        _error: &str,
+        retry_at: Option<DateTime<Utc>>,
+    ) -> Result<(), Errors> {
+        for stored in self.lock().outbox.iter_mut() {
+            if stored.queued.id == id {
+                stored.queued.attempts = attempts;
+                stored.next_attempt_at = retry_at.map(|retry_at| retry_at.timestamp());
+                stored.claimed_at = None;
+            }
+        }
+        Ok(())
+    }
+}
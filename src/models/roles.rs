@@ -0,0 +1,62 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::Errors;
+
+/// what a user is allowed to do. each role can do everything the ones before it can,
+/// so roles compare by how much they allow: `Member < Steward < Admin`
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// can propose, vote and invite new members
+    Member,
+    /// also looks after the co-op's day to day, like reviewing login lockouts
+    Steward,
+    /// also manages members' roles and can close proposals early
+    Admin,
+}
+
+impl Role {
+    pub const ALL: [Role; 3] = [Role::Member, Role::Steward, Role::Admin];
+}
+
+impl FromStr for Role {
+    type Err = Errors;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "member" => Ok(Role::Member),
+            "steward" => Ok(Role::Steward),
+            "admin" => Ok(Role::Admin),
+            _ => Err(Errors::RoleParseError(s.to_string())),
+        }
+    }
+}
+
+impl Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Role::Member => "member",
+            Role::Steward => "steward",
+            Role::Admin => "admin",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roles() {
+        assert!(Role::Member < Role::Steward && Role::Steward < Role::Admin);
+        for role in Role::ALL {
+            assert_eq!(role.to_string().parse::<Role>().unwrap(), role);
+        }
+        assert!(matches!(
+            "owner".parse::<Role>(),
+            Err(Errors::RoleParseError(_))
+        ));
+    }
+}
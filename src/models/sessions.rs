@@ -0,0 +1,194 @@
+use std::sync::Arc;
+
+use axum_sessions::async_session::{self, async_trait, chrono, Session, SessionStore};
+use libsql_client::Client;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{constants::session_keys::AUTH_STATE, errors::Errors, models::db::Query};
+
+/// persists sessions to the `sessions` table, so they survive restarts
+/// and are shared between replicas.
+#[derive(Clone)]
+pub struct LibsqlSessionStore {
+    db: Arc<Client>,
+}
+
+/// the part of `AuthState` the store cares about, so sessions can be found by user
+#[derive(Deserialize)]
+struct SessionUser {
+    userid: Uuid,
+}
+
+impl LibsqlSessionStore {
+    pub fn new(db: Arc<Client>) -> Self {
+        Self { db }
+    }
+
+    /// deletes every expired session, returning how many were removed
+    pub async fn cleanup(&self) -> Result<u64, Errors> {
+        let now = chrono::offset::Utc::now().timestamp();
+        Query::new("DELETE FROM sessions WHERE expiresAt IS NOT NULL AND expiresAt <= ?;")
+            .bind(now)
+            .execute(&self.db)
+            .await
+            .map(|rs| rs.rows_affected)
+    }
+}
+
+impl std::fmt::Debug for LibsqlSessionStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LibsqlSessionStore").finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl SessionStore for LibsqlSessionStore {
+    async fn load_session(&self, cookie_value: String) -> async_session::Result<Option<Session>> {
+        let id = Session::id_from_cookie_value(&cookie_value)?;
+        let now = chrono::offset::Utc::now().timestamp();
+        let row = Query::new(
+            "SELECT session FROM sessions WHERE id = ? AND (expiresAt IS NULL OR expiresAt > ?);",
+        )
+        .bind(id)
+        .bind(now)
+        .fetch_one(&self.db)
+        .await?;
+        match row {
+            Some(row) => {
+                let session: &str = row.try_column("session")?;
+                Ok(serde_json::from_str::<Session>(session)?.validate())
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn store_session(&self, session: Session) -> async_session::Result<Option<String>> {
+        let userid = session
+            .get::<SessionUser>(AUTH_STATE)
+            .map(|user| user.userid.urn().to_string());
+        Query::new(
+            "INSERT INTO sessions (id, session, userId, expiresAt) VALUES (?, ?, ?, ?)
+                ON CONFLICT (id) DO UPDATE SET
+                    session = excluded.session,
+                    userId = excluded.userId,
+                    expiresAt = excluded.expiresAt;",
+        )
+        .bind(session.id())
+        .bind(serde_json::to_string(&session)?)
+        .bind_opt(userid)
+        .bind_opt(session.expiry().map(|expiry| expiry.timestamp()))
+        .execute(&self.db)
+        .await?;
+
+        session.reset_data_changed();
+        Ok(session.into_cookie_value())
+    }
+
+    async fn destroy_session(&self, session: Session) -> async_session::Result {
+        Query::new("DELETE FROM sessions WHERE id = ?;")
+            .bind(session.id())
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+
+    async fn clear_store(&self) -> async_session::Result {
+        Query::new("DELETE FROM sessions;")
+            .execute(&self.db)
+            .await?;
+        Ok(())
+    }
+}
+
+/// `SessionStore` needs `Clone`, so it can't be a trait object itself; this can, and is what
+/// lets `AppState` hold any store, the same way it holds any repository
+#[async_trait]
+trait ObjectSessionStore: Send + Sync {
+    async fn load_session(&self, cookie_value: String) -> async_session::Result<Option<Session>>;
+    async fn store_session(&self, session: Session) -> async_session::Result<Option<String>>;
+    async fn destroy_session(&self, session: Session) -> async_session::Result;
+    async fn clear_store(&self) -> async_session::Result;
+}
+
+#[async_trait]
+impl<S: SessionStore> ObjectSessionStore for S {
+    async fn load_session(&self, cookie_value: String) -> async_session::Result<Option<Session>> {
+        SessionStore::load_session(self, cookie_value).await
+    }
+
+    async fn store_session(&self, session: Session) -> async_session::Result<Option<String>> {
+        SessionStore::store_session(self, session).await
+    }
+
+    async fn destroy_session(&self, session: Session) -> async_session::Result {
+        SessionStore::destroy_session(self, session).await
+    }
+
+    async fn clear_store(&self) -> async_session::Result {
+        SessionStore::clear_store(self).await
+    }
+}
+
+/// any session store, behind an `Arc`
+#[derive(Clone)]
+pub struct DynSessionStore(Arc<dyn ObjectSessionStore>);
+
+impl DynSessionStore {
+    pub fn new(store: impl SessionStore) -> Self {
+        Self(Arc::new(store))
+    }
+}
+
+impl std::fmt::Debug for DynSessionStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DynSessionStore").finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl SessionStore for DynSessionStore {
+    async fn load_session(&self, cookie_value: String) -> async_session::Result<Option<Session>> {
+        self.0.load_session(cookie_value).await
+    }
+
+    async fn store_session(&self, session: Session) -> async_session::Result<Option<String>> {
+        self.0.store_session(session).await
+    }
+
+    async fn destroy_session(&self, session: Session) -> async_session::Result {
+        self.0.destroy_session(session).await
+    }
+
+    async fn clear_store(&self) -> async_session::Result {
+        self.0.clear_store().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_session_store() {
+        let client = libsql_client::Client::in_memory().unwrap();
+        crate::models::init_db(&client).await.unwrap();
+        let store = LibsqlSessionStore::new(Arc::new(client));
+
+        let mut session = Session::new();
+        session.insert("key", "value").unwrap();
+        let cookie_value = store.store_session(session).await.unwrap().unwrap();
+        let loaded = store.load_session(cookie_value.clone()).await.unwrap();
+        assert_eq!(loaded.unwrap().get::<String>("key").unwrap(), "value");
+
+        let mut expired = Session::new();
+        expired.set_expiry(chrono::offset::Utc::now() - chrono::Duration::seconds(1));
+        let expired_cookie = store.store_session(expired).await.unwrap().unwrap();
+        assert!(store.load_session(expired_cookie).await.unwrap().is_none());
+        assert_eq!(store.cleanup().await.unwrap(), 1);
+
+        let session = store.load_session(cookie_value.clone()).await.unwrap();
+        store.destroy_session(session.unwrap()).await.unwrap();
+        assert!(store.load_session(cookie_value).await.unwrap().is_none());
+    }
+}
@@ -1,18 +1,30 @@
-use argon2::{password_hash::SaltString, PasswordHash};
-use libsql_client::{args, Client, Statement, Value};
-use log::debug;
+use argon2::{password_hash::SaltString, Argon2, PasswordHash};
+use axum_sessions::async_session::chrono;
+use libsql_client::{Client, Row, Value};
 use serde::Serialize;
 
-#[cfg(passkey)]
+#[cfg(feature = "passkey")]
+use log::debug;
+#[cfg(feature = "passkey")]
 use std::sync::Arc;
 use uuid::Uuid;
 
-use crate::{errors::Errors, models::passwords};
+use crate::{
+    errors::Errors,
+    models::{
+        db::{batch, Query},
+        passwords,
+        roles::Role,
+    },
+};
 
-#[derive(Serialize)]
+#[derive(Serialize, Clone)]
 pub struct User {
-    id: Uuid,
+    pub(super) id: Uuid,
     pub username: String,
+    pub role: Role,
+    /// disabled accounts can't log in, and their sessions are revoked
+    pub disabled: bool,
 }
 
 impl User {
@@ -22,7 +34,7 @@ impl User {
 
         let val = &row.values[0];
         if let Value::Text { value } = val {
-            id = Uuid::parse_str(value.as_str()).map_err(Errors::UuidParsingError)?;
+            id = Uuid::parse_str(value.as_str())?;
         } else {
             return Err(Errors::DbStoredUuidWrongTypeError());
         }
@@ -34,24 +46,38 @@ impl User {
             return Err(Errors::DbStoredUsernameWrongTypeError());
         }
 
-        Ok(Self { id, username })
+        let role = row
+            .try_column::<&str>("role")
+            .map_err(Errors::DbFetchError)?
+            .parse()?;
+        let disabled = row.try_column::<&str>("disabledAt").is_ok();
+
+        Ok(Self {
+            id,
+            username,
+            role,
+            disabled,
+        })
     }
 }
 
-#[cfg(passkey)]
+#[cfg(feature = "passkey")]
 pub async fn create_user(
     client: &Arc<libsql_client::Client>,
     username: &str,
-) -> Result<Uuid, crate::Error> {
+) -> Result<Uuid, Errors> {
     let id: Uuid = Uuid::new_v4();
     debug!("creating user: {}, {}", id, username);
-    let stmt: String = format!(
-        "INSERT INTO users (id, username) VALUES (\"{}\", \"{}\");",
-        id, username
-    );
-    debug!("stmt: {}", stmt);
-
-    client.execute(stmt).await.expect("error creating user");
+    Query::new(
+        "INSERT INTO users (id, username, role)
+            SELECT ?, ?, CASE WHEN (SELECT COUNT(*) FROM users) = 0 THEN ? ELSE ? END;",
+    )
+    .bind(id.urn().to_string())
+    .bind(username)
+    .bind(Role::Admin.to_string())
+    .bind(Role::Member.to_string())
+    .execute(client)
+    .await?;
 
     debug!("user {} created", id);
     Ok(id)
@@ -59,8 +85,10 @@ pub async fn create_user(
 
 pub async fn create_user_with_password(
     db: &Client,
+    argon2: &Argon2<'_>,
     username: &str,
     password: &str,
+    email: Option<&str>,
 ) -> Result<(), Errors> {
     if user_exists(db, username).await? {
         return Err(Errors::UserAlreadyExists(username.to_owned()));
@@ -68,46 +96,268 @@ pub async fn create_user_with_password(
 
     let uuid: Uuid = Uuid::new_v4();
     let salt: SaltString = passwords::generate_salt();
-    let hash: PasswordHash<'_> = passwords::get_hash(password, &salt)?;
+    let hash: PasswordHash<'_> = passwords::get_hash(argon2, password, &salt)?;
 
-    let stmt: Statement = libsql_client::Statement::with_args(
-        "INSERT INTO users (id, username, hash, salt) VALUES (?,?,?,?);",
-        args!(
-            uuid.urn().to_string(),
-            username,
-            hash.to_string(),
-            salt.to_string()
-        ),
-    );
+    // the salt is part of the PHC hash string, so it isn't stored separately.
+    // the first account is made an admin, so there's someone to hand out roles; it's
+    // worked out in the insert itself so two first sign ups can't both be admins
+    Query::new(
+        "INSERT INTO users (id, username, hash, email, role)
+            SELECT ?, ?, ?, ?, CASE WHEN (SELECT COUNT(*) FROM users) = 0 THEN ? ELSE ? END;",
+    )
+    .bind(uuid.urn().to_string())
+    .bind(username)
+    .bind(hash.to_string())
+    .bind_opt(email)
+    .bind(Role::Admin.to_string())
+    .bind(Role::Member.to_string())
+    .execute(db)
+    .await
+    .map(|_| ())
+}
 
-    debug!("stmt: {}", stmt);
-    db.execute(stmt)
-        .await
-        .map_err(Errors::DbInsertError)
-        .map(|_| ())
+pub async fn get_role(db: &Client, userid: Uuid) -> Result<Role, Errors> {
+    Query::new("SELECT role FROM users WHERE id = ?;")
+        .bind(userid.urn().to_string())
+        .fetch_one(db)
+        .await?
+        .ok_or(Errors::DbUserNotFound(userid.to_string()))?
+        .try_column::<&str>("role")
+        .map_err(Errors::DbFetchError)?
+        .parse()
+}
+
+/// changes `username`'s role, refusing to demote the only remaining admin. the check is
+/// part of the update, so two admins can't demote each other at once
+pub async fn set_role(db: &Client, username: &str, role: Role) -> Result<(), Errors> {
+    let admin = Role::Admin.to_string();
+    let rs = Query::new(
+        "UPDATE users SET role = ? WHERE username = ?
+            AND NOT (? != ? AND role = ? AND (SELECT COUNT(*) FROM users WHERE role = ?) <= 1);",
+    )
+    .bind(role.to_string())
+    .bind(username)
+    .bind(role.to_string())
+    .bind(admin.as_str())
+    .bind(admin.as_str())
+    .bind(admin.as_str())
+    .execute(db)
+    .await?;
+    match rs.rows_affected {
+        0 if user_exists(db, username).await? => Err(Errors::CannotRemoveLastAdmin),
+        0 => Err(Errors::DbUserNotFound(username.to_string())),
+        _ => Ok(()),
+    }
+}
+
+pub async fn get_user(db: &Client, userid: Uuid) -> Result<User, Errors> {
+    let row = Query::new("SELECT id, username, role, disabledAt FROM users WHERE id = ?;")
+        .bind(userid.urn().to_string())
+        .fetch_one(db)
+        .await?;
+    match row {
+        Some(row) => User::from_db_row(&row),
+        None => Err(Errors::DbUserNotFound(userid.to_string())),
+    }
+}
+
+pub async fn get_user_id(db: &Client, username: &str) -> Result<Uuid, Errors> {
+    let row = Query::new("SELECT id FROM users WHERE username = ?;")
+        .bind(username)
+        .fetch_one(db)
+        .await?
+        .ok_or(Errors::DbUserNotFound(username.to_string()))?;
+    let id: &str = row
+        .try_column("id")
+        .map_err(Errors::DbStoredUuidParsingError)?;
+    Uuid::parse_str(id).map_err(Errors::UuidParsingError)
+}
+
+/// the address notifications are emailed to, if the user gave one
+pub async fn get_email(db: &Client, username: &str) -> Result<Option<String>, Errors> {
+    let row = Query::new("SELECT email FROM users WHERE username = ?;")
+        .bind(username)
+        .fetch_one(db)
+        .await?
+        .ok_or(Errors::DbUserNotFound(username.to_string()))?;
+    Ok(row.try_column::<&str>("email").ok().map(str::to_string))
 }
 
 async fn user_exists(db: &Client, username: &str) -> Result<bool, Errors> {
-    let stmt = Statement::with_args("SELECT id FROM users WHERE username = ?;", args!(username));
-    debug!("stmt: {}", stmt);
-    db.execute(stmt)
+    Query::new("SELECT id FROM users WHERE username = ?;")
+        .bind(username)
+        .fetch_one(db)
         .await
-        .map_err(Errors::DbFetchError)
-        .map(|rs| rs.rows.len())
-        .map(|num_users| num_users > 0)
+        .map(|row| row.is_some())
 }
 
 pub async fn all_users(db: &Client) -> Result<Vec<User>, Errors> {
-    let stmt = Statement::new("SELECT id, username FROM users;");
-    debug!("stmt: {}", stmt);
-    db.execute(stmt)
-        .await
+    Query::new("SELECT id, username, role, disabledAt FROM users ORDER BY rowid;")
+        .fetch(db)
+        .await?
+        .iter()
+        .map(User::from_db_row)
+        .collect()
+}
+
+/// members who can still log in, and so vote
+pub async fn count_active_users(db: &Client) -> Result<u32, Errors> {
+    let row = Query::new("SELECT COUNT(*) AS count FROM users WHERE disabledAt IS NULL;")
+        .fetch_one(db)
+        .await?;
+    count_from(row)
+}
+
+fn count_from(row: Option<Row>) -> Result<u32, Errors> {
+    row.map(|row| row.try_column::<usize>("count"))
+        .unwrap_or(Ok(0))
+        .map(|count| count as u32)
         .map_err(Errors::DbFetchError)
-        .map(|rs| {
-            rs.rows
-                .iter()
-                .map(User::from_db_row)
-                .map(Result::unwrap)
-                .collect()
-        })
+}
+
+/// invalidates every session `userid` currently has: stored sessions are deleted,
+/// and any still held elsewhere are rejected by `AuthState::is_revoked`
+pub async fn revoke_sessions(db: &Client, userid: Uuid) -> Result<(), Errors> {
+    let now = chrono::offset::Utc::now().timestamp_millis();
+    batch(
+        db,
+        [
+            Query::new("UPDATE users SET sessionsRevokedAt = ? WHERE id = ?;")
+                .bind(now)
+                .bind(userid.urn().to_string()),
+            Query::new("DELETE FROM sessions WHERE userId = ?;").bind(userid.urn().to_string()),
+        ],
+    )
+    .await
+    .map(|_| ())
+}
+
+/// stops `username` logging in, and logs them out everywhere; or lets them back in
+pub async fn set_disabled(db: &Client, username: &str, disabled: bool) -> Result<(), Errors> {
+    let now = chrono::offset::Utc::now();
+    let queries = match disabled {
+        true => vec![
+            Query::new(
+                "UPDATE users SET disabledAt = ?, sessionsRevokedAt = ? WHERE username = ?;",
+            )
+            .bind(now.to_rfc3339())
+            .bind(now.timestamp_millis())
+            .bind(username),
+            Query::new(
+                "DELETE FROM sessions WHERE userId = (SELECT id FROM users WHERE username = ?);",
+            )
+            .bind(username),
+        ],
+        false => vec![
+            Query::new("UPDATE users SET disabledAt = NULL WHERE username = ?;").bind(username),
+        ],
+    };
+    let results = batch(db, queries).await?;
+    match results.first() {
+        Some(rs) if rs.rows_affected > 0 => Ok(()),
+        _ => Err(Errors::DbUserNotFound(username.to_string())),
+    }
+}
+
+pub async fn sessions_revoked_at(db: &Client, userid: Uuid) -> Result<i64, Errors> {
+    let row = Query::new("SELECT sessionsRevokedAt FROM users WHERE id = ?;")
+        .bind(userid.urn().to_string())
+        .fetch_one(db)
+        .await?;
+    match row {
+        Some(row) => row
+            .try_column("sessionsRevokedAt")
+            .map_err(Errors::DbFetchError),
+        None => Ok(0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use argon2::Params;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_roles() {
+        let client = libsql_client::Client::in_memory().unwrap();
+        crate::models::init_db(&client).await.unwrap();
+        let argon2 = passwords::hasher(Params::new(1024, 1, 1, None).unwrap());
+        for username in ["first", "second"] {
+            create_user_with_password(&client, &argon2, username, "pw", None)
+                .await
+                .unwrap();
+        }
+        let first = get_user_id(&client, "first").await.unwrap();
+        let second = get_user_id(&client, "second").await.unwrap();
+
+        // the first account is an admin, and everyone after is a member
+        assert_eq!(get_role(&client, first).await.unwrap(), Role::Admin);
+        assert_eq!(get_role(&client, second).await.unwrap(), Role::Member);
+
+        // there's always at least one admin
+        assert!(matches!(
+            set_role(&client, "first", Role::Member).await,
+            Err(Errors::CannotRemoveLastAdmin)
+        ));
+        set_role(&client, "second", Role::Admin).await.unwrap();
+        set_role(&client, "first", Role::Steward).await.unwrap();
+        assert_eq!(get_role(&client, first).await.unwrap(), Role::Steward);
+        assert!(matches!(
+            set_role(&client, "nobody", Role::Admin).await,
+            Err(Errors::DbUserNotFound(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_usernames_are_bound_not_spliced() {
+        let client = std::sync::Arc::new(libsql_client::Client::in_memory().unwrap());
+        crate::models::init_db(&client).await.unwrap();
+        let argon2 = passwords::hasher(Params::new(1024, 1, 1, None).unwrap());
+        let usernames = [
+            "o'brien",
+            "\"quoted\"",
+            "x\" OR \"1\"=\"1",
+            "'); DROP TABLE users; --",
+            "\"; DELETE FROM users WHERE \"\"=\"",
+        ];
+
+        for username in usernames {
+            create_user_with_password(&client, &argon2, username, "pw", Some("a'b@example.com"))
+                .await
+                .unwrap();
+            let id = get_user_id(&client, username).await.unwrap();
+            assert_eq!(get_user(&client, id).await.unwrap().username, username);
+            assert_eq!(
+                get_email(&client, username).await.unwrap().as_deref(),
+                Some("a'b@example.com")
+            );
+        }
+        #[cfg(feature = "passkey")]
+        {
+            let id = create_user(&client, "passkey'); DROP TABLE users; --")
+                .await
+                .unwrap();
+            assert_eq!(
+                get_user_id(&client, "passkey'); DROP TABLE users; --")
+                    .await
+                    .unwrap(),
+                id
+            );
+        }
+
+        // every name was stored as-is, and none matched anyone else's
+        let mut stored: Vec<String> = all_users(&client)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|user| user.username)
+            .collect();
+        stored.retain(|username| !username.starts_with("passkey"));
+        assert_eq!(stored, usernames);
+        assert!(matches!(
+            get_user_id(&client, "x").await,
+            Err(Errors::DbUserNotFound(_))
+        ));
+    }
 }
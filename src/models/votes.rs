@@ -0,0 +1,249 @@
+use axum_sessions::async_session::chrono;
+use libsql_client::Client;
+use uuid::Uuid;
+
+use crate::{
+    errors::Errors,
+    models::{db::Query, proposals::ProposalStatus},
+};
+
+pub mod tally;
+
+pub use tally::{DecisionRule, Outcome, Tally, Threshold, VoteChoice};
+
+/// records `userid`'s vote on a proposal, replacing any earlier vote they cast on it.
+/// the proposal is checked in the same statement, so a vote can't land after it closes
+pub async fn cast_vote(
+    db: &Client,
+    proposal_id: i64,
+    userid: Uuid,
+    choice: VoteChoice,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Result<(), Errors> {
+    let now = now.to_rfc3339();
+    let rs = Query::new(
+        "INSERT INTO votes (proposalId, userId, choice, createdAt, updatedAt)
+            SELECT ?, ?, ?, ?, ? WHERE EXISTS (
+                SELECT 1 FROM proposals WHERE id = ? AND status = 'open' AND closesAt > ?
+            )
+            ON CONFLICT (proposalId, userId)
+            DO UPDATE SET choice = excluded.choice, updatedAt = excluded.updatedAt;",
+    )
+    .bind(proposal_id)
+    .bind(userid.urn().to_string())
+    .bind(choice.to_string())
+    .bind(now.as_str())
+    .bind(now.as_str())
+    .bind(proposal_id)
+    .bind(now.as_str())
+    .execute(db)
+    .await?;
+    if rs.rows_affected == 0 {
+        return Err(Errors::ProposalNotInStatus(
+            proposal_id,
+            ProposalStatus::Open,
+        ));
+    }
+    Ok(())
+}
+
+/// only changes something if the votes on a proposal still add up to `tally`,
+/// so statements chained after it in a batch are skipped if a vote came in since
+pub(crate) fn unchanged_since(proposal_id: i64, tally: &Tally) -> Query {
+    Query::new(
+        "UPDATE proposals SET updatedAt = updatedAt WHERE id = ? AND (
+            SELECT COUNT(*) FILTER (WHERE choice = 'yes'),
+                COUNT(*) FILTER (WHERE choice = 'no'),
+                COUNT(*) FILTER (WHERE choice = 'abstain'),
+                COUNT(*) FILTER (WHERE choice = 'block')
+            FROM votes WHERE proposalId = ?
+        ) = (?, ?, ?, ?);",
+    )
+    .bind(proposal_id)
+    .bind(proposal_id)
+    .bind(i64::from(tally.yes))
+    .bind(i64::from(tally.no))
+    .bind(i64::from(tally.abstain))
+    .bind(i64::from(tally.block))
+}
+
+pub async fn get_votes(db: &Client, proposal_id: i64) -> Result<Vec<VoteChoice>, Errors> {
+    Query::new("SELECT choice FROM votes WHERE proposalId = ?;")
+        .bind(proposal_id)
+        .fetch(db)
+        .await?
+        .iter()
+        .map(|row| {
+            row.try_column::<&str>("choice")
+                .map_err(Errors::DbFetchError)?
+                .parse()
+        })
+        .collect()
+}
+
+pub async fn get_user_vote(
+    db: &Client,
+    proposal_id: i64,
+    userid: Uuid,
+) -> Result<Option<VoteChoice>, Errors> {
+    let row = Query::new("SELECT choice FROM votes WHERE proposalId = ? AND userId = ?;")
+        .bind(proposal_id)
+        .bind(userid.urn().to_string())
+        .fetch_one(db)
+        .await?;
+    match row {
+        Some(row) => row
+            .try_column::<&str>("choice")
+            .map_err(Errors::DbFetchError)?
+            .parse()
+            .map(Some),
+        None => Ok(None),
+    }
+}
+
+/// tallies every vote cast on a proposal, counting every member who isn't disabled
+/// as an eligible voter.
+pub async fn tally_proposal(
+    db: &Client,
+    proposal_id: i64,
+    rule: &DecisionRule,
+) -> Result<Tally, Errors> {
+    let votes = get_votes(db, proposal_id).await?;
+    let eligible = crate::models::users::count_active_users(db).await?;
+    Ok(tally::tally(rule, &votes, eligible))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// an open proposal to vote on, by a new author
+    async fn open_proposal(client: &Client, author: &str) -> i64 {
+        let argon2 = argon2::Argon2::default();
+        crate::models::users::create_user_with_password(client, &argon2, author, "pw", None)
+            .await
+            .unwrap();
+        let author = crate::models::users::get_user_id(client, author)
+            .await
+            .unwrap();
+        let rule = DecisionRule::default();
+        let id = crate::models::proposals::create_proposal(client, author, "title", "", &rule)
+            .await
+            .unwrap();
+        let closes_at = chrono::offset::Utc::now() + chrono::Duration::days(1);
+        crate::models::proposals::open_proposal(client, id, closes_at)
+            .await
+            .unwrap();
+        id
+    }
+
+    #[tokio::test]
+    async fn test_one_vote_per_member() {
+        let client = libsql_client::Client::in_memory().unwrap();
+        crate::models::init_db(&client).await.unwrap();
+        open_proposal(&client, "member").await;
+        open_proposal(&client, "other").await;
+        let member = crate::models::users::get_user_id(&client, "member")
+            .await
+            .unwrap();
+
+        cast_vote(
+            &client,
+            1,
+            member,
+            VoteChoice::Yes,
+            chrono::offset::Utc::now(),
+        )
+        .await
+        .unwrap();
+        cast_vote(
+            &client,
+            1,
+            member,
+            VoteChoice::Block,
+            chrono::offset::Utc::now(),
+        )
+        .await
+        .unwrap();
+        cast_vote(
+            &client,
+            2,
+            member,
+            VoteChoice::No,
+            chrono::offset::Utc::now(),
+        )
+        .await
+        .unwrap();
+        // there's no proposal 3 to vote on
+        assert!(matches!(
+            cast_vote(
+                &client,
+                3,
+                member,
+                VoteChoice::Yes,
+                chrono::offset::Utc::now()
+            )
+            .await,
+            Err(Errors::ProposalNotInStatus(3, ProposalStatus::Open))
+        ));
+
+        assert_eq!(
+            get_votes(&client, 1).await.unwrap(),
+            vec![VoteChoice::Block]
+        );
+        assert_eq!(
+            get_user_vote(&client, 2, member).await.unwrap(),
+            Some(VoteChoice::No)
+        );
+        assert_eq!(get_user_vote(&client, 3, member).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_unchanged_since() {
+        let client = libsql_client::Client::in_memory().unwrap();
+        crate::models::init_db(&client).await.unwrap();
+        let id = open_proposal(&client, "member").await;
+        let member = crate::models::users::get_user_id(&client, "member")
+            .await
+            .unwrap();
+        let rule = DecisionRule::default();
+
+        let tally = tally_proposal(&client, id, &rule).await.unwrap();
+        let rs = unchanged_since(id, &tally).execute(&client).await.unwrap();
+        assert_eq!(rs.rows_affected, 1);
+
+        // a vote lands after the tally
+        cast_vote(
+            &client,
+            id,
+            member,
+            VoteChoice::Yes,
+            chrono::offset::Utc::now(),
+        )
+        .await
+        .unwrap();
+        let rs = unchanged_since(id, &tally).execute(&client).await.unwrap();
+        assert_eq!(rs.rows_affected, 0);
+    }
+
+    #[tokio::test]
+    async fn test_disabled_members_are_not_eligible() {
+        let client = libsql_client::Client::in_memory().unwrap();
+        crate::models::init_db(&client).await.unwrap();
+        let id = open_proposal(&client, "member").await;
+        open_proposal(&client, "disabled").await;
+        let rule = DecisionRule::default();
+        assert_eq!(
+            tally_proposal(&client, id, &rule).await.unwrap().eligible,
+            2
+        );
+
+        crate::models::users::set_disabled(&client, "disabled", true)
+            .await
+            .unwrap();
+        assert_eq!(
+            tally_proposal(&client, id, &rule).await.unwrap().eligible,
+            1
+        );
+    }
+}
@@ -0,0 +1,285 @@
+use std::{fmt::Display, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::Errors;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum VoteChoice {
+    Yes,
+    No,
+    Abstain,
+    Block,
+}
+
+impl FromStr for VoteChoice {
+    type Err = Errors;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "yes" => Ok(VoteChoice::Yes),
+            "no" => Ok(VoteChoice::No),
+            "abstain" => Ok(VoteChoice::Abstain),
+            "block" => Ok(VoteChoice::Block),
+            _ => Err(Errors::VoteChoiceParseError(s.to_string())),
+        }
+    }
+}
+
+impl Display for VoteChoice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            VoteChoice::Yes => "yes",
+            VoteChoice::No => "no",
+            VoteChoice::Abstain => "abstain",
+            VoteChoice::Block => "block",
+        })
+    }
+}
+
+/// how many yes votes a proposal needs to pass.
+/// stored in the db as `majority`, `supermajority:2/3` or `consensus`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String", into = "String")]
+pub enum Threshold {
+    /// more yes than no votes; blocks count as no
+    Majority,
+    /// at least `numerator/denominator` of the yes/no/block votes are yes
+    Supermajority { numerator: u32, denominator: u32 },
+    /// at least one yes and no blocks; no votes stand aside
+    Consensus,
+}
+
+impl FromStr for Threshold {
+    type Err = Errors;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || Errors::ThresholdParseError(s.to_string());
+        match s.to_lowercase().split_once(':') {
+            None if s.eq_ignore_ascii_case("majority") => Ok(Threshold::Majority),
+            None if s.eq_ignore_ascii_case("consensus") => Ok(Threshold::Consensus),
+            Some(("supermajority", fraction)) => {
+                let (numerator, denominator) = fraction.split_once('/').ok_or_else(err)?;
+                let numerator: u32 = numerator.trim().parse().map_err(|_| err())?;
+                let denominator: u32 = denominator.trim().parse().map_err(|_| err())?;
+                if denominator == 0 || numerator > denominator {
+                    return Err(err());
+                }
+                Ok(Threshold::Supermajority {
+                    numerator,
+                    denominator,
+                })
+            }
+            _ => Err(err()),
+        }
+    }
+}
+
+impl Display for Threshold {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Threshold::Majority => f.write_str("majority"),
+            Threshold::Supermajority {
+                numerator,
+                denominator,
+            } => write!(f, "supermajority:{}/{}", numerator, denominator),
+            Threshold::Consensus => f.write_str("consensus"),
+        }
+    }
+}
+
+impl TryFrom<String> for Threshold {
+    type Error = Errors;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<Threshold> for String {
+    fn from(value: Threshold) -> Self {
+        value.to_string()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecisionRule {
+    pub threshold: Threshold,
+    /// percentage of eligible members that must vote (abstentions count), 0 for none
+    pub quorum: u32,
+}
+
+impl Default for DecisionRule {
+    fn default() -> Self {
+        Self {
+            threshold: Threshold::Majority,
+            quorum: 0,
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Outcome {
+    Passed,
+    Failed,
+    Blocked,
+    NoQuorum,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Tally {
+    pub yes: u32,
+    pub no: u32,
+    pub abstain: u32,
+    pub block: u32,
+    pub eligible: u32,
+    pub quorum_met: bool,
+    pub outcome: Outcome,
+}
+
+/// computes the outcome of a vote under `rule`, given every vote cast
+/// and the number of members eligible to vote.
+pub fn tally(rule: &DecisionRule, votes: &[VoteChoice], eligible: u32) -> Tally {
+    let count = |choice: VoteChoice| votes.iter().filter(|v| **v == choice).count() as u32;
+    let (yes, no, abstain, block) = (
+        count(VoteChoice::Yes),
+        count(VoteChoice::No),
+        count(VoteChoice::Abstain),
+        count(VoteChoice::Block),
+    );
+
+    let cast = votes.len() as u64;
+    let quorum_met = cast * 100 >= u64::from(rule.quorum) * u64::from(eligible);
+
+    let outcome = if !quorum_met {
+        Outcome::NoQuorum
+    } else {
+        match rule.threshold {
+            Threshold::Majority if yes > no + block => Outcome::Passed,
+            Threshold::Supermajority {
+                numerator,
+                denominator,
+            } if yes > 0
+                && u64::from(yes) * u64::from(denominator)
+                    >= u64::from(numerator) * u64::from(yes + no + block) =>
+            {
+                Outcome::Passed
+            }
+            Threshold::Consensus if block > 0 => Outcome::Blocked,
+            Threshold::Consensus if yes > 0 => Outcome::Passed,
+            _ => Outcome::Failed,
+        }
+    };
+
+    Tally {
+        yes,
+        no,
+        abstain,
+        block,
+        eligible,
+        quorum_met,
+        outcome,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use VoteChoice::*;
+
+    fn rule(threshold: &str, quorum: u32) -> DecisionRule {
+        DecisionRule {
+            threshold: threshold.parse().unwrap(),
+            quorum,
+        }
+    }
+
+    #[test]
+    fn test_majority() {
+        let majority = rule("majority", 0);
+        assert_eq!(
+            tally(&majority, &[Yes, Yes, No], 3).outcome,
+            Outcome::Passed
+        );
+        assert_eq!(tally(&majority, &[Yes, No], 3).outcome, Outcome::Failed);
+        assert_eq!(
+            tally(&majority, &[Yes, Yes, No, Block], 4).outcome,
+            Outcome::Failed
+        );
+        assert_eq!(
+            tally(&majority, &[Yes, Abstain, Abstain], 3).outcome,
+            Outcome::Passed
+        );
+        assert_eq!(tally(&majority, &[], 3).outcome, Outcome::Failed);
+    }
+
+    #[test]
+    fn test_supermajority() {
+        let two_thirds = rule("supermajority:2/3", 0);
+        assert_eq!(
+            tally(&two_thirds, &[Yes, Yes, No], 3).outcome,
+            Outcome::Passed
+        );
+        assert_eq!(
+            tally(&two_thirds, &[Yes, Yes, No, No], 4).outcome,
+            Outcome::Failed
+        );
+        assert_eq!(
+            tally(&two_thirds, &[Yes, Yes, Yes, No, Abstain], 5).outcome,
+            Outcome::Passed
+        );
+        assert_eq!(tally(&two_thirds, &[Abstain], 1).outcome, Outcome::Failed);
+    }
+
+    #[test]
+    fn test_consensus() {
+        let consensus = rule("consensus", 0);
+        assert_eq!(
+            tally(&consensus, &[Yes, No, Abstain], 3).outcome,
+            Outcome::Passed
+        );
+        assert_eq!(
+            tally(&consensus, &[Yes, Yes, Block], 3).outcome,
+            Outcome::Blocked
+        );
+        assert_eq!(tally(&consensus, &[Abstain], 3).outcome, Outcome::Failed);
+    }
+
+    #[test]
+    fn test_quorum() {
+        let quorum = rule("majority", 50);
+        let result = tally(&quorum, &[Yes], 3);
+        assert!(!result.quorum_met);
+        assert_eq!(result.outcome, Outcome::NoQuorum);
+
+        let result = tally(&quorum, &[Yes, Abstain], 4);
+        assert!(result.quorum_met);
+        assert_eq!(result.outcome, Outcome::Passed);
+    }
+
+    #[test]
+    fn test_threshold_parsing() {
+        assert_eq!(
+            "Majority".parse::<Threshold>().unwrap(),
+            Threshold::Majority
+        );
+        assert_eq!(
+            "supermajority:3/4".parse::<Threshold>().unwrap(),
+            Threshold::Supermajority {
+                numerator: 3,
+                denominator: 4
+            }
+        );
+        assert!("supermajority:4/3".parse::<Threshold>().is_err());
+        assert!("supermajority:1/0".parse::<Threshold>().is_err());
+        assert!("unanimous".parse::<Threshold>().is_err());
+        for threshold in ["majority", "supermajority:2/3", "consensus"] {
+            assert_eq!(
+                threshold.parse::<Threshold>().unwrap().to_string(),
+                threshold
+            );
+        }
+    }
+}
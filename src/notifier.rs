@@ -0,0 +1,253 @@
+use std::{path::PathBuf, sync::Arc};
+
+use axum_sessions::async_session::{
+    async_trait,
+    chrono::{DateTime, Utc},
+};
+use serde_json::json;
+use tera::Tera;
+use tokio::{fs::OpenOptions, io::AsyncWriteExt};
+use url::Url;
+
+use crate::{
+    errors::Errors,
+    mailer::Email,
+    models::repositories::{OutboxRepository, UserRepository},
+};
+
+/// something the app needs to tell a user outside of the browser
+#[derive(Debug, Clone)]
+pub enum Notification {
+    PasswordReset {
+        username: String,
+        link: Url,
+        expires_at: DateTime<Utc>,
+    },
+    /// sent to someone who doesn't have an account yet, so it's addressed by email
+    Invitation {
+        email: String,
+        invited_by: String,
+        link: Url,
+        expires_at: DateTime<Utc>,
+    },
+}
+
+impl Notification {
+    /// the username or, for people without an account, email address this is for
+    pub fn recipient(&self) -> &str {
+        match self {
+            Notification::PasswordReset { username, .. } => username,
+            Notification::Invitation { email, .. } => email,
+        }
+    }
+
+    pub fn body(&self) -> String {
+        match self {
+            Notification::PasswordReset {
+                link, expires_at, ..
+            } => format!(
+                "Reset your password at {} before {}. If you didn't ask to reset it, you can ignore this.",
+                link,
+                expires_at.to_rfc3339()
+            ),
+            Notification::Invitation {
+                invited_by,
+                link,
+                expires_at,
+                ..
+            } => format!(
+                "{} invited you to join. Register at {} before {}.",
+                invited_by,
+                link,
+                expires_at.to_rfc3339()
+            ),
+        }
+    }
+}
+
+/// delivers notifications to users; swapped out per environment
+#[async_trait]
+pub trait Notifier: Send + Sync {
+    async fn notify(&self, notification: Notification) -> Result<(), Errors>;
+}
+
+/// emails notifications to the user's address on file, through the outbox
+pub struct MailNotifier {
+    outbox: Arc<dyn OutboxRepository>,
+    users: Arc<dyn UserRepository>,
+    templates: Tera,
+}
+
+impl MailNotifier {
+    pub fn new(
+        outbox: Arc<dyn OutboxRepository>,
+        users: Arc<dyn UserRepository>,
+        templates: Tera,
+    ) -> Self {
+        Self {
+            outbox,
+            users,
+            templates,
+        }
+    }
+}
+
+#[async_trait]
+impl Notifier for MailNotifier {
+    async fn notify(&self, notification: Notification) -> Result<(), Errors> {
+        let email = match &notification {
+            Notification::PasswordReset {
+                username,
+                link,
+                expires_at,
+            } => {
+                let to = self
+                    .users
+                    .get_email(username)
+                    .await?
+                    .ok_or(Errors::UserHasNoEmail(username.to_string()))?;
+                Email::render(
+                    &self.templates,
+                    "password_reset",
+                    to,
+                    "Reset your password".to_string(),
+                    &json!({
+                        "username": username,
+                        "link": link.as_str(),
+                        "expires_at": expires_at.to_rfc3339(),
+                    }),
+                )?
+            }
+            Notification::Invitation {
+                email,
+                invited_by,
+                link,
+                expires_at,
+            } => Email::render(
+                &self.templates,
+                "invitation",
+                email.clone(),
+                format!("{} invited you to worker.coop", invited_by),
+                &json!({
+                    "invited_by": invited_by,
+                    "link": link.as_str(),
+                    "expires_at": expires_at.to_rfc3339(),
+                }),
+            )?,
+        };
+        self.outbox.enqueue(&email, Utc::now()).await
+    }
+}
+
+/// appends notifications to a file, one per line
+pub struct FileNotifier {
+    path: PathBuf,
+}
+
+impl FileNotifier {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+}
+
+#[async_trait]
+impl Notifier for FileNotifier {
+    async fn notify(&self, notification: Notification) -> Result<(), Errors> {
+        let line = format!(
+            "{} {}: {}\n",
+            Utc::now().to_rfc3339(),
+            notification.recipient(),
+            notification.body()
+        );
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await
+            .map_err(Errors::NotifierError)?;
+        file.write_all(line.as_bytes())
+            .await
+            .map_err(Errors::NotifierError)
+    }
+}
+
+#[cfg(test)]
+pub use recording::RecordingNotifier;
+
+#[cfg(test)]
+mod tests {
+    use argon2::Params;
+
+    use super::*;
+    use crate::models::{passwords, repositories::LibsqlRepository, users};
+
+    #[tokio::test]
+    async fn test_mail_notifier() {
+        let client = Arc::new(libsql_client::Client::in_memory().unwrap());
+        crate::models::init_db(&client).await.unwrap();
+        let argon2 = passwords::hasher(Params::new(1024, 1, 1, None).unwrap());
+        users::create_user_with_password(&client, &argon2, "mailed", "pw", Some("a@example.com"))
+            .await
+            .unwrap();
+        users::create_user_with_password(&client, &argon2, "unmailed", "pw", None)
+            .await
+            .unwrap();
+        let templates = Tera::new("src/ui/templates/**/*.{html,txt}").unwrap();
+        let repository = Arc::new(LibsqlRepository::new(client.clone()));
+        let notifier = MailNotifier::new(repository.clone(), repository.clone(), templates);
+        let reset = |username: &str| Notification::PasswordReset {
+            username: username.to_string(),
+            link: Url::parse("http://localhost:8080/auth/password/reset?token=abc").unwrap(),
+            expires_at: Utc::now(),
+        };
+
+        notifier.notify(reset("mailed")).await.unwrap();
+        assert!(matches!(
+            notifier.notify(reset("unmailed")).await,
+            Err(Errors::UserHasNoEmail(_))
+        ));
+
+        // invitations go straight to the invited address
+        notifier
+            .notify(Notification::Invitation {
+                email: "new@example.com".to_string(),
+                invited_by: "mailed".to_string(),
+                link: Url::parse("http://localhost:8080/auth/register?invite=def").unwrap(),
+                expires_at: Utc::now(),
+            })
+            .await
+            .unwrap();
+
+        let queued = repository.due(Utc::now()).await.unwrap();
+        assert_eq!(queued.len(), 2);
+        assert_eq!(queued[0].email.to, "a@example.com");
+        assert!(queued[0].email.text.contains("token=abc"));
+        assert_eq!(queued[1].email.to, "new@example.com");
+        assert!(queued[1].email.text.contains("invite=def"));
+    }
+}
+
+#[cfg(test)]
+mod recording {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    /// keeps every notification in memory, so tests can read what was sent
+    #[derive(Default)]
+    pub struct RecordingNotifier(Mutex<Vec<Notification>>);
+
+    impl RecordingNotifier {
+        pub fn sent(&self) -> Vec<Notification> {
+            self.0.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait]
+    impl Notifier for RecordingNotifier {
+        async fn notify(&self, notification: Notification) -> Result<(), Errors> {
+            self.0.lock().unwrap().push(notification);
+            Ok(())
+        }
+    }
+}
@@ -1,22 +1,59 @@
 use axum::{
-    response::{ErrorResponse, Html},
+    middleware::Next,
+    response::{ErrorResponse, Html, IntoResponse, Response},
     Extension,
 };
-use axum_sessions::extractors::ReadableSession;
-use http::Request;
+use http::{header, Request};
 use hyper::Body;
+use log::error;
 
-use crate::{controllers, handle_error, state::AppState};
+use crate::{
+    controllers::{self, auth::current_user::OptionalUser},
+    errors::ErrorBody,
+    handle_error,
+    state::AppState,
+    views,
+};
 
 pub mod router;
 
 pub async fn root(
     Extension(app): Extension<AppState>,
-    session: ReadableSession,
+    OptionalUser(user): OptionalUser,
     req: Request<Body>,
 ) -> Result<Html<String>, ErrorResponse> {
-    match controllers::get_index(app, session, req).await {
+    match controllers::get_index(app, user, req).await {
         Ok(html) => Ok(html),
         Err(e) => Err(handle_error("error rendering index", e)),
     }
 }
+
+/// swaps the json body of an `Errors` response for a rendered message when htmx made the
+/// request, so it can be shown in place. everything else passes through untouched
+pub async fn render_errors<B>(req: Request<B>, next: Next<B>) -> Response {
+    let htmx = req.headers().contains_key("HX-Request");
+    let app = req.extensions().get::<AppState>().cloned();
+    let response = next.run(req).await;
+
+    if !htmx {
+        return response;
+    }
+    let (Some(app), Some(body)) = (app, response.extensions().get::<ErrorBody>().cloned()) else {
+        return response;
+    };
+    match views::error_partial(&app.templates, &body) {
+        Ok(html) => {
+            let mut page = (response.status(), html).into_response();
+            // a rate limited client still needs to know when to come back
+            if let Some(retry_after) = response.headers().get(header::RETRY_AFTER) {
+                page.headers_mut()
+                    .insert(header::RETRY_AFTER, retry_after.clone());
+            }
+            page
+        }
+        Err(e) => {
+            error!("error rendering error partial: {:?}", e);
+            response
+        }
+    }
+}
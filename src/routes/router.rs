@@ -1,11 +1,21 @@
 use axum::{
-    routing::{get, post},
+    middleware,
+    routing::{get, post, put},
     Router,
 };
 use log::info;
 
+#[cfg(feature = "passkey")]
+use crate::controllers::auth::passkey_auth;
 use crate::{
-    controllers::auth::{create_password_registration, login},
+    controllers::{
+        admin,
+        auth::{
+            create_password_registration, get_registration, login, logout, logout_everywhere,
+            password_reset,
+        },
+        invitations, proposals,
+    },
     errors::Errors,
     routes,
 };
@@ -14,7 +24,14 @@ pub async fn init() -> Result<Router, Errors> {
     info!("intializing router");
     let router = Router::new()
         .route("/", get(routes::root))
-        .nest("/auth", auth_router());
+        .nest("/auth", auth_router())
+        .route(
+            "/invitations",
+            get(invitations::get_invitations).post(invitations::create_invitation),
+        )
+        .nest("/proposals", proposals_router())
+        .nest("/admin", admin_router())
+        .layer(middleware::from_fn(routes::render_errors));
     info!("done initializing router.");
     Ok(router)
 }
@@ -22,22 +39,45 @@ pub async fn init() -> Result<Router, Errors> {
 #[allow(clippy::let_and_return)]
 fn auth_router() -> Router {
     let router = Router::new()
+        .route("/register", get(get_registration))
         .route("/password/register", post(create_password_registration))
-        .route("/password/login", post(login));
+        .route("/password/login", post(login))
+        .route(
+            "/password/forgot",
+            get(password_reset::get_forgot_password).post(password_reset::forgot_password),
+        )
+        .route(
+            "/password/reset",
+            get(password_reset::get_reset_password).post(password_reset::reset_password),
+        )
+        .route("/logout", post(logout))
+        .route("/logout/everywhere", post(logout_everywhere));
 
-    #[cfg(passkey)]
-    router.nest("/passkey", passkey_auth::get_router());
+    #[cfg(feature = "passkey")]
+    let router = router.nest("/passkey", passkey_auth::get_router());
 
-    #[cfg(not(passkey))]
     router
 }
 
-#[cfg(passkey)]
-fn get_router() -> Router {
+fn proposals_router() -> Router {
     Router::new()
         .route(
-            "/registration/options",
-            post(get_passkey_registration_options),
+            "/",
+            get(proposals::list_proposals).post(proposals::create_proposal),
         )
-        .route("/registration/create", post(create_passkey_registration))
+        .route(
+            "/:id",
+            get(proposals::get_proposal).put(proposals::update_proposal),
+        )
+        .route("/:id/open", post(proposals::open_proposal))
+        .route("/:id/close", post(proposals::close_proposal))
+        .route("/:id/withdraw", post(proposals::withdraw_proposal))
+        .route("/:id/votes", post(proposals::cast_vote))
+}
+
+fn admin_router() -> Router {
+    Router::new()
+        .route("/members", get(admin::list_members))
+        .route("/members/:username/role", put(admin::set_role))
+        .route("/lockouts", get(admin::list_lockouts))
 }
@@ -1,64 +1,123 @@
+use argon2::Argon2;
+use axum_sessions::async_session::chrono::Duration;
 use std::{env, sync::Arc};
 use tera::Tera;
+use url::Url;
 
-#[cfg(passkey)]
-use webauthn_rs::{prelude::Url, Webauthn, WebauthnBuilder};
+#[cfg(feature = "passkey")]
+use webauthn_rs::{Webauthn, WebauthnBuilder};
 
-#[cfg(passkey)]
-use crate::Error;
+use crate::{
+    clock::{Clock, SystemClock},
+    config::Config,
+    mailer::{init_mailer, Mailer},
+    models::{
+        passwords,
+        repositories::{
+            InvitationRepository, LoginAttemptRepository, OutboxRepository,
+            PasswordResetRepository, ProposalRepository, Repository, UserRepository,
+        },
+        sessions::DynSessionStore,
+    },
+    notifier::{FileNotifier, MailNotifier, Notifier},
+    Error,
+};
+#[cfg(feature = "passkey")]
+use crate::{config::Stage, models::repositories::KeyRepository};
 
 #[derive(Clone)]
 pub struct AppState {
-    #[cfg(passkey)]
+    #[cfg(feature = "passkey")]
     pub webauthn: Arc<Webauthn>,
     pub templates: Tera,
-    pub db: Arc<libsql_client::Client>,
+    /// what the session layer reads and writes sessions through
+    pub sessions: DynSessionStore,
+    pub users: Arc<dyn UserRepository>,
+    #[cfg(feature = "passkey")]
+    pub keys: Arc<dyn KeyRepository>,
+    pub proposals: Arc<dyn ProposalRepository>,
+    pub invitations: Arc<dyn InvitationRepository>,
+    pub login_attempts: Arc<dyn LoginAttemptRepository>,
+    pub password_resets: Arc<dyn PasswordResetRepository>,
+    pub outbox: Arc<dyn OutboxRepository>,
+    pub clock: Arc<dyn Clock>,
+    pub argon2: Argon2<'static>,
+    pub notifier: Arc<dyn Notifier>,
+    pub mailer: Arc<dyn Mailer>,
+    pub public_url: Url,
+    pub password_reset_ttl: Duration,
+    pub open_registration: bool,
+    pub invitation_ttl: Duration,
+    pub client_ip_header: Option<String>,
 }
 
 impl AppState {
-    pub fn new(db_client: libsql_client::Client, templates: Tera) -> Self {
-        Self {
-            #[cfg(passkey)]
-            webauthn: Arc::new(init_webauthn()),
+    /// `repository` keeps all of the app's data, and `sessions` its sessions
+    pub fn new<R: Repository + 'static>(
+        config: &Config,
+        templates: Tera,
+        sessions: DynSessionStore,
+        repository: Arc<R>,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            #[cfg(feature = "passkey")]
+            webauthn: Arc::new(init_webauthn(config)?),
+            notifier: init_notifier(
+                config,
+                repository.clone(),
+                repository.clone(),
+                templates.clone(),
+            ),
+            mailer: init_mailer(config)?,
             templates,
-            db: Arc::new(db_client),
-        }
+            sessions,
+            users: repository.clone(),
+            #[cfg(feature = "passkey")]
+            keys: repository.clone(),
+            proposals: repository.clone(),
+            invitations: repository.clone(),
+            login_attempts: repository.clone(),
+            password_resets: repository.clone(),
+            outbox: repository,
+            clock: Arc::new(SystemClock),
+            argon2: passwords::hasher(config.argon2_params.clone()),
+            public_url: config.public_url.clone(),
+            password_reset_ttl: Duration::minutes(config.password_reset_ttl_mins),
+            open_registration: config.open_registration,
+            invitation_ttl: Duration::days(config.invitation_ttl_days),
+            client_ip_header: config.client_ip_header.clone(),
+        })
     }
 }
 
-#[cfg(passkey)]
-pub fn init_webauthn() -> Result<Webauthn, Error> {
-    // Effective domain name.
-    let rp_id = "lochstep.mcarthur.in";
-    // Url containing the effective domain name
-    // MUST include the port number!
-    let rp_origin = match Url::parse(format!("https://{}", rp_id).as_str()) {
-        Ok(url) => url,
-        Err(e) => return Err(Box::new(e)),
-    };
-
-    let builder = match WebauthnBuilder::new(rp_id, &rp_origin) {
-        Ok(builder) => builder,
-        Err(e) => return Err(Box::new(e)),
-    };
-
-    // Now, with the builder you can define other options.
-    // Set a "nice" relying party name. Has no security properties and
-    // may be changed in the future.
-    let builder = builder
-        .rp_name("Lochstep")
-        .allow_any_port(true)
-        .append_allowed_origin(&Url::parse("https://lochstep.mcarthur.in").unwrap())
-        .append_allowed_origin(&Url::parse("https://lochstep.drewmca.dev").unwrap())
-        .append_allowed_origin(&Url::parse("http://localhost").unwrap());
+fn init_notifier(
+    config: &Config,
+    outbox: Arc<dyn OutboxRepository>,
+    users: Arc<dyn UserRepository>,
+    templates: Tera,
+) -> Arc<dyn Notifier> {
+    match &config.notifier_file {
+        Some(path) => Arc::new(FileNotifier::new(path.clone())),
+        None => Arc::new(MailNotifier::new(outbox, users, templates)),
+    }
+}
 
-    // Consume the builder and create our webauthn instance.
-    let webauthn = match builder.build() {
-        Ok(webauthn) => webauthn,
-        Err(e) => return Err(Box::new(e)),
-    };
+#[cfg(feature = "passkey")]
+pub fn init_webauthn(config: &Config) -> Result<Webauthn, Error> {
+    let rp = &config.webauthn;
+    // `Config::validate` has already checked each origin belongs to the rp id
+    let builder = rp
+        .rp_origins
+        .iter()
+        .skip(1)
+        .fold(
+            WebauthnBuilder::new(&rp.rp_id, &rp.rp_origins[0])?,
+            |builder, origin| builder.append_allowed_origin(origin),
+        )
+        .rp_name(&rp.rp_name)
+        .allow_any_port(config.stage != Stage::Prod);
 
-    Ok(webauthn)
+    Ok(builder.build()?)
 }
 
 pub fn get_app_port() -> String {
@@ -1,7 +1,14 @@
-use std::path::{Path, PathBuf};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use axum::Extension;
-use http::{Method, Request};
+use axum::{body::BoxBody, Extension, Router};
+use axum_sessions::async_session::{
+    chrono::{Duration, Utc},
+    MemoryStore,
+};
+use http::{header, Method, Request, Response, StatusCode};
 use hyper::Body;
 use libsql_client::Client;
 use log::info;
@@ -10,10 +17,19 @@ use tower::ServiceExt;
 use tower_http::services::ServeDir;
 
 use crate::{
-    config::{Config, Stage},
+    clock::FakeClock,
+    config::{Config, MailConfig, Stage, WebauthnConfig},
+    constants::login::{BACKOFF_BASE_SECS, FREE_ATTEMPTS},
     controllers::auth::Login,
     errors::Errors,
-    init_session_layer, init_templates, models, routes,
+    init_session_layer, init_templates,
+    models::{
+        self,
+        repositories::{LibsqlRepository, MemoryRepository},
+        sessions::{DynSessionStore, LibsqlSessionStore},
+    },
+    notifier::{Notification, RecordingNotifier},
+    routes,
     state::AppState,
     Error,
 };
@@ -37,42 +53,65 @@ fn get_test_requests() -> Vec<Request<Body>> {
                 .unwrap(),
             ))
             .unwrap(),
-        Request::builder()
-            .method(Method::POST)
-            .uri("/auth/password/login")
-            .header("content-type", "application/json")
-            .body(Body::from(
-                serde_json::to_string(&Login {
-                    username: "test".to_string(),
-                    password: "test".to_string(),
-                })
-                .unwrap(),
-            ))
-            .unwrap(),
+        login_request("test", "test"),
         Request::builder()
             .method(Method::GET)
             .uri("/")
             .body(Body::empty())
             .unwrap(),
+        Request::builder()
+            .method(Method::POST)
+            .uri("/auth/logout")
+            .body(Body::empty())
+            .unwrap(),
     ]
 }
 
-fn test_config() -> Config {
+pub(crate) fn test_config() -> Config {
     Config {
         db_url: ":memory:".to_string(),
         db_token: None,
         stage: Stage::Test,
         log_level: log::Level::Debug,
+        session_secret: "test".repeat(16),
+        webauthn: WebauthnConfig {
+            rp_id: "lochstep.mcarthur.in".to_string(),
+            rp_name: "Lochstep".to_string(),
+            rp_origins: vec![url::Url::parse("https://lochstep.mcarthur.in").unwrap()],
+        },
+        // cheap enough to keep tests fast
+        argon2_params: argon2::Params::new(1024, 1, 1, None).unwrap(),
+        public_url: url::Url::parse("http://localhost:8080").unwrap(),
+        password_reset_ttl_mins: 60,
+        open_registration: true,
+        invitation_ttl_days: 7,
+        notifier_file: None,
+        mail: MailConfig {
+            from: "Lochstep <noreply@localhost>".to_string(),
+            smtp_host: None,
+            smtp_port: 587,
+            smtp_username: None,
+            smtp_password: None,
+            smtp_starttls: true,
+            dir: std::env::temp_dir().join("lochstep-test-mail"),
+        },
+        client_ip_header: None,
     }
 }
 
-async fn init_test_db_client() -> Result<Client, Errors> {
-    Client::in_memory().map_err(Errors::DbInitializationError)
+/// a migrated in-memory database
+async fn init_test_db() -> Result<Arc<Client>, Error> {
+    let db_client = Client::in_memory().map_err(Errors::DbInitializationError)?;
+    models::init_db(&db_client).await?;
+    Ok(Arc::new(db_client))
 }
 
-#[tokio::test]
-async fn happy_path() -> Result<(), Error> {
-    let config = test_config();
+pub(crate) async fn init_test_state(config: &Config) -> Result<AppState, Error> {
+    init_test_state_with_db(config, init_test_db().await?)
+}
+
+/// for tests that have to get at the database behind the repositories
+fn init_test_state_with_db(config: &Config, db: Arc<Client>) -> Result<AppState, Error> {
     let ui_dir = Path::new("src").join("ui");
 
     info!("intializing appstate");
@@ -80,19 +119,81 @@ async fn happy_path() -> Result<(), Error> {
         Ok(templates) => templates,
         Err(e) => return Err(e),
     };
-    let static_dir: PathBuf = ui_dir.join("static");
-
-    let db_client = init_test_db_client().await.unwrap();
-    models::init_db(&db_client).await.unwrap();
 
-    let state: AppState = AppState::new(db_client, templates);
+    let sessions = DynSessionStore::new(LibsqlSessionStore::new(db.clone()));
+    let state: AppState = AppState::new(
+        config,
+        templates,
+        sessions,
+        Arc::new(LibsqlRepository::new(db)),
+    )?;
     info!("done intializing appstate");
-    let router = routes::router::init()
+    Ok(state)
+}
+
+/// app state that keeps everything in memory, for unit testing controllers without a database.
+/// the repository is returned too, for setting up and inspecting what the handlers did
+pub(crate) async fn init_memory_state() -> Result<(AppState, Arc<MemoryRepository>), Error> {
+    let templates = init_templates(&Path::new("src").join("ui"))?;
+    let repository = Arc::new(MemoryRepository::new());
+    let sessions = DynSessionStore::new(MemoryStore::new());
+    let state = AppState::new(&test_config(), templates, sessions, repository.clone())?;
+    Ok((state, repository))
+}
+
+async fn init_test_router(config: &Config) -> Result<Router, Error> {
+    Ok(init_test_router_with_state(config, init_test_state(config).await?).await)
+}
+
+async fn init_test_router_with_state(config: &Config, state: AppState) -> Router {
+    let static_dir: PathBuf = Path::new("src").join("ui").join("static");
+    routes::router::init()
         .await
         .unwrap()
         .nest_service("/static", ServeDir::new(static_dir))
-        .layer(init_session_layer(&config))
-        .layer(Extension(state));
+        .layer(init_session_layer(config, state.sessions.clone()))
+        .layer(Extension(state))
+}
+
+fn login_request(username: &str, password: &str) -> Request<Body> {
+    Request::builder()
+        .method(Method::POST)
+        .uri("/auth/password/login")
+        .header("content-type", "application/json")
+        .body(Body::from(
+            serde_json::to_string(&Login {
+                username: username.to_string(),
+                password: password.to_string(),
+            })
+            .unwrap(),
+        ))
+        .unwrap()
+}
+
+fn request(method: Method, uri: &str, cookie: &str, body: Body) -> Request<Body> {
+    Request::builder()
+        .method(method)
+        .uri(uri)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::COOKIE, cookie)
+        .body(body)
+        .unwrap()
+}
+
+fn json_request(uri: &str, cookie: &str, body: String) -> Request<Body> {
+    request(Method::POST, uri, cookie, Body::from(body))
+}
+
+fn session_cookie(response: &Response<BoxBody>, cookie: &mut String) {
+    if let Some(set_cookie) = response.headers().get(header::SET_COOKIE) {
+        let set_cookie = set_cookie.to_str().unwrap();
+        *cookie = set_cookie.split(';').next().unwrap().to_string();
+    }
+}
+
+#[tokio::test]
+async fn happy_path() -> Result<(), Error> {
+    let router = init_test_router(&test_config()).await?;
 
     for req in get_test_requests() {
         let path = req.uri().path().to_string();
@@ -106,3 +207,750 @@ async fn happy_path() -> Result<(), Error> {
     }
     Ok(())
 }
+
+#[tokio::test]
+async fn login_throttling() -> Result<(), Error> {
+    let config = test_config();
+    let mut state = init_test_state(&config).await?;
+    let clock = Arc::new(FakeClock::new(Utc::now()));
+    state.clock = clock.clone();
+    state
+        .users
+        .create_user_with_password(&state.argon2, "test", "test", None)
+        .await?;
+    let router = init_test_router_with_state(&config, state).await;
+
+    for _ in 0..=FREE_ATTEMPTS {
+        let response = router
+            .clone()
+            .oneshot(login_request("test", "wrong"))
+            .await?;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    // even the right password is refused until the backoff has passed
+    let response = router
+        .clone()
+        .oneshot(login_request("test", "test"))
+        .await?;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(
+        response.headers()[header::RETRY_AFTER],
+        BACKOFF_BASE_SECS.to_string()
+    );
+
+    clock.advance(Duration::seconds(BACKOFF_BASE_SECS));
+    let response = router
+        .clone()
+        .oneshot(login_request("test", "test"))
+        .await?;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+    // logging in clears the username, but the address keeps counting, so one more guess
+    // from it backs off further rather than starting over
+    let response = router
+        .clone()
+        .oneshot(login_request("other", "wrong"))
+        .await?;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = router
+        .clone()
+        .oneshot(login_request("other", "wrong"))
+        .await?;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(
+        response.headers()[header::RETRY_AFTER],
+        (BACKOFF_BASE_SECS * 2).to_string()
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn login_throttling_behind_proxy() -> Result<(), Error> {
+    let config = Config {
+        client_ip_header: Some("x-forwarded-for".to_string()),
+        ..test_config()
+    };
+    let mut state = init_test_state(&config).await?;
+    state.clock = Arc::new(FakeClock::new(Utc::now()));
+    state
+        .users
+        .create_user_with_password(&state.argon2, "test", "test", None)
+        .await?;
+    let router = init_test_router_with_state(&config, state).await;
+    let login_from = |forwarded_for: &str, username: &str, password: &str| {
+        let mut request = login_request(username, password);
+        request
+            .headers_mut()
+            .insert("x-forwarded-for", forwarded_for.parse().unwrap());
+        request
+    };
+
+    // guesses at many accounts from one client are throttled by its address
+    for i in 0..=FREE_ATTEMPTS {
+        let response = router
+            .clone()
+            .oneshot(login_from("10.0.0.1", &format!("user{}", i), "wrong"))
+            .await?;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+    // and the client can't get out of it by adding addresses of its own before the proxy's
+    let response = router
+        .clone()
+        .oneshot(login_from("10.0.0.2, 10.0.0.1", "test", "test"))
+        .await?;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+    // while other clients behind the same proxy aren't affected
+    let response = router
+        .clone()
+        .oneshot(login_from("10.0.0.2", "test", "test"))
+        .await?;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    Ok(())
+}
+
+#[tokio::test]
+async fn password_reset() -> Result<(), Error> {
+    let config = test_config();
+    let mut state = init_test_state(&config).await?;
+    let notifier = Arc::new(RecordingNotifier::default());
+    state.notifier = notifier.clone();
+    state
+        .users
+        .create_user_with_password(&state.argon2, "test", "test", None)
+        .await?;
+    let router = init_test_router_with_state(&config, state).await;
+
+    let mut old_session = String::new();
+    let response = router
+        .clone()
+        .oneshot(login_request("test", "test"))
+        .await?;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    session_cookie(&response, &mut old_session);
+
+    // unknown users get the same response, but nothing is sent
+    for username in ["nobody", "test"] {
+        let response = router
+            .clone()
+            .oneshot(json_request(
+                "/auth/password/forgot",
+                "",
+                serde_json::json!({ "username": username }).to_string(),
+            ))
+            .await?;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+    }
+    let sent = notifier.sent();
+    assert_eq!(sent.len(), 1);
+    let Notification::PasswordReset { username, link, .. } = &sent[0] else {
+        panic!("expected a password reset, got {:?}", sent[0]);
+    };
+    assert_eq!(username, "test");
+    let token = link
+        .query_pairs()
+        .find(|(key, _)| key == "token")
+        .map(|(_, token)| token.to_string())
+        .unwrap();
+
+    let reset = serde_json::json!({ "token": token, "password": "new" }).to_string();
+    let response = router
+        .clone()
+        .oneshot(json_request("/auth/password/reset", "", reset.clone()))
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = router
+        .clone()
+        .oneshot(json_request("/auth/password/reset", "", reset))
+        .await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // the old password and every existing session stop working
+    let response = router
+        .clone()
+        .oneshot(login_request("test", "test"))
+        .await?;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = router
+        .clone()
+        .oneshot(request(
+            Method::GET,
+            "/proposals",
+            &old_session,
+            Body::empty(),
+        ))
+        .await?;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = router.clone().oneshot(login_request("test", "new")).await?;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+    // and asking for link after link is throttled, like failed logins are
+    let forgot = || {
+        json_request(
+            "/auth/password/forgot",
+            "",
+            serde_json::json!({ "username": "test" }).to_string(),
+        )
+    };
+    // two requests were made from this address already
+    for _ in 2..=FREE_ATTEMPTS {
+        let response = router.clone().oneshot(forgot()).await?;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+    }
+    let response = router.clone().oneshot(forgot()).await?;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(notifier.sent().len(), FREE_ATTEMPTS as usize);
+    Ok(())
+}
+
+#[tokio::test]
+async fn invitation_registration() -> Result<(), Error> {
+    let config = Config {
+        open_registration: false,
+        ..test_config()
+    };
+    let mut state = init_test_state(&config).await?;
+    let notifier = Arc::new(RecordingNotifier::default());
+    state.notifier = notifier.clone();
+    state
+        .users
+        .create_user_with_password(&state.argon2, "member", "pw", None)
+        .await?;
+    let users = state.users.clone();
+    let router = init_test_router_with_state(&config, state).await;
+
+    let register = |invite: Option<&str>, username: &str| {
+        json_request(
+            "/auth/password/register",
+            "",
+            serde_json::json!({ "username": username, "password": "pw", "invite": invite })
+                .to_string(),
+        )
+    };
+
+    // nobody gets in without an invitation, and only members can invite
+    let response = router.clone().oneshot(register(None, "crasher")).await?;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let invitation = serde_json::json!({ "email": "new@example.com" }).to_string();
+    let response = router
+        .clone()
+        .oneshot(json_request("/invitations", "", invitation.clone()))
+        .await?;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let mut cookie = String::new();
+    let response = router
+        .clone()
+        .oneshot(login_request("member", "pw"))
+        .await?;
+    session_cookie(&response, &mut cookie);
+    let response = router
+        .clone()
+        .oneshot(json_request("/invitations", &cookie, invitation))
+        .await?;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+    let sent = notifier.sent();
+    assert_eq!(sent.len(), 1);
+    let Notification::Invitation {
+        email,
+        invited_by,
+        link,
+        ..
+    } = &sent[0]
+    else {
+        panic!("expected an invitation, got {:?}", sent[0]);
+    };
+    assert_eq!(
+        (email.as_str(), invited_by.as_str()),
+        ("new@example.com", "member")
+    );
+    let token = link
+        .query_pairs()
+        .find(|(key, _)| key == "invite")
+        .map(|(_, token)| token.to_string())
+        .unwrap();
+
+    let response = router
+        .clone()
+        .oneshot(request(
+            Method::GET,
+            link.as_str().trim_start_matches("http://localhost:8080"),
+            "",
+            Body::empty(),
+        ))
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // a failed registration doesn't use the invitation up, but a successful one does
+    let response = router
+        .clone()
+        .oneshot(register(Some(&token), "member"))
+        .await?;
+    assert!(!response.status().is_success());
+    let response = router
+        .clone()
+        .oneshot(register(Some(&token), "newbie"))
+        .await?;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    let response = router
+        .clone()
+        .oneshot(register(Some(&token), "another"))
+        .await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+    // the invitee's account has the address they were invited at
+    let response = router
+        .clone()
+        .oneshot(login_request("newbie", "pw"))
+        .await?;
+    assert_eq!(response.status(), StatusCode::ACCEPTED);
+    assert_eq!(
+        users.get_email("newbie").await?,
+        Some("new@example.com".to_string())
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn current_user() -> Result<(), Error> {
+    let config = test_config();
+    let db = init_test_db().await?;
+    let state = init_test_state_with_db(&config, db.clone())?;
+    state
+        .users
+        .create_user_with_password(&state.argon2, "test", "test", None)
+        .await?;
+    let router = init_test_router_with_state(&config, state).await;
+    let page = |cookie: &str, htmx: bool| {
+        let builder = Request::builder()
+            .uri("/proposals")
+            .header(header::ACCEPT, "text/html")
+            .header(header::COOKIE, cookie);
+        let builder = match htmx {
+            true => builder.header("HX-Request", "true"),
+            false => builder,
+        };
+        builder.body(Body::empty()).unwrap()
+    };
+
+    // browsers are sent to log in, while htmx and api clients get a 401
+    let response = router.clone().oneshot(page("", false)).await?;
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    assert_eq!(response.headers()[header::LOCATION], "/");
+    let response = router.clone().oneshot(page("", true)).await?;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = router
+        .clone()
+        .oneshot(request(Method::GET, "/proposals", "", Body::empty()))
+        .await?;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+
+    let mut cookie = String::new();
+    let response = router
+        .clone()
+        .oneshot(login_request("test", "test"))
+        .await?;
+    session_cookie(&response, &mut cookie);
+    let response = router.clone().oneshot(page(&cookie, false)).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // a session outliving its user doesn't count
+    db.execute("DELETE FROM users;").await?;
+    let response = router.clone().oneshot(page(&cookie, false)).await?;
+    assert_eq!(response.status(), StatusCode::SEE_OTHER);
+    let response = router
+        .clone()
+        .oneshot(request(Method::GET, "/", &cookie, Body::empty()))
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // but failing to check it isn't mistaken for being logged out
+    db.execute("DROP TABLE users;").await?;
+    let response = router
+        .clone()
+        .oneshot(request(Method::GET, "/", &cookie, Body::empty()))
+        .await?;
+    assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    Ok(())
+}
+
+#[tokio::test]
+async fn logout_everywhere() -> Result<(), Error> {
+    let config = test_config();
+    let state = init_test_state(&config).await?;
+    state
+        .users
+        .create_user_with_password(&state.argon2, "test", "test", None)
+        .await?;
+    let router = init_test_router_with_state(&config, state).await;
+    let proposals = |cookie: &str| request(Method::GET, "/proposals", cookie, Body::empty());
+
+    // logged in from two browsers at once
+    let (mut laptop, mut phone) = (String::new(), String::new());
+    for cookie in [&mut laptop, &mut phone] {
+        let response = router
+            .clone()
+            .oneshot(login_request("test", "test"))
+            .await?;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        session_cookie(&response, cookie);
+    }
+    assert_ne!(laptop, phone);
+    for cookie in [&laptop, &phone] {
+        let response = router.clone().oneshot(proposals(cookie)).await?;
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    let response = router
+        .clone()
+        .oneshot(request(
+            Method::POST,
+            "/auth/logout/everywhere",
+            &laptop,
+            Body::empty(),
+        ))
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+
+    // the session that didn't ask is logged out as well
+    for cookie in [&phone, &laptop] {
+        let response = router.clone().oneshot(proposals(cookie)).await?;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    // and logging in again afterwards still works
+    let mut cookie = String::new();
+    let response = router
+        .clone()
+        .oneshot(login_request("test", "test"))
+        .await?;
+    session_cookie(&response, &mut cookie);
+    let response = router.clone().oneshot(proposals(&cookie)).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    Ok(())
+}
+
+#[tokio::test]
+async fn error_responses() -> Result<(), Error> {
+    let config = Config {
+        open_registration: false,
+        ..test_config()
+    };
+    let state = init_test_state(&config).await?;
+    state
+        .users
+        .create_user_with_password(&state.argon2, "test", "test", None)
+        .await?;
+    let router = init_test_router_with_state(&config, state).await;
+    let body = |response: Response<BoxBody>| async move {
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    };
+
+    // api clients get json with a stable code, the same for unknown users and wrong passwords
+    let unknown = router
+        .clone()
+        .oneshot(login_request("nobody", "test"))
+        .await?;
+    assert_eq!(unknown.status(), StatusCode::UNAUTHORIZED);
+    let wrong = router
+        .clone()
+        .oneshot(login_request("test", "wrong"))
+        .await?;
+    assert_eq!(wrong.status(), StatusCode::UNAUTHORIZED);
+    let unknown = body(unknown).await;
+    assert_eq!(unknown, body(wrong).await);
+    let json: serde_json::Value = serde_json::from_str(&unknown)?;
+    assert_eq!(json["code"], "invalid_credentials");
+    assert!(!unknown.contains("nobody"));
+
+    // htmx gets a message to swap into the page
+    let mut req = login_request("nobody", "test");
+    req.headers_mut()
+        .insert("HX-Request", "true".parse().unwrap());
+    let response = router.clone().oneshot(req).await?;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let html = body(response).await;
+    assert!(html.contains(r#"<p class="error" data-code="invalid_credentials">"#));
+
+    // bad input says what was wrong with it
+    let response = router.clone().oneshot(login_request("test", "")).await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let json: serde_json::Value = serde_json::from_str(&body(response).await)?;
+    assert_eq!(json["code"], "credentials_missing");
+
+    let reset = serde_json::json!({ "token": "token", "password": "" }).to_string();
+    let response = router
+        .clone()
+        .oneshot(json_request("/auth/password/reset", "", reset))
+        .await?;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let json: serde_json::Value = serde_json::from_str(&body(response).await)?;
+    assert_eq!(json["code"], "invalid_input");
+    assert_eq!(json["message"], "A password is required");
+
+    let register = serde_json::json!({ "username": "crasher", "password": "pw" }).to_string();
+    let response = router
+        .clone()
+        .oneshot(json_request("/auth/password/register", "", register))
+        .await?;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let json: serde_json::Value = serde_json::from_str(&body(response).await)?;
+    assert_eq!(json["code"], "invitation_required");
+
+    // throttled clients are told when to try again, htmx ones included
+    for _ in 0..=FREE_ATTEMPTS {
+        router
+            .clone()
+            .oneshot(login_request("test", "wrong"))
+            .await?;
+    }
+    let mut req = login_request("test", "test");
+    req.headers_mut()
+        .insert("HX-Request", "true".parse().unwrap());
+    let response = router.clone().oneshot(req).await?;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert!(response.headers().contains_key(header::RETRY_AFTER));
+    let html = body(response).await;
+    assert!(html.contains(r#"<p class="error" data-code="too_many_attempts">"#));
+    Ok(())
+}
+
+#[tokio::test]
+async fn roles() -> Result<(), Error> {
+    let config = test_config();
+    let state = init_test_state(&config).await?;
+    for username in ["boss", "member"] {
+        state
+            .users
+            .create_user_with_password(&state.argon2, username, "pw", None)
+            .await?;
+    }
+    let member_id = state.users.get_user_id("member").await?;
+    let proposal = state
+        .proposals
+        .create_proposal(member_id, "title", "description", &Default::default())
+        .await?;
+    state
+        .proposals
+        .open_proposal(proposal, Utc::now() + Duration::days(1))
+        .await?;
+    let router = init_test_router_with_state(&config, state).await;
+
+    let mut boss = String::new();
+    let mut member = String::new();
+    for (username, cookie) in [("boss", &mut boss), ("member", &mut member)] {
+        let response = router
+            .clone()
+            .oneshot(login_request(username, "pw"))
+            .await?;
+        session_cookie(&response, cookie);
+    }
+    let get = |uri: &str, cookie: &str| request(Method::GET, uri, cookie, Body::empty());
+    let close = format!("/proposals/{}/close", proposal);
+
+    // nobody logged in is a 401, and a member gets a page explaining the 403
+    let response = router.clone().oneshot(get("/admin/members", "")).await?;
+    assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    let response = router
+        .clone()
+        .oneshot(get("/admin/members", &member))
+        .await?;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let body = hyper::body::to_bytes(response.into_body()).await?;
+    assert!(String::from_utf8_lossy(&body).contains("You need to be an admin"));
+    let response = router
+        .clone()
+        .oneshot(json_request(&close, &member, String::new()))
+        .await?;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // the first account is an admin, and can promote others
+    let response = router.clone().oneshot(get("/admin/members", &boss)).await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = router
+        .clone()
+        .oneshot(request(
+            Method::PUT,
+            "/admin/members/member/role",
+            &boss,
+            Body::from(serde_json::json!({ "role": "steward" }).to_string()),
+        ))
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = router
+        .clone()
+        .oneshot(get("/admin/lockouts", &member))
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    let response = router
+        .clone()
+        .oneshot(get("/admin/members", &member))
+        .await?;
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+    // the only admin can't demote themselves
+    let response = router
+        .clone()
+        .oneshot(request(
+            Method::PUT,
+            "/admin/members/boss/role",
+            &boss,
+            Body::from(serde_json::json!({ "role": "member" }).to_string()),
+        ))
+        .await?;
+    assert_eq!(response.status(), StatusCode::CONFLICT);
+
+    let response = router
+        .clone()
+        .oneshot(json_request(&close, &boss, String::new()))
+        .await?;
+    assert_eq!(response.status(), StatusCode::OK);
+    Ok(())
+}
+
+#[cfg(feature = "passkey")]
+mod passkey {
+    use serde::de::DeserializeOwned;
+    use serde_json::json;
+    use webauthn_authenticator_rs::{softpasskey::SoftPasskey, WebauthnAuthenticator};
+    use webauthn_rs::prelude::{CreationChallengeResponse, RequestChallengeResponse};
+
+    use super::*;
+
+    async fn read_json<T: DeserializeOwned>(response: Response<BoxBody>) -> T {
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn passkey_registration_and_login() -> Result<(), Error> {
+        let config = test_config();
+        let router = init_test_router(&config).await?;
+        let origin = config.webauthn.rp_origins[0].clone();
+        let mut authenticator = WebauthnAuthenticator::new(SoftPasskey::new());
+        let mut cookie = String::new();
+        let username = json!({ "username": "passkey" }).to_string();
+
+        // registration
+        let response = router
+            .clone()
+            .oneshot(json_request(
+                "/auth/passkey/registration/options",
+                &cookie,
+                username.clone(),
+            ))
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        session_cookie(&response, &mut cookie);
+        let challenge: CreationChallengeResponse = read_json(response).await;
+
+        let credential = authenticator
+            .do_registration(origin.clone(), challenge)
+            .unwrap();
+        let first_credential_id = credential.id.clone();
+        let response = router
+            .clone()
+            .oneshot(json_request(
+                "/auth/passkey/registration/create",
+                &cookie,
+                serde_json::to_string(&credential)?,
+            ))
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        // authentication
+        let response = router
+            .clone()
+            .oneshot(json_request(
+                "/auth/passkey/authentication/options",
+                &cookie,
+                username,
+            ))
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        session_cookie(&response, &mut cookie);
+        let challenge: RequestChallengeResponse = read_json(response).await;
+
+        let credential = authenticator
+            .do_authentication(origin.clone(), challenge)
+            .unwrap();
+        let response = router
+            .clone()
+            .oneshot(json_request(
+                "/auth/passkey/authentication/finish",
+                &cookie,
+                serde_json::to_string(&credential)?,
+            ))
+            .await?;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        session_cookie(&response, &mut cookie);
+
+        // the only passkey can't be removed from an account without a password
+        let first_key = format!("/auth/passkey/keys/{}", first_credential_id);
+        let response = router
+            .clone()
+            .oneshot(request(Method::DELETE, &first_key, &cookie, Body::empty()))
+            .await?;
+        assert_eq!(response.status(), StatusCode::CONFLICT);
+
+        // a logged in user can add another key from a second authenticator
+        let response = router
+            .clone()
+            .oneshot(json_request(
+                "/auth/passkey/registration/options",
+                &cookie,
+                "{}".to_string(),
+            ))
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let challenge: CreationChallengeResponse = read_json(response).await;
+        let credential = WebauthnAuthenticator::new(SoftPasskey::new())
+            .do_registration(origin, challenge)
+            .unwrap();
+        let response = router
+            .clone()
+            .oneshot(json_request(
+                "/auth/passkey/registration/create",
+                &cookie,
+                serde_json::to_string(&credential)?,
+            ))
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = router
+            .clone()
+            .oneshot(request(
+                Method::PUT,
+                &first_key,
+                &cookie,
+                Body::from(json!({ "nickname": "laptop" }).to_string()),
+            ))
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let response = router
+            .clone()
+            .oneshot(request(
+                Method::GET,
+                "/auth/passkey/keys",
+                &cookie,
+                Body::empty(),
+            ))
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        let page = String::from_utf8_lossy(&body);
+        assert!(page.contains("laptop"));
+        assert!(page.contains("Passkey 2"));
+
+        let response = router
+            .clone()
+            .oneshot(request(Method::DELETE, &first_key, &cookie, Body::empty()))
+            .await?;
+        assert_eq!(response.status(), StatusCode::OK);
+        Ok(())
+    }
+}
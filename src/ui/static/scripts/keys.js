@@ -0,0 +1,50 @@
+///
+// registers another passkey for the logged in user
+///
+
+const encode = (buf) => Base64.fromUint8Array(new Uint8Array(buf), true);
+
+const addKey = async () => {
+  const opts = await fetch("/auth/passkey/registration/options", {
+    method: "POST",
+    credentials: "same-origin",
+    headers: {
+      "Content-Type": "application/json",
+    },
+    body: JSON.stringify({}),
+  }).then((res) => res.json());
+
+  opts.publicKey.challenge = Base64.toUint8Array(opts.publicKey.challenge);
+  opts.publicKey.user.id = Base64.toUint8Array(opts.publicKey.user.id);
+  opts.publicKey.excludeCredentials?.forEach(
+    (cred) => (cred.id = Base64.toUint8Array(cred.id))
+  );
+
+  const credential = await navigator.credentials.create({
+    publicKey: opts.publicKey,
+  });
+
+  const res = await fetch("/auth/passkey/registration/create", {
+    method: "POST",
+    credentials: "same-origin",
+    headers: {
+      "Content-Type": "application/json",
+    },
+    body: JSON.stringify({
+      id: credential.id,
+      rawId: encode(credential.rawId),
+      type: credential.type,
+      response: {
+        attestationObject: encode(credential.response.attestationObject),
+        clientDataJSON: encode(credential.response.clientDataJSON),
+      },
+    }),
+  });
+  if (res.ok) window.location.reload();
+};
+
+htmx
+  .find("section.keys button.add-key")
+  .addEventListener("click", () =>
+    addKey().catch((e) => console.error("error adding passkey: " + e))
+  );
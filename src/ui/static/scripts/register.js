@@ -92,6 +92,9 @@ const getPasskeyRegistrationOptions = async () => {
 const generateCredentials = async (opts) => {
   opts.publicKey.challenge = Base64.toUint8Array(opts.publicKey.challenge);
   opts.publicKey.user.id = Base64.toUint8Array(opts.publicKey.user.id);
+  opts.publicKey.excludeCredentials?.forEach(
+    (cred) => (cred.id = Base64.toUint8Array(cred.id))
+  );
 
   return await navigator.credentials.create({
     publicKey: opts.publicKey,
@@ -124,7 +127,64 @@ const createPasskeyRegistration = async (credential) => {
   });
 };
 
-const login = () => {};
+const login = async () => {
+  if (!(await isPasskeyAuthEnabled)) return;
+  await getPasskeyAuthenticationOptions()
+    .then((res) => res.json())
+    .then((opts) => getCredential(opts))
+    .then((cred) => finishPasskeyAuthentication(cred))
+    .catch((e) => console.error("error authenticating with passkey: " + e));
+};
+
+const getPasskeyAuthenticationOptions = async () => {
+  return await fetch("/auth/passkey/authentication/options", {
+    method: "POST",
+    credentials: "same-origin",
+    headers: {
+      "Content-Type": "application/json",
+    },
+    body: JSON.stringify({
+      username: htmx.find("input.username").value,
+    }),
+  });
+};
+
+const getCredential = async (opts) => {
+  opts.publicKey.challenge = Base64.toUint8Array(opts.publicKey.challenge);
+  opts.publicKey.allowCredentials?.forEach(
+    (cred) => (cred.id = Base64.toUint8Array(cred.id))
+  );
+
+  return await navigator.credentials.get({
+    publicKey: opts.publicKey,
+  });
+};
+
+const finishPasskeyAuthentication = async (credential) => {
+  const encode = (buf) => Base64.fromUint8Array(new Uint8Array(buf), true);
+  return await fetch("/auth/passkey/authentication/finish", {
+    method: "POST",
+    credentials: "same-origin",
+    headers: {
+      "Content-Type": "application/json",
+    },
+    body: JSON.stringify({
+      id: credential.id,
+      rawId: encode(credential.rawId),
+      type: credential.type,
+      response: {
+        authenticatorData: encode(credential.response.authenticatorData),
+        clientDataJSON: encode(credential.response.clientDataJSON),
+        signature: encode(credential.response.signature),
+        userHandle: credential.response.userHandle
+          ? encode(credential.response.userHandle)
+          : null,
+      },
+    }),
+  }).then((res) => {
+    if (res.ok) window.location.href = "/";
+  });
+};
 
 htmx
   .find("section.register button.register")
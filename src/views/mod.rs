@@ -1,17 +1,33 @@
 use axum::response::Html;
+use hyper::StatusCode;
 use log::error;
 use tera::{Context, Tera};
 
-use crate::{errors::Errors, models::users::User};
+#[cfg(feature = "passkey")]
+use crate::models::keys::Key;
+use crate::{
+    errors::{ErrorBody, Errors},
+    models::{
+        login_attempts::Lockout,
+        proposals::{Proposal, Transition},
+        roles::Role,
+        users::User,
+        votes::{Tally, VoteChoice},
+    },
+};
 
 pub fn homepage(
     templates: Tera,
     name: String,
+    role: Role,
     all_users: Vec<User>,
 ) -> Result<Html<String>, Errors> {
     let mut ctx = Context::new();
     ctx.insert("name", &name);
+    ctx.insert("is_steward", &(role >= Role::Steward));
+    ctx.insert("is_admin", &(role >= Role::Admin));
     ctx.insert("all_users", &all_users);
+    ctx.insert("passkeys_enabled", &cfg!(feature = "passkey"));
 
     match templates.render("homepage.html", &ctx) {
         Ok(html) => Ok(Html(html)),
@@ -19,21 +35,162 @@ pub fn homepage(
     }
 }
 
-pub fn login(templates: Tera) -> Html<String> {
-    Html(
-        templates
-            .render("login.html", &Context::new())
-            .expect("error rendering login page"),
-    )
+pub fn login(templates: Tera, open_registration: bool) -> Result<Html<String>, Errors> {
+    let mut ctx = Context::new();
+    ctx.insert("open_registration", &open_registration);
+
+    match templates.render("login.html", &ctx) {
+        Ok(html) => Ok(Html(html)),
+        Err(e) => Err(Errors::RenderingError("login".to_string(), e)),
+    }
+}
+
+/// `email` is who the invitation was sent to, or `None` if it can't be used
+pub fn register(
+    templates: Tera,
+    invite: String,
+    email: Option<String>,
+) -> Result<Html<String>, Errors> {
+    let mut ctx = Context::new();
+    ctx.insert("invite", &invite);
+    ctx.insert("email", &email);
+
+    match templates.render("register.html", &ctx) {
+        Ok(html) => Ok(Html(html)),
+        Err(e) => Err(Errors::RenderingError("register".to_string(), e)),
+    }
+}
+
+pub fn invitations(templates: Tera) -> Result<Html<String>, Errors> {
+    match templates.render("invitations.html", &Context::new()) {
+        Ok(html) => Ok(Html(html)),
+        Err(e) => Err(Errors::RenderingError("invitations".to_string(), e)),
+    }
 }
 
 pub fn login_success(templates: Tera) -> Result<Html<String>, Errors> {
     let html = match templates.render("login_success.html", &Context::new()) {
         Ok(html) => html,
         Err(e) => {
-            error!("error rendering login success result: {}", e);
+            error!("error rendering login success result: {:?}", e);
             return Err(Errors::RenderingError("login_sucess".to_string(), e));
         }
     };
     Ok(Html(html))
 }
+
+/// a full page explaining why a request was refused, for people who navigated to it
+pub fn error(templates: Tera, status: StatusCode, message: &str) -> Result<Html<String>, Errors> {
+    let mut ctx = Context::new();
+    ctx.insert("status", &status.as_u16());
+    ctx.insert("reason", &status.canonical_reason().unwrap_or_default());
+    ctx.insert("message", message);
+
+    match templates.render("error.html", &ctx) {
+        Ok(html) => Ok(Html(html)),
+        Err(e) => Err(Errors::RenderingError("error".to_string(), e)),
+    }
+}
+
+/// just the message, for htmx to swap into the page that made the request
+pub fn error_partial(templates: &Tera, error: &ErrorBody) -> Result<Html<String>, Errors> {
+    let mut ctx = Context::new();
+    ctx.insert("code", error.code);
+    ctx.insert("message", &error.message);
+
+    match templates.render("partials/error.html", &ctx) {
+        Ok(html) => Ok(Html(html)),
+        Err(e) => Err(Errors::RenderingError("error partial".to_string(), e)),
+    }
+}
+
+pub fn forgot_password(templates: Tera) -> Result<Html<String>, Errors> {
+    match templates.render("forgot_password.html", &Context::new()) {
+        Ok(html) => Ok(Html(html)),
+        Err(e) => Err(Errors::RenderingError("forgot_password".to_string(), e)),
+    }
+}
+
+pub fn reset_password(templates: Tera, token: String) -> Result<Html<String>, Errors> {
+    let mut ctx = Context::new();
+    ctx.insert("token", &token);
+
+    match templates.render("reset_password.html", &ctx) {
+        Ok(html) => Ok(Html(html)),
+        Err(e) => Err(Errors::RenderingError("reset_password".to_string(), e)),
+    }
+}
+
+pub fn proposals(
+    templates: Tera,
+    name: String,
+    proposals: Vec<Proposal>,
+) -> Result<Html<String>, Errors> {
+    let mut ctx = Context::new();
+    ctx.insert("name", &name);
+    ctx.insert("proposals", &proposals);
+
+    match templates.render("proposals.html", &ctx) {
+        Ok(html) => Ok(Html(html)),
+        Err(e) => Err(Errors::RenderingError("proposals".to_string(), e)),
+    }
+}
+
+pub fn proposal(
+    templates: Tera,
+    proposal: Proposal,
+    transitions: Vec<Transition>,
+    tally: Tally,
+    my_vote: Option<VoteChoice>,
+    can_vote: bool,
+    is_author: bool,
+    can_close: bool,
+) -> Result<Html<String>, Errors> {
+    let mut ctx = Context::new();
+    ctx.insert("can_vote", &can_vote);
+    ctx.insert("proposal", &proposal);
+    ctx.insert("transitions", &transitions);
+    ctx.insert("tally", &tally);
+    ctx.insert("my_vote", &my_vote);
+    ctx.insert("is_author", &is_author);
+    ctx.insert("can_close", &can_close);
+
+    match templates.render("proposal.html", &ctx) {
+        Ok(html) => Ok(Html(html)),
+        Err(e) => Err(Errors::RenderingError("proposal".to_string(), e)),
+    }
+}
+
+#[cfg(feature = "passkey")]
+pub fn keys(templates: Tera, name: String, keys: Vec<Key>) -> Result<Html<String>, Errors> {
+    let mut ctx = Context::new();
+    ctx.insert("name", &name);
+    ctx.insert("keys", &keys);
+
+    match templates.render("keys.html", &ctx) {
+        Ok(html) => Ok(Html(html)),
+        Err(e) => Err(Errors::RenderingError("keys".to_string(), e)),
+    }
+}
+
+pub fn members(templates: Tera, name: String, members: Vec<User>) -> Result<Html<String>, Errors> {
+    let mut ctx = Context::new();
+    ctx.insert("name", &name);
+    ctx.insert("members", &members);
+    ctx.insert("roles", &Role::ALL);
+
+    match templates.render("members.html", &ctx) {
+        Ok(html) => Ok(Html(html)),
+        Err(e) => Err(Errors::RenderingError("members".to_string(), e)),
+    }
+}
+
+pub fn lockouts(templates: Tera, lockouts: Vec<Lockout>) -> Result<Html<String>, Errors> {
+    let mut ctx = Context::new();
+    ctx.insert("lockouts", &lockouts);
+
+    match templates.render("lockouts.html", &ctx) {
+        Ok(html) => Ok(Html(html)),
+        Err(e) => Err(Errors::RenderingError("lockouts".to_string(), e)),
+    }
+}